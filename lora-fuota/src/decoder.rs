@@ -0,0 +1,510 @@
+use heapless::Vec;
+
+/// Fragments of a session, the 14-bit fragment counter limits uncoded and coded ones together
+pub const MAX_FRAGMENTS: usize = 1 << 14;
+
+const WORDS: usize = MAX_FRAGMENTS / 32;
+
+/// Storage the data block is reassembled into, offsets are relative to the start of the block.
+/// Every byte is written at most once so erased flash needs no read-modify-write.
+pub trait FragmentStore {
+    type Error;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Eq, Debug)]
+pub enum DecodeError<E> {
+    Store(E),
+    Session,         // no session set up
+    Size,            // fragment size does not match the session
+    Index,           // fragment counter is 0 or beyond the fragments of the session
+    NotEnoughMemory, // more fragments lost than can be recovered
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Eq, Debug)]
+pub enum SetupError {
+    FragmentSize,
+    Fragments,
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Progress {
+    Pending,
+    Complete,
+}
+
+/// Coded fragment reduced to the lost fragments it still combines, one coefficient bit each
+struct Row<const F: usize> {
+    coeffs: u128,
+    data: [u8; F],
+}
+
+/// Reassembles a data block of `nb_frag` uncoded fragments of up to `F` bytes, recovering up to
+/// `L` lost ones (at most 128) from the coded fragments following them.
+///
+/// Uncoded fragments go straight to the store. The first coded fragment fixes the set of lost
+/// ones, each coded fragment after it is reduced by Gaussian elimination over GF(2) until there
+/// are as many independent rows as lost fragments.
+pub struct Decoder<const F: usize, const L: usize> {
+    nb_frag: u16,
+    frag_size: usize,
+    received: [u32; WORDS], // uncoded fragments written to the store
+    parity: [u32; WORDS],   // scratch for the parity matrix row of a coded fragment
+    fragments: u16,         // uncoded and coded fragments received
+    coded: bool,
+    overflow: bool,    // more fragments lost than `L`
+    lost: Vec<u16, L>, // ascending, fixed by the first coded fragment
+    rows: Vec<Row<F>, L>,
+    complete: bool,
+}
+
+impl<const F: usize, const L: usize> Default for Decoder<F, L> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const F: usize, const L: usize> Decoder<F, L> {
+    pub const fn new() -> Self {
+        assert!(L <= 128, "coefficients of a row are a u128");
+        Self {
+            nb_frag: 0,
+            frag_size: 0,
+            received: [0; WORDS],
+            parity: [0; WORDS],
+            fragments: 0,
+            coded: false,
+            overflow: false,
+            lost: Vec::new(),
+            rows: Vec::new(),
+            complete: false,
+        }
+    }
+
+    /// Starts over with a new session, the store is expected to be erased
+    pub fn setup(&mut self, nb_frag: u16, frag_size: u8) -> Result<(), SetupError> {
+        if frag_size == 0 || frag_size as usize > F {
+            return Err(SetupError::FragmentSize);
+        }
+        if nb_frag == 0 || nb_frag as usize >= MAX_FRAGMENTS {
+            return Err(SetupError::Fragments);
+        }
+
+        // reset in place, the decoder is too large to be rebuilt on the stack
+        self.nb_frag = nb_frag;
+        self.frag_size = frag_size as usize;
+        self.received.fill(0);
+        self.fragments = 0;
+        self.coded = false;
+        self.overflow = false;
+        self.lost.clear();
+        self.rows.clear();
+        self.complete = false;
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
+        self.nb_frag = 0;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.nb_frag != 0
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Uncoded and coded fragments received so far, for `FragSessionStatusAns`
+    pub fn fragments(&self) -> u16 {
+        self.fragments
+    }
+
+    /// Fragments still needed to complete the block
+    pub fn missing(&self) -> u16 {
+        if self.complete {
+            0
+        } else if self.coded && !self.overflow {
+            (self.lost.len() - self.rows.len()) as u16
+        } else {
+            self.nb_frag - self.received_count()
+        }
+    }
+
+    /// Whether the lost fragments exceeded what can be recovered
+    pub fn not_enough_memory(&self) -> bool {
+        self.overflow
+    }
+
+    /// Adds fragment `n`, counting from 1, uncoded ones come first
+    pub fn push<S: FragmentStore>(&mut self, store: &mut S, n: u16, data: &[u8]) -> Result<Progress, DecodeError<S::Error>> {
+        if !self.is_active() {
+            return Err(DecodeError::Session);
+        }
+        if self.complete {
+            return Ok(Progress::Complete);
+        }
+        if data.len() != self.frag_size {
+            return Err(DecodeError::Size);
+        }
+        if n == 0 || n as usize >= MAX_FRAGMENTS {
+            return Err(DecodeError::Index);
+        }
+        self.fragments = self.fragments.saturating_add(1);
+
+        if n <= self.nb_frag {
+            self.push_uncoded(store, n - 1, data)?;
+        } else {
+            self.push_coded(store, n - self.nb_frag, data)?;
+        }
+
+        if !self.complete && self.missing() == 0 {
+            self.complete(store)?;
+        }
+        Ok(if self.complete { Progress::Complete } else { Progress::Pending })
+    }
+
+    fn push_uncoded<S: FragmentStore>(&mut self, store: &mut S, index: u16, data: &[u8]) -> Result<(), DecodeError<S::Error>> {
+        if test_bit(&self.received, index as usize) {
+            return Ok(());
+        }
+        set_bit(&mut self.received, index as usize);
+
+        if !self.coded {
+            return store.write(self.offset(index), data).map_err(DecodeError::Store);
+        }
+
+        // lost fragment arriving late is a row of its own
+        match self.lost.binary_search(&index) {
+            Ok(i) => {
+                let mut row = Row {
+                    coeffs: 1 << i,
+                    data: [0; F],
+                };
+                row.data[..self.frag_size].copy_from_slice(data);
+                self.add_row(row);
+                Ok(())
+            }
+            Err(_) => store.write(self.offset(index), data).map_err(DecodeError::Store),
+        }
+    }
+
+    fn push_coded<S: FragmentStore>(&mut self, store: &mut S, n: u16, data: &[u8]) -> Result<(), DecodeError<S::Error>> {
+        if !self.coded {
+            self.coded = true;
+            for index in 0..self.nb_frag {
+                if !test_bit(&self.received, index as usize) && self.lost.push(index).is_err() {
+                    self.overflow = true;
+                    break;
+                }
+            }
+        }
+        if self.overflow {
+            return Err(DecodeError::NotEnoughMemory);
+        }
+
+        let mut row = Row { coeffs: 0, data: [0; F] };
+        row.data[..self.frag_size].copy_from_slice(data);
+        let mut known = [0u8; F];
+
+        parity_row(n, self.nb_frag, &mut self.parity);
+        for index in 0..self.nb_frag {
+            if !test_bit(&self.parity, index as usize) {
+                continue;
+            }
+            match self.lost.binary_search(&index) {
+                Ok(i) => row.coeffs |= 1 << i,
+                Err(_) => {
+                    let known = &mut known[..self.frag_size];
+                    store.read(self.offset(index), known).map_err(DecodeError::Store)?;
+                    xor(&mut row.data, known);
+                }
+            }
+        }
+
+        self.add_row(row);
+        Ok(())
+    }
+
+    // Eliminates the pivots of the rows so far, drops the row when it adds nothing new
+    fn add_row(&mut self, mut row: Row<F>) {
+        for other in self.rows.iter() {
+            if row.coeffs & (1 << other.coeffs.trailing_zeros()) != 0 {
+                row.coeffs ^= other.coeffs;
+                xor(&mut row.data, &other.data);
+            }
+        }
+        if row.coeffs != 0 {
+            let _ = self.rows.push(row);
+        }
+    }
+
+    // With as many rows as lost fragments every row added after another holds none of its
+    // pivot, so solving from the last row back leaves each with its pivot only
+    fn complete<S: FragmentStore>(&mut self, store: &mut S) -> Result<(), DecodeError<S::Error>> {
+        for i in (0..self.rows.len()).rev() {
+            let (solved, rest) = self.rows.split_at_mut(i + 1);
+            let row = &mut solved[i];
+            for other in rest.iter() {
+                if row.coeffs & other.coeffs != 0 {
+                    row.coeffs ^= other.coeffs;
+                    xor(&mut row.data, &other.data);
+                }
+            }
+
+            let index = self.lost[row.coeffs.trailing_zeros() as usize];
+            let offset = index as u32 * self.frag_size as u32;
+            store.write(offset, &row.data[..self.frag_size]).map_err(DecodeError::Store)?;
+        }
+
+        self.complete = true;
+        Ok(())
+    }
+
+    fn offset(&self, index: u16) -> u32 {
+        index as u32 * self.frag_size as u32
+    }
+
+    fn received_count(&self) -> u16 {
+        self.received.iter().map(|word| word.count_ones()).sum::<u32>() as u16
+    }
+}
+
+/// Uncoded fragments combined into coded fragment `n`, counting from 1, out of `m` uncoded ones.
+/// Row of the parity matrix of TS004 annex, as generated by the reference implementation.
+pub(crate) fn parity_row(n: u16, m: u16, row: &mut [u32; WORDS]) {
+    row.fill(0);
+    let m = m as u32;
+    // modulo one above a power of two, which would only ever pick the low bits of the prbs
+    let modulo = if m.is_power_of_two() { m + 1 } else { m };
+
+    let mut x = 1 + 1001 * n as u32;
+    for _ in 0..m / 2 {
+        let mut r = m;
+        while r >= m {
+            x = prbs23(x);
+            r = x % modulo;
+        }
+        set_bit(row, r as usize);
+    }
+}
+
+fn prbs23(x: u32) -> u32 {
+    let b0 = x & 1;
+    let b1 = (x & 0x20) >> 5;
+    (x >> 1) + ((b0 ^ b1) << 22)
+}
+
+fn xor(out: &mut [u8], data: &[u8]) {
+    out.iter_mut().zip(data).for_each(|(out, byte)| *out ^= byte);
+}
+
+fn test_bit(bits: &[u32; WORDS], index: usize) -> bool {
+    bits[index / 32] & (1 << (index % 32)) != 0
+}
+
+fn set_bit(bits: &mut [u32; WORDS], index: usize) {
+    bits[index / 32] |= 1 << (index % 32);
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    use super::*;
+
+    const FRAG_SIZE: u8 = 16;
+
+    struct Memory(Vec<u8>);
+
+    impl FragmentStore for Memory {
+        type Error = ();
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ()> {
+            let offset = offset as usize;
+            buf.copy_from_slice(self.0.get(offset..offset + buf.len()).ok_or(())?);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ()> {
+            let offset = offset as usize;
+            let target = self.0.get_mut(offset..offset + data.len()).ok_or(())?;
+            // flash only clears bits, a second write of the same area would corrupt it
+            assert!(target.iter().all(|byte| *byte == 0xff), "offset {} written twice", offset);
+            target.copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    // Deterministic stand-in for the network server and the fragments lost on air
+    struct Lcg(u32);
+
+    impl Lcg {
+        fn next(&mut self) -> u32 {
+            self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            self.0 >> 16
+        }
+    }
+
+    fn block(size: usize) -> Vec<u8> {
+        let mut lcg = Lcg(7);
+        (0..size).map(|_| lcg.next() as u8).collect()
+    }
+
+    fn fragment(block: &[u8], index: u16) -> [u8; FRAG_SIZE as usize] {
+        let mut fragment = [0u8; FRAG_SIZE as usize];
+        let start = index as usize * FRAG_SIZE as usize;
+        let end = block.len().min(start + FRAG_SIZE as usize);
+        fragment[..end - start].copy_from_slice(&block[start..end]);
+        fragment
+    }
+
+    // Coded fragment `n` as the network server computes it
+    fn coded(block: &[u8], nb_frag: u16, n: u16) -> [u8; FRAG_SIZE as usize] {
+        let mut parity = Box::new([0u32; WORDS]);
+        parity_row(n, nb_frag, &mut parity);
+
+        let mut fragment = [0u8; FRAG_SIZE as usize];
+        for index in (0..nb_frag).filter(|index| test_bit(&parity, *index as usize)) {
+            xor(&mut fragment, &self::fragment(block, index));
+        }
+        fragment
+    }
+
+    fn row(n: u16, m: u16) -> Vec<u16> {
+        let mut parity = Box::new([0u32; WORDS]);
+        parity_row(n, m, &mut parity);
+        (0..m).filter(|index| test_bit(&parity, *index as usize)).collect()
+    }
+
+    #[test]
+    fn generates_reference_parity_rows() {
+        assert_eq!(row(1, 10), [2, 5]);
+        assert_eq!(row(2, 10), [0, 2, 4, 5, 9]);
+        assert_eq!(row(1, 16), [0, 1, 2, 4, 5, 10, 13, 15]);
+        assert_eq!(row(3, 16), [0, 1, 2, 8, 10, 12, 13]);
+        assert_eq!(
+            row(5, 100),
+            [
+                3, 12, 14, 16, 21, 24, 25, 29, 34, 35, 37, 40, 41, 43, 45, 48, 55, 57, 58, 60, 62, 64, 68, 72, 74, 77, 80, 82, 84, 86, 87,
+                88, 92, 96, 97
+            ]
+        );
+    }
+
+    // Sends all uncoded fragments, dropping one in `loss` unless 0, followed by `coded_fragments` coded ones
+    fn transfer<const L: usize>(size: usize, loss: u32, coded_fragments: u16) -> (Box<Decoder<16, L>>, Memory, Vec<u8>) {
+        let nb_frag = size.div_ceil(FRAG_SIZE as usize) as u16;
+        let block = block(size);
+        let mut store = Memory(std::vec![0xff; nb_frag as usize * FRAG_SIZE as usize]);
+        let mut decoder = Box::new(Decoder::<16, L>::new());
+        decoder.setup(nb_frag, FRAG_SIZE).unwrap();
+
+        let mut lcg = Lcg(42);
+        for index in 0..nb_frag {
+            if loss == 0 || !lcg.next().is_multiple_of(loss) {
+                decoder.push(&mut store, index + 1, &fragment(&block, index)).unwrap();
+            }
+        }
+        for n in 1..=coded_fragments {
+            if decoder.is_complete() {
+                break;
+            }
+            let _ = decoder.push(&mut store, nb_frag + n, &coded(&block, nb_frag, n));
+        }
+        (decoder, store, block)
+    }
+
+    #[test]
+    fn reassembles_without_loss() {
+        let (decoder, store, block) = transfer::<8>(1000, 0, 0);
+
+        assert!(decoder.is_complete());
+        assert_eq!(decoder.fragments(), 63);
+        assert_eq!(&store.0[..block.len()], &block[..]);
+    }
+
+    #[test]
+    fn recovers_lost_fragments() {
+        // about every fifth fragment lost
+        let (decoder, store, block) = transfer::<64>(2000, 5, 80);
+
+        assert!(decoder.is_complete());
+        assert_eq!(decoder.missing(), 0);
+        assert_eq!(&store.0[..block.len()], &block[..]);
+    }
+
+    #[test]
+    fn recovers_late_uncoded_fragment() {
+        let size = 10 * FRAG_SIZE as usize;
+        let block = block(size);
+        let mut store = Memory(std::vec![0xff; size]);
+        let mut decoder = Decoder::<16, 4>::new();
+        decoder.setup(10, FRAG_SIZE).unwrap();
+
+        for index in [0, 2, 3, 4, 5, 6, 7, 8, 9] {
+            decoder.push(&mut store, index + 1, &fragment(&block, index)).unwrap();
+        }
+        // first coded fragment does not cover fragment 1, it arrives as a retransmission
+        assert_eq!(decoder.push(&mut store, 11, &coded(&block, 10, 1)).unwrap(), Progress::Pending);
+        assert_eq!(decoder.missing(), 1);
+        assert_eq!(decoder.push(&mut store, 2, &fragment(&block, 1)).unwrap(), Progress::Complete);
+        assert_eq!(store.0, block);
+    }
+
+    #[test]
+    fn reports_too_many_lost() {
+        let (mut decoder, mut store, block) = transfer::<4>(1000, 3, 1);
+
+        assert!(!decoder.is_complete());
+        assert!(decoder.not_enough_memory());
+        assert_eq!(
+            decoder.push(&mut store, 63 + 2, &coded(&block, 63, 2)),
+            Err(DecodeError::NotEnoughMemory)
+        );
+    }
+
+    #[test]
+    fn ignores_redundant_fragments() {
+        let size = 16 * FRAG_SIZE as usize;
+        let block = block(size);
+        let mut store = Memory(std::vec![0xff; size]);
+        let mut decoder = Decoder::<16, 8>::new();
+        decoder.setup(16, FRAG_SIZE).unwrap();
+
+        for index in (0..16).filter(|index| ![0, 2, 13].contains(index)) {
+            decoder.push(&mut store, index + 1, &fragment(&block, index)).unwrap();
+        }
+        decoder.push(&mut store, 5, &fragment(&block, 4)).unwrap(); // duplicate
+        for n in 1..=20 {
+            if decoder.push(&mut store, 16 + n, &coded(&block, 16, n)).unwrap() == Progress::Complete {
+                break;
+            }
+            assert!(decoder.missing() > 0);
+        }
+
+        assert!(decoder.is_complete());
+        assert_eq!(store.0, block);
+    }
+
+    #[test]
+    fn rejects_invalid_fragments() {
+        let mut store = Memory(std::vec![0xff; 64]);
+        let mut decoder = Decoder::<16, 4>::new();
+        assert_eq!(decoder.push(&mut store, 1, &[0; 16]), Err(DecodeError::Session));
+
+        assert_eq!(decoder.setup(4, 17), Err(SetupError::FragmentSize));
+        assert_eq!(decoder.setup(0, 16), Err(SetupError::Fragments));
+        decoder.setup(4, 16).unwrap();
+        assert_eq!(decoder.push(&mut store, 1, &[0; 8]), Err(DecodeError::Size));
+        assert_eq!(decoder.push(&mut store, 0, &[0; 16]), Err(DecodeError::Index));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::CommandError;
+
+/// LoRaWAN Fragmented Data Block Transport (TS004 v1.0.0) package, network server splits
+/// a data block into fragments of equal size followed by redundant coded ones.
+pub const FPORT: u8 = 201;
+
+const PACKAGE_VERSION: u8 = 0x00;
+const FRAG_SESSION_STATUS: u8 = 0x01;
+const FRAG_SESSION_SETUP: u8 = 0x02;
+const FRAG_SESSION_DELETE: u8 = 0x03;
+const DATA_FRAGMENT: u8 = 0x08;
+
+const PACKAGE_ID: u8 = 3;
+const PACKAGE_VERSION_NUMBER: u8 = 1;
+
+// Status bits of `FragSessionSetupAns`
+pub const SETUP_ENCODING_UNSUPPORTED: u8 = 0x01;
+pub const SETUP_NOT_ENOUGH_MEMORY: u8 = 0x02;
+pub const SETUP_INDEX_UNSUPPORTED: u8 = 0x04;
+pub const SETUP_WRONG_DESCRIPTOR: u8 = 0x08;
+
+/// Fragmentation matrix of TS004 annex, the only one defined
+pub const MATRIX_PARITY: u8 = 0;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SessionSetup {
+    pub index: u8,           // fragmentation session, 0 to 3
+    pub mc_group_mask: u8,   // multicast groups the session is sent to, 0 for unicast
+    pub nb_frag: u16,        // uncoded fragments of the data block
+    pub frag_size: u8,       // bytes per fragment
+    pub matrix: u8,          // fragmentation matrix of the coded fragments
+    pub block_ack_delay: u8, // status answers to multicast requests are spread over 2^(delay + 4) seconds
+    pub padding: u8,         // bytes appended to the data block to fill the last fragment
+    pub descriptor: u32,     // free for the application, e.g. firmware version
+}
+
+impl SessionSetup {
+    /// Size of the data block without padding
+    pub fn block_size(&self) -> usize {
+        (self.nb_frag as usize * self.frag_size as usize).saturating_sub(self.padding as usize)
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Eq, Debug)]
+pub enum Command<'a> {
+    PackageVersionReq,
+    /// `participants` asks every device to answer, otherwise only those still missing fragments
+    FragSessionStatusReq {
+        index: u8,
+        participants: bool,
+    },
+    FragSessionSetupReq(SessionSetup),
+    FragSessionDeleteReq {
+        index: u8,
+    },
+    /// Fragment `n` counts from 1, uncoded fragments come first
+    DataFragment {
+        index: u8,
+        n: u16,
+        data: &'a [u8],
+    },
+}
+
+/// Iterates the commands of a downlink received on `FPORT`, stops after the first error
+pub fn parse(payload: &[u8]) -> Commands<'_> {
+    Commands { payload }
+}
+
+pub struct Commands<'a> {
+    payload: &'a [u8],
+}
+
+impl<'a> Iterator for Commands<'a> {
+    type Item = Result<Command<'a>, CommandError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (command, rest) = match self.payload {
+            [] => return None,
+            [PACKAGE_VERSION, rest @ ..] => (Command::PackageVersionReq, rest),
+            [FRAG_SESSION_STATUS, param, rest @ ..] => (
+                Command::FragSessionStatusReq {
+                    index: (param >> 1) & 0x03,
+                    participants: param & 0x01 != 0,
+                },
+                rest,
+            ),
+            [FRAG_SESSION_SETUP, session, n0, n1, size, control, padding, d0, d1, d2, d3, rest @ ..] => (
+                Command::FragSessionSetupReq(SessionSetup {
+                    index: (session >> 4) & 0x03,
+                    mc_group_mask: session & 0x0f,
+                    nb_frag: u16::from_le_bytes([*n0, *n1]),
+                    frag_size: *size,
+                    matrix: (control >> 3) & 0x07,
+                    block_ack_delay: control & 0x07,
+                    padding: *padding,
+                    descriptor: u32::from_le_bytes([*d0, *d1, *d2, *d3]),
+                }),
+                rest,
+            ),
+            [FRAG_SESSION_DELETE, param, rest @ ..] => (Command::FragSessionDeleteReq { index: param & 0x03 }, rest),
+            // fragment takes the rest of the downlink
+            [DATA_FRAGMENT, i0, i1, data @ ..] => {
+                let index_and_n = u16::from_le_bytes([*i0, *i1]);
+                (
+                    Command::DataFragment {
+                        index: (index_and_n >> 14) as u8,
+                        n: index_and_n & 0x3fff,
+                        data,
+                    },
+                    &[][..],
+                )
+            }
+            [FRAG_SESSION_STATUS | FRAG_SESSION_SETUP | FRAG_SESSION_DELETE | DATA_FRAGMENT, ..] => {
+                self.payload = &[];
+                return Some(Err(CommandError::Truncated));
+            }
+            [cid, ..] => {
+                let cid = *cid;
+                self.payload = &[];
+                return Some(Err(CommandError::Unknown(cid)));
+            }
+        };
+
+        self.payload = rest;
+        Some(Ok(command))
+    }
+}
+
+/// Encodes `PackageVersionAns`
+pub fn package_version_ans() -> [u8; 3] {
+    [PACKAGE_VERSION, PACKAGE_ID, PACKAGE_VERSION_NUMBER]
+}
+
+/// Encodes `FragSessionSetupAns`, `status` is a combination of the `SETUP_*` bits
+pub fn session_setup_ans(index: u8, status: u8) -> [u8; 2] {
+    [FRAG_SESSION_SETUP, (index & 0x03) << 6 | (status & 0x0f)]
+}
+
+/// Encodes `FragSessionDeleteAns`
+pub fn session_delete_ans(index: u8, exists: bool) -> [u8; 2] {
+    let missing = if exists { 0x00 } else { 0x04 };
+    [FRAG_SESSION_DELETE, missing | (index & 0x03)]
+}
+
+/// Encodes `FragSessionStatusAns`, `missing` saturates at 255
+pub fn session_status_ans(index: u8, received: u16, missing: u16, not_enough_memory: bool) -> [u8; 5] {
+    let received_and_index = ((index as u16 & 0x03) << 14 | (received & 0x3fff)).to_le_bytes();
+    [
+        FRAG_SESSION_STATUS,
+        received_and_index[0],
+        received_and_index[1],
+        missing.min(255) as u8,
+        not_enough_memory as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_session_setup() {
+        // session 1 for multicast group 0, 300 fragments of 50 bytes, 7 bytes padding
+        let payload = [0x02, 0x11, 0x2c, 0x01, 0x32, 0x02, 0x07, 0x01, 0x02, 0x03, 0x04];
+        let mut commands = parse(&payload);
+
+        let setup = SessionSetup {
+            index: 1,
+            mc_group_mask: 0x01,
+            nb_frag: 300,
+            frag_size: 50,
+            matrix: MATRIX_PARITY,
+            block_ack_delay: 2,
+            padding: 7,
+            descriptor: 0x0403_0201,
+        };
+        assert_eq!(commands.next(), Some(Ok(Command::FragSessionSetupReq(setup))));
+        assert_eq!(commands.next(), None);
+        assert_eq!(setup.block_size(), 14_993);
+    }
+
+    #[test]
+    fn parses_data_fragment() {
+        let payload = [0x08, 0x05, 0x40, 0xaa, 0xbb, 0xcc];
+        assert_eq!(
+            parse(&payload).next(),
+            Some(Ok(Command::DataFragment {
+                index: 1,
+                n: 5,
+                data: &[0xaa, 0xbb, 0xcc],
+            }))
+        );
+    }
+
+    #[test]
+    fn parses_several_commands() {
+        let payload = [0x00, 0x01, 0x05, 0x03, 0x02];
+        let commands: heapless::Vec<_, 3> = parse(&payload).map(Result::unwrap).collect();
+
+        assert_eq!(
+            commands,
+            [
+                Command::PackageVersionReq,
+                Command::FragSessionStatusReq {
+                    index: 2,
+                    participants: true,
+                },
+                Command::FragSessionDeleteReq { index: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_on_invalid_command() {
+        let mut commands = parse(&[0x00, 0x02, 0x11, 0x2c]);
+        assert_eq!(commands.next(), Some(Ok(Command::PackageVersionReq)));
+        assert_eq!(commands.next(), Some(Err(CommandError::Truncated)));
+        assert_eq!(commands.next(), None);
+
+        let mut commands = parse(&[0x04, 0x00]);
+        assert_eq!(commands.next(), Some(Err(CommandError::Unknown(0x04))));
+        assert_eq!(commands.next(), None);
+    }
+
+    #[test]
+    fn encodes_answers() {
+        assert_eq!(package_version_ans(), [0x00, 0x03, 0x01]);
+        assert_eq!(session_setup_ans(1, SETUP_NOT_ENOUGH_MEMORY), [0x02, 0x42]);
+        assert_eq!(session_delete_ans(2, false), [0x03, 0x06]);
+        assert_eq!(session_status_ans(1, 298, 300, false), [0x01, 0x2a, 0x41, 0xff, 0x00]);
+    }
+}
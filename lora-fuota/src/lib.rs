@@ -0,0 +1,20 @@
+//! Firmware updates over LoRaWAN, the application layer packages for Remote Multicast Setup (TS005)
+//! and Fragmented Data Block Transport (TS004) with the forward error correction of the latter.
+//! Free of hardware dependencies so reassembly is tested on host:
+//!
+//! ```shell
+//! cargo test -p lora-fuota --target x86_64-unknown-linux-gnu
+//! ```
+#![no_std]
+
+pub mod decoder;
+pub mod frag;
+pub mod multicast;
+
+/// Package command which could not be decoded, commands following it in the same downlink are dropped
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Eq, Debug)]
+pub enum CommandError {
+    Unknown(u8),
+    Truncated,
+}
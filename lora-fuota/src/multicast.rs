@@ -0,0 +1,416 @@
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use cmac::{Cmac, Mac};
+use heapless::Vec;
+
+use crate::CommandError;
+
+/// LoRaWAN Remote Multicast Setup (TS005 v1.0.0) package, network server provisions up to four
+/// multicast groups and schedules Class C sessions on them.
+pub const FPORT: u8 = 200;
+pub const MAX_GROUPS: usize = 4;
+
+const PACKAGE_VERSION: u8 = 0x00;
+const MC_GROUP_STATUS: u8 = 0x01;
+const MC_GROUP_SETUP: u8 = 0x02;
+const MC_GROUP_DELETE: u8 = 0x03;
+const MC_CLASS_C_SESSION: u8 = 0x04;
+const MC_CLASS_B_SESSION: u8 = 0x05;
+
+const PACKAGE_ID: u8 = 2;
+const PACKAGE_VERSION_NUMBER: u8 = 1;
+
+// Status bits of `McClassCSessionAns`
+pub const SESSION_DR_ERROR: u8 = 0x04;
+pub const SESSION_FREQUENCY_ERROR: u8 = 0x08;
+pub const SESSION_GROUP_UNDEFINED: u8 = 0x10;
+
+const MHDR_UNCONFIRMED_DOWN: u8 = 0x60;
+const FHDR_SIZE: usize = 7;
+const MIC_SIZE: usize = 4;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct GroupSetup {
+    pub id: u8,
+    pub addr: u32,
+    pub key_enc: [u8; 16], // McKey encrypted with McKEKey
+    pub min_fcnt: u32,
+    pub max_fcnt: u32,
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ClassCSession {
+    pub id: u8,
+    pub session_time: u32, // start, GPS epoch seconds modulo 2^32
+    pub timeout: u8,       // session lasts 2^timeout seconds
+    pub frequency: u32,    // Hz
+    pub data_rate: u8,
+}
+
+impl ClassCSession {
+    pub fn duration_secs(&self) -> u32 {
+        1 << self.timeout
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Eq, Debug)]
+pub enum Command {
+    PackageVersionReq,
+    McGroupStatusReq {
+        mask: u8,
+    },
+    McGroupSetupReq(GroupSetup),
+    McGroupDeleteReq {
+        id: u8,
+    },
+    McClassCSessionReq(ClassCSession),
+    /// Class B is not supported, the request goes unanswered
+    McClassBSessionReq {
+        id: u8,
+    },
+}
+
+/// Iterates the commands of a downlink received on `FPORT`, stops after the first error
+pub fn parse(payload: &[u8]) -> Commands<'_> {
+    Commands { payload }
+}
+
+pub struct Commands<'a> {
+    payload: &'a [u8],
+}
+
+impl Iterator for Commands<'_> {
+    type Item = Result<Command, CommandError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (command, rest) = match self.payload {
+            [] => return None,
+            [PACKAGE_VERSION, rest @ ..] => (Command::PackageVersionReq, rest),
+            [MC_GROUP_STATUS, mask, rest @ ..] => (Command::McGroupStatusReq { mask: mask & 0x0f }, rest),
+            [MC_GROUP_SETUP, id, rest @ ..] if rest.len() >= 28 => {
+                let (fields, rest) = rest.split_at(28);
+                let mut key_enc = [0u8; 16];
+                key_enc.copy_from_slice(&fields[4..20]);
+                (
+                    Command::McGroupSetupReq(GroupSetup {
+                        id: id & 0x03,
+                        addr: le_u32(&fields[0..4]),
+                        key_enc,
+                        min_fcnt: le_u32(&fields[20..24]),
+                        max_fcnt: le_u32(&fields[24..28]),
+                    }),
+                    rest,
+                )
+            }
+            [MC_GROUP_DELETE, id, rest @ ..] => (Command::McGroupDeleteReq { id: id & 0x03 }, rest),
+            [MC_CLASS_C_SESSION, id, t0, t1, t2, t3, timeout, f0, f1, f2, dr, rest @ ..] => (
+                Command::McClassCSessionReq(ClassCSession {
+                    id: id & 0x03,
+                    session_time: u32::from_le_bytes([*t0, *t1, *t2, *t3]),
+                    timeout: timeout & 0x0f,
+                    frequency: u32::from_le_bytes([*f0, *f1, *f2, 0]) * 100,
+                    data_rate: *dr,
+                }),
+                rest,
+            ),
+            [MC_CLASS_B_SESSION, id, _, _, _, _, _, _, _, _, _, rest @ ..] => (Command::McClassBSessionReq { id: id & 0x03 }, rest),
+            [MC_GROUP_STATUS | MC_GROUP_SETUP | MC_GROUP_DELETE | MC_CLASS_C_SESSION | MC_CLASS_B_SESSION, ..] => {
+                self.payload = &[];
+                return Some(Err(CommandError::Truncated));
+            }
+            [cid, ..] => {
+                let cid = *cid;
+                self.payload = &[];
+                return Some(Err(CommandError::Unknown(cid)));
+            }
+        };
+
+        self.payload = rest;
+        Some(Ok(command))
+    }
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Encodes `PackageVersionAns`
+pub fn package_version_ans() -> [u8; 3] {
+    [PACKAGE_VERSION, PACKAGE_ID, PACKAGE_VERSION_NUMBER]
+}
+
+/// Encodes `McGroupStatusAns` listing the groups of `mask` out of the defined `groups`, given by id and address
+pub fn group_status_ans(mask: u8, groups: &[(u8, u32)]) -> Vec<u8, { 2 + 5 * MAX_GROUPS }> {
+    let mut ans = Vec::new();
+    let answered = groups.iter().filter(|(id, _)| mask & (1 << id) != 0);
+    let ans_mask = answered.clone().fold(0u8, |ans_mask, (id, _)| ans_mask | 1 << id);
+
+    let _ = ans.push(MC_GROUP_STATUS);
+    let _ = ans.push(((groups.len() as u8) << 4) | ans_mask);
+    for (id, addr) in answered {
+        let _ = ans.push(*id);
+        let _ = ans.extend_from_slice(&addr.to_le_bytes());
+    }
+    ans
+}
+
+/// Encodes `McGroupSetupAns`
+pub fn group_setup_ans(id: u8, id_error: bool) -> [u8; 2] {
+    [MC_GROUP_SETUP, (id & 0x03) | (id_error as u8) << 2]
+}
+
+/// Encodes `McGroupDeleteAns`
+pub fn group_delete_ans(id: u8, undefined: bool) -> [u8; 2] {
+    [MC_GROUP_DELETE, (id & 0x03) | (undefined as u8) << 2]
+}
+
+/// Encodes `McClassCSessionAns`, `status` is a combination of the `SESSION_*` bits,
+/// time to start is only sent for an accepted session
+pub fn class_c_session_ans(id: u8, status: u8, time_to_start: u32) -> Vec<u8, 5> {
+    let mut ans = Vec::new();
+    let _ = ans.push(MC_CLASS_C_SESSION);
+    let _ = ans.push((id & 0x03) | (status & 0x1c));
+    if status == 0 {
+        let _ = ans.extend_from_slice(&time_to_start.to_le_bytes()[..3]);
+    }
+    ans
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Eq, Debug)]
+pub enum FrameError {
+    TooShort,
+    MType,   // not an unconfirmed data downlink
+    Address, // addressed to another group
+    FOpts,   // multicast frames carry no MAC commands
+    Port,
+    Mic,
+    FCnt, // repeated or outside of the range of the group
+}
+
+/// Multicast group with its session keys derived from the root key of the device
+pub struct McGroup {
+    pub id: u8,
+    pub addr: u32,
+    app_s_key: [u8; 16],
+    nwk_s_key: [u8; 16],
+    next_fcnt: u32,
+    max_fcnt: u32,
+}
+
+impl McGroup {
+    /// Derives the group keys from the LoRaWAN 1.0 `GenAppKey` of the device
+    pub fn new(gen_app_key: &[u8; 16], setup: &GroupSetup) -> Self {
+        let mut block = [0u8; 16];
+        let root_key = encrypt(gen_app_key, block);
+        let ke_key = encrypt(&root_key, block);
+        let mc_key = encrypt(&ke_key, setup.key_enc);
+
+        block[1..5].copy_from_slice(&setup.addr.to_le_bytes());
+        block[0] = 0x01;
+        let app_s_key = encrypt(&mc_key, block);
+        block[0] = 0x02;
+        let nwk_s_key = encrypt(&mc_key, block);
+
+        Self {
+            id: setup.id,
+            addr: setup.addr,
+            app_s_key,
+            nwk_s_key,
+            next_fcnt: setup.min_fcnt,
+            max_fcnt: setup.max_fcnt,
+        }
+    }
+
+    /// Authenticates and decrypts a downlink of the group in place, returns port and payload
+    pub fn decrypt<'a>(&mut self, frame: &'a mut [u8]) -> Result<(u8, &'a [u8]), FrameError> {
+        if frame.len() < 1 + FHDR_SIZE + 1 + MIC_SIZE {
+            return Err(FrameError::TooShort);
+        }
+        if frame[0] != MHDR_UNCONFIRMED_DOWN {
+            return Err(FrameError::MType);
+        }
+        if le_u32(&frame[1..5]) != self.addr {
+            return Err(FrameError::Address);
+        }
+        if frame[5] & 0x0f != 0 {
+            return Err(FrameError::FOpts);
+        }
+        let port = frame[8];
+        if port == 0 {
+            return Err(FrameError::Port);
+        }
+
+        // 16 bits on air, the upper half follows from the last frame of the group
+        let fcnt16 = u16::from_le_bytes([frame[6], frame[7]]) as u32;
+        let mut fcnt = (self.next_fcnt & !0xffff) | fcnt16;
+        if fcnt < self.next_fcnt {
+            fcnt = fcnt.checked_add(0x1_0000).ok_or(FrameError::FCnt)?;
+        }
+        if fcnt > self.max_fcnt {
+            return Err(FrameError::FCnt);
+        }
+
+        let (msg, mic) = frame.split_at_mut(frame.len() - MIC_SIZE);
+        let mut b0 = self.block(0x49, fcnt);
+        b0[15] = msg.len() as u8;
+        let mut cmac = <Cmac<Aes128> as KeyInit>::new(GenericArray::from_slice(&self.nwk_s_key));
+        cmac.update(&b0);
+        cmac.update(msg);
+        if cmac.finalize().into_bytes()[..MIC_SIZE] != *mic {
+            return Err(FrameError::Mic);
+        }
+        self.next_fcnt = fcnt.saturating_add(1);
+
+        let payload = &mut msg[1 + FHDR_SIZE + 1..];
+        for (i, chunk) in payload.chunks_mut(16).enumerate() {
+            let mut a = self.block(0x01, fcnt);
+            a[15] = i as u8 + 1;
+            let s = encrypt(&self.app_s_key, a);
+            chunk.iter_mut().zip(s).for_each(|(byte, s)| *byte ^= s);
+        }
+
+        Ok((port, payload))
+    }
+
+    fn block(&self, first: u8, fcnt: u32) -> [u8; 16] {
+        let mut block = [0u8; 16];
+        block[0] = first;
+        block[5] = 0x01; // downlink
+        block[6..10].copy_from_slice(&self.addr.to_le_bytes());
+        block[10..14].copy_from_slice(&fcnt.to_le_bytes());
+        block
+    }
+}
+
+fn encrypt(key: &[u8; 16], block: [u8; 16]) -> [u8; 16] {
+    let mut block = GenericArray::from(block);
+    Aes128::new(GenericArray::from_slice(key)).encrypt_block(&mut block);
+    block.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GEN_APP_KEY: [u8; 16] = [
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c,
+    ];
+    const ADDR: u32 = 0x01ff_a0b1;
+
+    // McKey 0102..10 encrypted for the device by the network server
+    const SETUP: [u8; 30] = [
+        0x02, 0x01, 0xb1, 0xa0, 0xff, 0x01, 0xa5, 0x70, 0x81, 0x97, 0x63, 0x9e, 0x60, 0x0d, 0x94, 0xa8, 0xc3, 0x31, 0x30, 0x59, 0xae, 0xc2,
+        0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00,
+    ];
+    // Fragment 5 of session 0 sent to the group with frame counter 0x10005
+    const FRAME: [u8; 36] = [
+        0x60, 0xb1, 0xa0, 0xff, 0x01, 0x00, 0x05, 0x00, 0xc9, 0x7d, 0x1c, 0x89, 0x76, 0xee, 0x5e, 0xba, 0x33, 0x91, 0x47, 0x3a, 0x14, 0x23,
+        0xb6, 0xf7, 0x80, 0xc3, 0xfc, 0xc8, 0xa6, 0x74, 0x92, 0x69, 0x3c, 0xbd, 0xe7, 0x95,
+    ];
+    const PAYLOAD: [u8; 23] = [
+        0x08, 0x05, 0x00, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f, 0x30, 0x31, 0x32,
+        0x33,
+    ];
+
+    fn setup() -> GroupSetup {
+        match parse(&SETUP).next() {
+            Some(Ok(Command::McGroupSetupReq(setup))) => setup,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_group_setup() {
+        let setup = setup();
+        assert_eq!(setup.id, 1);
+        assert_eq!(setup.addr, ADDR);
+        assert_eq!(setup.min_fcnt, 0x1_0000);
+        assert_eq!(setup.max_fcnt, 0x2_0000);
+    }
+
+    #[test]
+    fn derives_session_keys() {
+        let group = McGroup::new(&GEN_APP_KEY, &setup());
+        assert_eq!(
+            group.app_s_key,
+            [0x1c, 0xe4, 0x57, 0xe2, 0xf9, 0x3c, 0x58, 0x58, 0xfb, 0xb8, 0x04, 0xb0, 0x21, 0x52, 0x74, 0x5f]
+        );
+        assert_eq!(
+            group.nwk_s_key,
+            [0x70, 0xfd, 0xe3, 0x84, 0x09, 0x5b, 0x75, 0x4f, 0xfc, 0xd7, 0x77, 0x3e, 0xa7, 0x94, 0xc7, 0xb2]
+        );
+    }
+
+    #[test]
+    fn decrypts_group_frame() {
+        let mut group = McGroup::new(&GEN_APP_KEY, &setup());
+        let mut frame = FRAME;
+        assert_eq!(group.decrypt(&mut frame), Ok((201, &PAYLOAD[..])));
+
+        // replayed, counter continues past the range of the group
+        assert_eq!(group.decrypt(&mut FRAME.clone()), Err(FrameError::FCnt));
+    }
+
+    #[test]
+    fn rejects_foreign_frames() {
+        let mut group = McGroup::new(&GEN_APP_KEY, &setup());
+
+        let mut frame = FRAME;
+        frame[20] ^= 0x01;
+        assert_eq!(group.decrypt(&mut frame), Err(FrameError::Mic));
+
+        let mut frame = FRAME;
+        frame[1] = 0xb2;
+        assert_eq!(group.decrypt(&mut frame), Err(FrameError::Address));
+
+        let mut frame = FRAME;
+        frame[0] = 0xa0;
+        assert_eq!(group.decrypt(&mut frame), Err(FrameError::MType));
+
+        let mut group = McGroup::new(
+            &GEN_APP_KEY,
+            &GroupSetup {
+                max_fcnt: 0x1_0004,
+                ..setup()
+            },
+        );
+        assert_eq!(group.decrypt(&mut FRAME.clone()), Err(FrameError::FCnt));
+    }
+
+    #[test]
+    fn parses_class_c_session() {
+        // 2^10 s on 869.525 MHz DR0
+        let payload = [0x04, 0x01, 0x00, 0x10, 0x20, 0x50, 0x0a, 0xd2, 0xad, 0x84, 0x00, 0x04, 0x01];
+        let mut commands = parse(&payload);
+
+        let session = ClassCSession {
+            id: 1,
+            session_time: 0x5020_1000,
+            timeout: 10,
+            frequency: 869_525_000,
+            data_rate: 0,
+        };
+        assert_eq!(commands.next(), Some(Ok(Command::McClassCSessionReq(session))));
+        assert_eq!(commands.next(), Some(Err(CommandError::Truncated)));
+        assert_eq!(session.duration_secs(), 1024);
+    }
+
+    #[test]
+    fn encodes_answers() {
+        // group 0 is defined but not asked for
+        assert_eq!(
+            group_status_ans(0x0e, &[(0, 0x0102_0304), (1, ADDR)]),
+            [0x01, 0x22, 0x01, 0xb1, 0xa0, 0xff, 0x01]
+        );
+        assert_eq!(group_setup_ans(3, true), [0x02, 0x07]);
+        assert_eq!(group_delete_ans(1, false), [0x03, 0x01]);
+        assert_eq!(class_c_session_ans(1, 0, 300), [0x04, 0x01, 0x2c, 0x01, 0x00]);
+        assert_eq!(class_c_session_ans(1, SESSION_FREQUENCY_ERROR, 300), [0x04, 0x09]);
+        assert_eq!(package_version_ans(), [0x00, 0x02, 0x01]);
+    }
+}
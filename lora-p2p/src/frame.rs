@@ -0,0 +1,120 @@
+use aes::Aes128;
+use ccm::aead::generic_array::GenericArray;
+use ccm::aead::{AeadInPlace, KeyInit};
+use ccm::consts::{U13, U8};
+use ccm::Ccm;
+
+/// Lightweight framing of the LoRa point-to-point mode, all fields little endian:
+///
+/// | version & flags | node id | sequence | port | encrypted payload | tag |
+/// |-----------------|---------|----------|------|-------------------|-----|
+/// | 1               | 4       | 4        | 1    | 0 - 237           | 8   |
+///
+/// Payload is encrypted and the whole frame authenticated with AES-CCM under a pre-shared key,
+/// nonce is built from node id, sequence and flags, hence acks never reuse the nonce of the acked frame.
+pub const HEADER_SIZE: usize = 10;
+pub const TAG_SIZE: usize = 8;
+pub const MAX_FRAME_SIZE: usize = 255;
+pub const MAX_PAYLOAD_SIZE: usize = MAX_FRAME_SIZE - HEADER_SIZE - TAG_SIZE;
+
+const VERSION: u8 = 0x10;
+const VERSION_MASK: u8 = 0xf0;
+
+pub const FLAG_ACK_REQUEST: u8 = 0x01; // sender waits for an ack
+pub const FLAG_ACK: u8 = 0x02; // frame acknowledges the frame with the same node id and sequence
+pub const FLAG_BOOT: u8 = 0x04; // first frame after reset, sequence still moves forward
+
+type Cipher = Ccm<Aes128, U8, U13>;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Header {
+    pub flags: u8,
+    pub node_id: u32,
+    pub seq: u32,
+    pub port: u8,
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Eq, Debug)]
+pub enum FrameError {
+    TooLarge,
+    TooShort,
+    Version,
+    Mic,
+}
+
+impl Header {
+    fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0u8; HEADER_SIZE];
+        buf[0] = VERSION | (self.flags & !VERSION_MASK);
+        buf[1..5].copy_from_slice(&self.node_id.to_le_bytes());
+        buf[5..9].copy_from_slice(&self.seq.to_le_bytes());
+        buf[9] = self.port;
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self, FrameError> {
+        if buf[0] & VERSION_MASK != VERSION {
+            return Err(FrameError::Version);
+        }
+
+        Ok(Self {
+            flags: buf[0] & !VERSION_MASK,
+            node_id: u32::from_le_bytes([buf[1], buf[2], buf[3], buf[4]]),
+            seq: u32::from_le_bytes([buf[5], buf[6], buf[7], buf[8]]),
+            port: buf[9],
+        })
+    }
+
+    fn nonce(&self) -> [u8; 13] {
+        let mut nonce = [0u8; 13];
+        nonce[..4].copy_from_slice(&self.node_id.to_le_bytes());
+        nonce[4..8].copy_from_slice(&self.seq.to_le_bytes());
+        nonce[8] = self.flags & !VERSION_MASK;
+        nonce
+    }
+}
+
+/// Encodes and encrypts a frame into `buf`, returns the frame size
+pub fn encode(key: &[u8; 16], header: &Header, payload: &[u8], buf: &mut [u8]) -> Result<usize, FrameError> {
+    let size = HEADER_SIZE + payload.len() + TAG_SIZE;
+    if payload.len() > MAX_PAYLOAD_SIZE || buf.len() < size {
+        return Err(FrameError::TooLarge);
+    }
+
+    let header_bytes = header.encode();
+    buf[..HEADER_SIZE].copy_from_slice(&header_bytes);
+    let (aad, rest) = buf.split_at_mut(HEADER_SIZE);
+    let (body, tag) = rest.split_at_mut(payload.len());
+    body.copy_from_slice(payload);
+
+    let cipher = Cipher::new(GenericArray::from_slice(key));
+    let mic = cipher
+        .encrypt_in_place_detached(GenericArray::from_slice(&header.nonce()), aad, body)
+        .map_err(|_| FrameError::TooLarge)?;
+    tag[..TAG_SIZE].copy_from_slice(&mic);
+
+    Ok(size)
+}
+
+/// Authenticates and decrypts a frame in place, returns its header and payload
+pub fn decode<'a>(key: &[u8; 16], frame: &'a mut [u8]) -> Result<(Header, &'a [u8]), FrameError> {
+    if frame.len() < HEADER_SIZE + TAG_SIZE {
+        return Err(FrameError::TooShort);
+    }
+    if frame.len() > MAX_FRAME_SIZE {
+        return Err(FrameError::TooLarge);
+    }
+
+    let header = Header::decode(frame)?;
+    let (aad, rest) = frame.split_at_mut(HEADER_SIZE);
+    let (body, tag) = rest.split_at_mut(rest.len() - TAG_SIZE);
+
+    let cipher = Cipher::new(GenericArray::from_slice(key));
+    cipher
+        .decrypt_in_place_detached(GenericArray::from_slice(&header.nonce()), aad, body, GenericArray::from_slice(tag))
+        .map_err(|_| FrameError::Mic)?;
+
+    Ok((header, body))
+}
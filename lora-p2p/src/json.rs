@@ -0,0 +1,170 @@
+use core::fmt::{self, Write};
+
+use crate::lpp::{self, LppError, Measurement};
+use crate::receiver::Received;
+
+/// Signal of a received frame as reported by the transceiver
+#[derive(Clone, Copy)]
+pub struct Signal {
+    pub rssi: i16, // dBm
+    pub snr: i16,  // dB
+}
+
+/// Writes a received frame as a single JSON line, `lpp` decodes the payload into measurements
+/// which ends with `lpp_error` should the payload not be valid Cayenne LPP. Raw payload
+/// is always included as hex.
+///
+/// ```json
+/// {"node":"1a2b3c4d","seq":4096,"port":1,"boot":true,"rssi":-87,"snr":6,"payload":"016700d9",
+///  "measurements":[{"channel":1,"type":"temperature","value":21.7}]}
+/// ```
+pub fn write_line<W: Write>(out: &mut W, received: &Received, signal: Signal, lpp: bool) -> fmt::Result {
+    let header = &received.header;
+    write!(
+        out,
+        "{{\"node\":\"{:08x}\",\"seq\":{},\"port\":{},\"boot\":{},\"rssi\":{},\"snr\":{},\"payload\":\"",
+        header.node_id,
+        header.seq,
+        header.port,
+        received.is_boot(),
+        signal.rssi,
+        signal.snr
+    )?;
+    for byte in received.payload {
+        write!(out, "{:02x}", byte)?;
+    }
+    out.write_char('"')?;
+
+    if lpp {
+        out.write_str(",\"measurements\":[")?;
+        let mut error = None;
+        for (i, measurement) in lpp::decode(received.payload).enumerate() {
+            match measurement {
+                Ok(measurement) => {
+                    if i > 0 {
+                        out.write_char(',')?;
+                    }
+                    write_measurement(out, &measurement)?;
+                }
+                Err(e) => error = Some(e),
+            }
+        }
+        out.write_char(']')?;
+
+        match error {
+            Some(LppError::UnknownType(data_type)) => write!(out, ",\"lpp_error\":\"unknown type {:#04x}\"", data_type)?,
+            Some(LppError::Truncated) => out.write_str(",\"lpp_error\":\"truncated\"")?,
+            None => {}
+        }
+    }
+
+    out.write_str("}\n")
+}
+
+fn write_measurement<W: Write>(out: &mut W, measurement: &Measurement) -> fmt::Result {
+    write!(
+        out,
+        "{{\"channel\":{},\"type\":\"{}\",\"value\":",
+        measurement.channel,
+        measurement.kind.name()
+    )?;
+
+    // fixed point is printed as is, floats would round readings like 21.7
+    let decimals = measurement.kind.decimals();
+    if decimals == 0 {
+        write!(out, "{}", measurement.value)?;
+    } else {
+        let scale = 10i64.pow(decimals);
+        let sign = if measurement.value < 0 { "-" } else { "" };
+        let abs = measurement.value.unsigned_abs();
+        write!(
+            out,
+            "{}{}.{:0width$}",
+            sign,
+            abs / scale as u64,
+            abs % scale as u64,
+            width = decimals as usize
+        )?;
+    }
+
+    out.write_char('}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{Header, FLAG_ACK_REQUEST, FLAG_BOOT};
+
+    const SIGNAL: Signal = Signal { rssi: -87, snr: 6 };
+
+    fn received(flags: u8, payload: &[u8]) -> Received<'_> {
+        Received {
+            header: Header {
+                flags,
+                node_id: 0x1a2b_3c4d,
+                seq: 4096,
+                port: 1,
+            },
+            payload,
+            duplicate: false,
+        }
+    }
+
+    fn line(received: &Received, lpp: bool) -> heapless::String<512> {
+        let mut out = heapless::String::new();
+        write_line(&mut out, received, SIGNAL, lpp).unwrap();
+        out
+    }
+
+    #[test]
+    fn writes_measurements() {
+        let payload = [0x01, 0x67, 0x00, 0xd9, 0x01, 0x68, 0x61, 0x01, 0x73, 0x27, 0x94];
+        assert_eq!(
+            line(&received(FLAG_ACK_REQUEST | FLAG_BOOT, &payload), true),
+            concat!(
+                r#"{"node":"1a2b3c4d","seq":4096,"port":1,"boot":true,"rssi":-87,"snr":6,"payload":"016700d90168610173279"#,
+                r#"4","measurements":[{"channel":1,"type":"temperature","value":21.7},"#,
+                r#"{"channel":1,"type":"humidity","value":48.5},{"channel":1,"type":"barometer","value":1013.2}]}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn writes_negative_fractions() {
+        let payload = [0x05, 0x67, 0xff, 0xfb, 0x06, 0x02, 0xff, 0xfe];
+        assert_eq!(
+            line(&received(0, &payload), true),
+            concat!(
+                r#"{"node":"1a2b3c4d","seq":4096,"port":1,"boot":false,"rssi":-87,"snr":6,"payload":"0567fffb0602fffe","#,
+                r#""measurements":[{"channel":5,"type":"temperature","value":-0.5},"#,
+                r#"{"channel":6,"type":"analog_input","value":-0.02}]}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn writes_lpp_error() {
+        let payload = [0x03, 0x01, 0x02];
+        assert_eq!(
+            line(&received(0, &payload), true),
+            concat!(
+                r#"{"node":"1a2b3c4d","seq":4096,"port":1,"boot":false,"rssi":-87,"snr":6,"payload":"030102","#,
+                r#""measurements":[],"lpp_error":"unknown type 0x01"}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn writes_raw_payload() {
+        assert_eq!(
+            line(&received(0, &[0x03, 0x01, 0x02]), false),
+            concat!(
+                r#"{"node":"1a2b3c4d","seq":4096,"port":1,"boot":false,"rssi":-87,"snr":6,"payload":"030102"}"#,
+                "\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,13 @@
+//! LoRa point-to-point link between sensor nodes and a single receiver, without LoRaWAN.
+//! Shared by the node radio and the gateway firmware, free of hardware dependencies
+//! so the receive path is tested on host:
+//!
+//! ```shell
+//! cargo test -p lora-p2p --target x86_64-unknown-linux-gnu
+//! ```
+#![no_std]
+
+pub mod frame;
+pub mod json;
+pub mod lpp;
+pub mod receiver;
@@ -0,0 +1,197 @@
+/// Cayenne LPP data types produced by the sensor nodes
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    DigitalInput, // 0x00, 1 byte
+    AnalogInput,  // 0x02, 2 bytes signed, 0.01
+    Illuminance,  // 0x65, 2 bytes unsigned, 1 lux
+    Temperature,  // 0x67, 2 bytes signed, 0.1 °C
+    Humidity,     // 0x68, 1 byte unsigned, 0.5 %
+    Barometer,    // 0x73, 2 bytes unsigned, 0.1 hPa
+    UnixTime,     // 0x85, 4 bytes unsigned, 1 s
+}
+
+impl Kind {
+    fn from_type(data_type: u8) -> Option<Self> {
+        match data_type {
+            0x00 => Some(Kind::DigitalInput),
+            0x02 => Some(Kind::AnalogInput),
+            0x65 => Some(Kind::Illuminance),
+            0x67 => Some(Kind::Temperature),
+            0x68 => Some(Kind::Humidity),
+            0x73 => Some(Kind::Barometer),
+            0x85 => Some(Kind::UnixTime),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Kind::DigitalInput | Kind::Humidity => 1,
+            Kind::AnalogInput | Kind::Illuminance | Kind::Temperature | Kind::Barometer => 2,
+            Kind::UnixTime => 4,
+        }
+    }
+
+    /// Decimal places of `Measurement::value`
+    pub fn decimals(&self) -> u32 {
+        match self {
+            Kind::DigitalInput | Kind::Illuminance | Kind::UnixTime => 0,
+            Kind::Temperature | Kind::Humidity | Kind::Barometer => 1,
+            Kind::AnalogInput => 2,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::DigitalInput => "digital_input",
+            Kind::AnalogInput => "analog_input",
+            Kind::Illuminance => "illuminance",
+            Kind::Temperature => "temperature",
+            Kind::Humidity => "humidity",
+            Kind::Barometer => "barometer",
+            Kind::UnixTime => "unix_time",
+        }
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Measurement {
+    pub channel: u8,
+    pub kind: Kind,
+    pub value: i64, // fixed point with `Kind::decimals` places, kept integral so nothing is lost in rounding
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Eq, Debug)]
+pub enum LppError {
+    UnknownType(u8),
+    Truncated,
+}
+
+/// Iterates the measurements of a Cayenne LPP payload, stops after the first error
+pub fn decode(payload: &[u8]) -> Decoder<'_> {
+    Decoder { payload }
+}
+
+pub struct Decoder<'a> {
+    payload: &'a [u8],
+}
+
+impl Iterator for Decoder<'_> {
+    type Item = Result<Measurement, LppError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let [channel, data_type, rest @ ..] = self.payload else {
+            if self.payload.is_empty() {
+                return None;
+            }
+            self.payload = &[];
+            return Some(Err(LppError::Truncated));
+        };
+
+        let Some(kind) = Kind::from_type(*data_type) else {
+            self.payload = &[];
+            return Some(Err(LppError::UnknownType(*data_type)));
+        };
+
+        if rest.len() < kind.size() {
+            self.payload = &[];
+            return Some(Err(LppError::Truncated));
+        }
+
+        let (data, rest) = rest.split_at(kind.size());
+        let value = match kind {
+            Kind::DigitalInput => data[0] as i64,
+            Kind::Humidity => data[0] as i64 * 5,
+            Kind::AnalogInput | Kind::Temperature => i16::from_be_bytes([data[0], data[1]]) as i64,
+            Kind::Illuminance | Kind::Barometer => u16::from_be_bytes([data[0], data[1]]) as i64,
+            Kind::UnixTime => u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as i64,
+        };
+        let channel = *channel;
+        self.payload = rest;
+
+        Some(Ok(Measurement { channel, kind, value }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_air_sensor_payload() {
+        // sht4x with bme280 pressure: 21.7 °C, 48.5 %, 1013.2 hPa
+        let payload = [0x01, 0x67, 0x00, 0xd9, 0x01, 0x68, 0x61, 0x01, 0x73, 0x27, 0x94];
+        let mut measurements = decode(&payload);
+
+        assert_eq!(
+            measurements.next(),
+            Some(Ok(Measurement {
+                channel: 1,
+                kind: Kind::Temperature,
+                value: 217
+            }))
+        );
+        assert_eq!(
+            measurements.next(),
+            Some(Ok(Measurement {
+                channel: 1,
+                kind: Kind::Humidity,
+                value: 485
+            }))
+        );
+        assert_eq!(
+            measurements.next(),
+            Some(Ok(Measurement {
+                channel: 1,
+                kind: Kind::Barometer,
+                value: 10132
+            }))
+        );
+        assert_eq!(measurements.next(), None);
+    }
+
+    #[test]
+    fn decodes_negative_values() {
+        let payload = [0x05, 0x67, 0xff, 0x9c, 0x06, 0x02, 0xfe, 0x0c];
+        let measurements: heapless::Vec<_, 2> = decode(&payload).map(Result::unwrap).collect();
+
+        assert_eq!(measurements[0].value, -100);
+        assert_eq!(measurements[1].value, -500);
+    }
+
+    #[test]
+    fn decodes_unix_time() {
+        let payload = [0x0b, 0x85, 0x67, 0x12, 0x34, 0x56];
+        assert_eq!(
+            decode(&payload).next(),
+            Some(Ok(Measurement {
+                channel: 11,
+                kind: Kind::UnixTime,
+                value: 0x6712_3456
+            }))
+        );
+    }
+
+    #[test]
+    fn stops_on_unknown_type() {
+        let payload = [0x01, 0x68, 0x50, 0x02, 0x88, 0x00, 0x03, 0x68, 0x50];
+        let mut measurements = decode(&payload);
+
+        assert!(matches!(measurements.next(), Some(Ok(_))));
+        assert_eq!(measurements.next(), Some(Err(LppError::UnknownType(0x88))));
+        assert_eq!(measurements.next(), None);
+    }
+
+    #[test]
+    fn stops_on_truncated_payload() {
+        let mut measurements = decode(&[0x01, 0x67, 0x00]);
+        assert_eq!(measurements.next(), Some(Err(LppError::Truncated)));
+        assert_eq!(measurements.next(), None);
+
+        assert_eq!(decode(&[0x01]).next(), Some(Err(LppError::Truncated)));
+        assert_eq!(decode(&[]).next(), None);
+    }
+}
@@ -0,0 +1,289 @@
+use heapless::Vec;
+
+use crate::frame::{self, FrameError, Header, FLAG_ACK, FLAG_ACK_REQUEST, FLAG_BOOT};
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Eq, Debug)]
+pub enum ReceiveError {
+    Frame(FrameError),
+    Ack,                  // ack sent by another receiver, nodes never hear each other
+    Replay { last: u32 }, // sequence not newer than the last one accepted from the node
+}
+
+impl From<FrameError> for ReceiveError {
+    fn from(e: FrameError) -> Self {
+        ReceiveError::Frame(e)
+    }
+}
+
+/// Frame of a sensor node which passed authentication and the replay check
+#[derive(PartialEq, Eq, Debug)]
+pub struct Received<'a> {
+    pub header: Header,
+    pub payload: &'a [u8],
+    pub duplicate: bool, // retransmission of the last accepted frame, only to be acked again
+}
+
+impl Received<'_> {
+    pub fn needs_ack(&self) -> bool {
+        self.header.flags & FLAG_ACK_REQUEST != 0
+    }
+
+    pub fn is_boot(&self) -> bool {
+        self.header.flags & FLAG_BOOT != 0
+    }
+}
+
+struct Node {
+    id: u32,
+    seq: u32,
+}
+
+/// Receiving end of the point-to-point link, remembers the last sequence of up to `N` nodes,
+/// the least recently heard node is forgotten first.
+///
+/// Nodes persist their sequence, it keeps moving forward across resets and frames marked with `FLAG_BOOT`
+/// pass the same replay check as any other.
+pub struct Receiver<const N: usize> {
+    key: [u8; 16],
+    nodes: Vec<Node, N>,
+}
+
+impl<const N: usize> Receiver<N> {
+    pub const fn new(key: [u8; 16]) -> Self {
+        Self { key, nodes: Vec::new() }
+    }
+
+    /// Authenticates and decrypts a frame in place, rejects replayed ones
+    pub fn receive<'a>(&mut self, frame: &'a mut [u8]) -> Result<Received<'a>, ReceiveError> {
+        let (header, payload) = frame::decode(&self.key, frame)?;
+        if header.flags & FLAG_ACK != 0 {
+            return Err(ReceiveError::Ack);
+        }
+
+        let mut duplicate = false;
+        if let Some(index) = self.nodes.iter().position(|node| node.id == header.node_id) {
+            let last = self.nodes[index].seq;
+            // sequences wrap around, newer ones are less than half the range ahead
+            let ahead = header.seq.wrapping_sub(last) as i32;
+            if ahead < 0 {
+                return Err(ReceiveError::Replay { last });
+            }
+            duplicate = ahead == 0;
+            self.nodes.remove(index);
+        } else if self.nodes.is_full() {
+            self.nodes.remove(0);
+        }
+
+        let _ = self.nodes.push(Node {
+            id: header.node_id,
+            seq: header.seq,
+        });
+
+        Ok(Received {
+            header,
+            payload,
+            duplicate,
+        })
+    }
+
+    /// Encodes the ack of `header` into `buf`, returns the frame size
+    pub fn ack(&self, header: &Header, buf: &mut [u8]) -> Result<usize, FrameError> {
+        let ack = Header {
+            flags: FLAG_ACK,
+            ..*header
+        };
+        frame::encode(&self.key, &ack, &[], buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::MAX_FRAME_SIZE;
+
+    const KEY: [u8; 16] = [
+        0x3c, 0x1f, 0x85, 0x6e, 0xa2, 0x47, 0x90, 0x0b, 0xd4, 0x5e, 0x13, 0xc8, 0x71, 0x2a, 0xf6, 0x09,
+    ];
+    const NODE_ID: u32 = 0x1a2b_3c4d;
+
+    // Frames recorded from node 0x1a2b3c4d, first one after reset carries the air sensor readings
+    const BOOT: [u8; 29] = [
+        0x15, 0x4d, 0x3c, 0x2b, 0x1a, 0x00, 0x10, 0x00, 0x00, 0x01, 0x29, 0x67, 0x15, 0xf3, 0x51, 0x79, 0x7d, 0xda, 0x6b, 0x4b, 0x82, 0x0f,
+        0xef, 0xd8, 0x94, 0xb8, 0x8a, 0xa1, 0x74,
+    ];
+    const NEXT: [u8; 22] = [
+        0x11, 0x4d, 0x3c, 0x2b, 0x1a, 0x01, 0x10, 0x00, 0x00, 0x01, 0x8d, 0x92, 0x8d, 0xd7, 0x69, 0xf5, 0x9a, 0x35, 0x78, 0xfb, 0x78, 0xef,
+    ];
+    // Same node, encrypted under another key
+    const FOREIGN: [u8; 29] = [
+        0x11, 0x4d, 0x3c, 0x2b, 0x1a, 0x03, 0x10, 0x00, 0x00, 0x01, 0xdc, 0x4d, 0xbf, 0xd5, 0x2c, 0x93, 0x6a, 0x97, 0x8d, 0x49, 0xe7, 0xbd,
+        0x66, 0x0f, 0xf9, 0x3c, 0xa8, 0x86, 0x95,
+    ];
+    // Receiver ack of `BOOT`
+    const BOOT_ACK: [u8; 18] = [
+        0x12, 0x4d, 0x3c, 0x2b, 0x1a, 0x00, 0x10, 0x00, 0x00, 0x01, 0x7d, 0xbf, 0x3f, 0x18, 0x67, 0xd8, 0xa7, 0xe6,
+    ];
+
+    #[test]
+    fn decodes_recorded_frame() {
+        let mut receiver = Receiver::<4>::new(KEY);
+        let mut frame = BOOT;
+        let received = receiver.receive(&mut frame).unwrap();
+
+        assert_eq!(
+            received.header,
+            Header {
+                flags: FLAG_ACK_REQUEST | FLAG_BOOT,
+                node_id: NODE_ID,
+                seq: 0x1000,
+                port: 1,
+            }
+        );
+        assert_eq!(
+            received.payload,
+            &[0x01, 0x67, 0x00, 0xd9, 0x01, 0x68, 0x61, 0x01, 0x73, 0x27, 0x94]
+        );
+        assert!(received.needs_ack());
+        assert!(received.is_boot());
+        assert!(!received.duplicate);
+    }
+
+    #[test]
+    fn rejects_foreign_key() {
+        let mut receiver = Receiver::<4>::new(KEY);
+        let mut frame = FOREIGN;
+        assert_eq!(receiver.receive(&mut frame), Err(ReceiveError::Frame(FrameError::Mic)));
+    }
+
+    #[test]
+    fn rejects_tampered_frame() {
+        let mut receiver = Receiver::<4>::new(KEY);
+        let mut frame = NEXT;
+        frame[9] = 0x02; // port is authenticated too
+        assert_eq!(receiver.receive(&mut frame), Err(ReceiveError::Frame(FrameError::Mic)));
+
+        let mut frame = NEXT;
+        frame[0] = 0x21;
+        assert_eq!(receiver.receive(&mut frame), Err(ReceiveError::Frame(FrameError::Version)));
+
+        let mut frame = NEXT;
+        assert_eq!(receiver.receive(&mut frame[..17]), Err(ReceiveError::Frame(FrameError::TooShort)));
+    }
+
+    #[test]
+    fn rejects_replay() {
+        let mut receiver = Receiver::<4>::new(KEY);
+        receiver.receive(&mut NEXT.clone()).unwrap();
+
+        // older sequence without the boot flag
+        let header = Header {
+            flags: FLAG_ACK_REQUEST,
+            node_id: NODE_ID,
+            seq: 0x0fff,
+            port: 1,
+        };
+        let mut frame = [0u8; MAX_FRAME_SIZE];
+        let size = frame::encode(&KEY, &header, &[], &mut frame).unwrap();
+        assert_eq!(receiver.receive(&mut frame[..size]), Err(ReceiveError::Replay { last: 0x1001 }));
+    }
+
+    #[test]
+    fn flags_retransmission_as_duplicate() {
+        let mut receiver = Receiver::<4>::new(KEY);
+        assert!(!receiver.receive(&mut NEXT.clone()).unwrap().duplicate);
+        assert!(receiver.receive(&mut NEXT.clone()).unwrap().duplicate);
+    }
+
+    #[test]
+    fn rejects_replayed_boot_frame() {
+        let mut receiver = Receiver::<4>::new(KEY);
+        receiver.receive(&mut BOOT.clone()).unwrap();
+        receiver.receive(&mut NEXT.clone()).unwrap();
+
+        assert_eq!(receiver.receive(&mut BOOT.clone()), Err(ReceiveError::Replay { last: 0x1001 }));
+        assert_eq!(receiver.receive(&mut NEXT.clone()).map(|received| received.duplicate), Ok(true));
+    }
+
+    #[test]
+    fn flags_boot_retransmission_as_duplicate() {
+        let mut receiver = Receiver::<4>::new(KEY);
+        assert!(!receiver.receive(&mut BOOT.clone()).unwrap().duplicate);
+        assert!(receiver.receive(&mut BOOT.clone()).unwrap().duplicate);
+    }
+
+    #[test]
+    fn accepts_boot_frame_ahead() {
+        let mut receiver = Receiver::<4>::new(KEY);
+        receiver.receive(&mut NEXT.clone()).unwrap();
+
+        // node was reset and continues from its persisted sequence
+        let header = Header {
+            flags: FLAG_ACK_REQUEST | FLAG_BOOT,
+            node_id: NODE_ID,
+            seq: 0x2000,
+            port: 1,
+        };
+        let mut frame = [0u8; MAX_FRAME_SIZE];
+        let size = frame::encode(&KEY, &header, &[], &mut frame).unwrap();
+        assert!(receiver.receive(&mut frame[..size]).unwrap().is_boot());
+    }
+
+    #[test]
+    fn accepts_wrapped_sequence() {
+        let mut receiver = Receiver::<4>::new(KEY);
+        let mut frame = [0u8; MAX_FRAME_SIZE];
+
+        for seq in [u32::MAX - 1, u32::MAX, 0, 1] {
+            let header = Header {
+                flags: 0,
+                node_id: NODE_ID,
+                seq,
+                port: 1,
+            };
+            let size = frame::encode(&KEY, &header, &[], &mut frame).unwrap();
+            assert!(receiver.receive(&mut frame[..size]).is_ok(), "seq {:#x}", seq);
+        }
+    }
+
+    #[test]
+    fn forgets_least_recent_node() {
+        let mut receiver = Receiver::<2>::new(KEY);
+        let mut frame = [0u8; MAX_FRAME_SIZE];
+        let mut send = |receiver: &mut Receiver<2>, node_id: u32, seq: u32| {
+            let header = Header {
+                flags: 0,
+                node_id,
+                seq,
+                port: 1,
+            };
+            let size = frame::encode(&KEY, &header, &[], &mut frame).unwrap();
+            receiver.receive(&mut frame[..size]).map(|_| ())
+        };
+
+        send(&mut receiver, 1, 10).unwrap();
+        send(&mut receiver, 2, 10).unwrap();
+        send(&mut receiver, 1, 11).unwrap();
+        send(&mut receiver, 3, 10).unwrap(); // node 2 is dropped
+
+        assert_eq!(send(&mut receiver, 1, 5), Err(ReceiveError::Replay { last: 11 }));
+        assert!(send(&mut receiver, 2, 5).is_ok());
+    }
+
+    #[test]
+    fn ignores_acks() {
+        let mut receiver = Receiver::<4>::new(KEY);
+        assert_eq!(receiver.receive(&mut BOOT_ACK.clone()), Err(ReceiveError::Ack));
+    }
+
+    #[test]
+    fn encodes_recorded_ack() {
+        let mut receiver = Receiver::<4>::new(KEY);
+        let mut frame = BOOT;
+        let header = receiver.receive(&mut frame).unwrap().header;
+
+        let mut ack = [0u8; MAX_FRAME_SIZE];
+        let size = receiver.ack(&header, &mut ack).unwrap();
+        assert_eq!(&ack[..size], &BOOT_ACK);
+    }
+}
@@ -1,8 +1,17 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 1024K - LENGTH(BOOT2)
-    CONFIG : ORIGIN = ORIGIN(FLASH) + LENGTH(FLASH), LENGTH = 256K
+    /* bootloader takes 0x10000100 to 0x10006000, see bootloader/memory.x */
+    BOOTLOADER_STATE : ORIGIN = 0x10006000, LENGTH = 4K
+    FLASH : ORIGIN = 0x10007000, LENGTH = 764K
+    CONFIG : ORIGIN = 0x10100000, LENGTH = 256K
+    DFU : ORIGIN = ORIGIN(CONFIG) + LENGTH(CONFIG), LENGTH = LENGTH(FLASH) + 4K
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOT2);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOT2);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOT2);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOT2);
+
 __config_start = ORIGIN(CONFIG) - ORIGIN(BOOT2);
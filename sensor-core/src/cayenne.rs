@@ -0,0 +1,83 @@
+/// Value size of a Cayenne LPP data type, `None` for types the firmware does not know
+pub fn value_size(data_type: u8) -> Option<usize> {
+    match data_type {
+        0x00 => Some(1), // digital input
+        0x01 => Some(1), // digital output
+        0x02 => Some(2), // analog input
+        0x03 => Some(2), // analog output
+        0x65 => Some(2), // illuminance
+        0x66 => Some(1), // presence
+        0x67 => Some(2), // temperature
+        0x68 => Some(1), // humidity
+        0x71 => Some(6), // accelerometer
+        0x73 => Some(2), // barometer
+        0x85 => Some(4), // unix time
+        0x86 => Some(6), // gyrometer
+        0x88 => Some(9), // gps location
+        _ => None,
+    }
+}
+
+/// Length of the leading records of `payload` fitting into a frame of `max` bytes, records are kept whole
+/// unless the first one does not fit on its own or its type is unknown, then the payload is cut at `max`.
+/// `None` when not even a byte of a non-empty payload fits, the frame would make no progress.
+pub fn frame_len(payload: &[u8], max: usize) -> Option<usize> {
+    if max == 0 && !payload.is_empty() {
+        return None;
+    }
+
+    let mut len = 0;
+    while let Some(data_type) = payload.get(len + 1) {
+        let Some(record) = value_size(*data_type).map(|size| 2 + size) else {
+            break;
+        };
+        if len + record > max || len + record > payload.len() {
+            break;
+        }
+        len += record;
+    }
+
+    if len == 0 {
+        Some(payload.len().min(max))
+    } else {
+        Some(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // temperature, humidity and analog input records of channel 1
+    const PAYLOAD: [u8; 11] = [0x01, 0x67, 0x00, 0xe1, 0x01, 0x68, 0x60, 0x01, 0x02, 0x01, 0xf4];
+
+    #[test]
+    fn fits_whole_payload() {
+        assert_eq!(frame_len(&PAYLOAD, 11), Some(11));
+        assert_eq!(frame_len(&PAYLOAD, 242), Some(11));
+    }
+
+    #[test]
+    fn splits_at_record_boundary() {
+        assert_eq!(frame_len(&PAYLOAD, 10), Some(7));
+        assert_eq!(frame_len(&PAYLOAD[7..], 10), Some(4));
+    }
+
+    #[test]
+    fn cuts_unknown_or_oversized_records() {
+        assert_eq!(frame_len(&[0x01, 0xff, 0x00, 0x00, 0x00], 3), Some(3));
+        assert_eq!(frame_len(&PAYLOAD, 3), Some(3));
+    }
+
+    #[test]
+    fn nothing_fits_without_room() {
+        assert_eq!(frame_len(&PAYLOAD, 0), None);
+        assert_eq!(frame_len(&[0x01, 0xff, 0x00], 0), None);
+        assert_eq!(frame_len(&[], 0), Some(0));
+    }
+
+    #[test]
+    fn empty_payload_is_empty_frame() {
+        assert_eq!(frame_len(&[], 11), Some(0));
+    }
+}
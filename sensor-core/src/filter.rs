@@ -0,0 +1,90 @@
+/// Robust estimate of a set of adc samples
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Estimate {
+    /// Trimmed mean of samples which passed outlier rejection
+    pub value: u16,
+    /// Interquartile range of all samples, a quality indicator of the reading
+    pub spread: u16,
+    /// Amount of samples rejected as outliers
+    pub rejected: usize,
+}
+
+/// Rejects outliers outside of Tukey fences (1.5 IQR beyond quartiles)
+/// and averages the rest after trimming `trim_percent` samples from both ends,
+/// trimming 50% reduces the estimate to median.
+///
+/// Samples are sorted in place.
+pub fn estimate(samples: &mut [u16], trim_percent: usize) -> Option<Estimate> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    samples.sort_unstable();
+
+    let len = samples.len();
+    let q1 = samples[len / 4] as i32;
+    let q3 = samples[(len * 3) / 4] as i32;
+    let iqr = q3 - q1;
+    let low = q1 - (iqr * 3) / 2;
+    let high = q3 + (iqr * 3) / 2;
+
+    let start = samples.iter().position(|s| *s as i32 >= low).unwrap_or(0);
+    let end = samples.iter().rposition(|s| *s as i32 <= high).map_or(len, |i| i + 1);
+    let accepted = &samples[start..end];
+
+    let trim = ((accepted.len() * trim_percent.min(50)) / 100).min((accepted.len() - 1) / 2);
+    let trimmed = &accepted[trim..accepted.len() - trim];
+    let sum: u32 = trimmed.iter().map(|s| *s as u32).sum();
+
+    Some(Estimate {
+        value: ((sum + trimmed.len() as u32 / 2) / trimmed.len() as u32) as u16,
+        spread: iqr as u16,
+        rejected: len - accepted.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_outlier() {
+        let mut samples = [100, 102, 101, 99, 100, 4095, 98, 101];
+
+        let estimate = estimate(&mut samples, 0).unwrap();
+
+        assert_eq!(estimate.rejected, 1);
+        assert_eq!(estimate.value, 100);
+    }
+
+    #[test]
+    fn keeps_equal_samples() {
+        let mut samples = [512; 16];
+
+        let estimate = estimate(&mut samples, 10).unwrap();
+
+        assert_eq!(
+            estimate,
+            Estimate {
+                value: 512,
+                spread: 0,
+                rejected: 0
+            }
+        );
+    }
+
+    #[test]
+    fn trims_to_median() {
+        let mut odd = [7, 1, 3, 9, 5];
+        let mut even = [1, 2, 4, 100];
+
+        assert_eq!(estimate(&mut odd, 50).unwrap().value, 5);
+        assert_eq!(estimate(&mut even, 50).unwrap().value, 3);
+    }
+
+    #[test]
+    fn no_estimate_without_samples() {
+        assert_eq!(estimate(&mut [], 10), None);
+    }
+}
@@ -0,0 +1,13 @@
+//! Hardware independent part of the sensor drivers, bus protocols are generic over
+//! embedded-hal traits so they are tested on host against a mock bus, as are sample filtering
+//! and payload encoding:
+//!
+//! ```shell
+//! cargo test -p sensor-core --target x86_64-unknown-linux-gnu
+//! ```
+#![cfg_attr(not(test), no_std)]
+
+pub mod cayenne;
+pub mod filter;
+pub mod scd4x;
+pub mod sensirion;
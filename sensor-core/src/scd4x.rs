@@ -0,0 +1,332 @@
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::{Error as _, ErrorKind, I2c};
+
+use crate::sensirion;
+
+const SERIAL_NUMBER_COMMAND: u16 = 0x3682;
+const READ_MEASUREMENT_COMMAND: u16 = 0xec05;
+const MEASURE_SINGLE_SHOT_COMMAND: u16 = 0x219d;
+const POWER_DOWN: u16 = 0x36e0;
+const WAKE_UP: u16 = 0x36f6;
+const PERFORM_SELF_TEST: u16 = 0x3639;
+const PERFORM_FACTORY_RESET: u16 = 0x3632;
+const REINIT: u16 = 0x3646;
+
+/// Serial number reads after a wake up before the sensor is given up
+pub const WAKE_UP_ATTEMPTS: u8 = 3;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    I2c(ErrorKind),
+    SelfTest(u16),
+    Crc,
+}
+
+impl From<ErrorKind> for Error {
+    fn from(value: ErrorKind) -> Self {
+        Self::I2c(value)
+    }
+}
+
+/// Single shot measurement
+#[derive(Clone, Copy, Debug)]
+pub struct Measurement {
+    pub co2: u16,         // ppm
+    pub temperature: f32, // °C
+    pub humidity: f32,    // %
+}
+
+/// Sensirion SCD4x CO2 sensor, kept in power down mode between measurements
+pub struct Scd4x<I, D> {
+    bus: I,
+    delay: D,
+    address: u8,
+    powered: bool,
+}
+
+impl<I: I2c, D: DelayNs> Scd4x<I, D> {
+    /// Sensor is idle after power up
+    pub fn new(bus: I, delay: D, address: u8) -> Self {
+        Self {
+            bus,
+            delay,
+            address,
+            powered: true,
+        }
+    }
+
+    pub fn is_powered(&self) -> bool {
+        self.powered
+    }
+
+    async fn write(&mut self, command: u16) -> Result<(), ErrorKind> {
+        self.bus.write(self.address, &command.to_be_bytes()).await.map_err(|err| err.kind())
+    }
+
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<(), ErrorKind> {
+        self.bus.read(self.address, buffer).await.map_err(|err| err.kind())
+    }
+
+    /// Reads `N` words, every word is sent msb first and followed by a crc byte
+    async fn read_words<const N: usize>(&mut self) -> Result<[u16; N], Error> {
+        let mut buffer = [0u8; 9];
+        let buffer = &mut buffer[..N * 3];
+
+        self.read(buffer).await?;
+
+        sensirion::words(buffer).ok_or(Error::Crc)
+    }
+
+    pub async fn read_serial_number(&mut self) -> Result<u64, Error> {
+        self.write(SERIAL_NUMBER_COMMAND).await?;
+
+        // wait 1ms according to spec
+        self.delay.delay_ms(1).await;
+
+        let [word0, word1, word2] = self.read_words().await?;
+
+        Ok((u64::from(word0) << 32) | (u64::from(word1) << 16) | u64::from(word2))
+    }
+
+    /// Identifies the chip by reading its serial number, sensor is woken up first in case it was left powered down
+    pub async fn identify(bus: &mut I, delay: &mut D, address: u8) -> bool {
+        let mut buffer = [0u8; 9];
+
+        // sensor does not acknowledge wake up command, hence the error is expected and ignored
+        let _ = bus.write(address, &WAKE_UP.to_be_bytes()).await;
+
+        // wait 30 ms according to spec
+        delay.delay_ms(30).await;
+
+        if bus.write(address, &SERIAL_NUMBER_COMMAND.to_be_bytes()).await.is_err() {
+            return false;
+        }
+
+        // wait 1ms according to spec
+        delay.delay_ms(1).await;
+
+        match bus.read(address, &mut buffer).await {
+            Ok(()) => sensirion::words::<3>(&buffer).is_some(),
+            Err(_) => false,
+        }
+    }
+
+    /// Wakes the sensor up, serial number is read to verify it woke up as the spec recommends
+    pub async fn on(&mut self) -> Result<(), Error> {
+        if self.powered {
+            return Ok(());
+        }
+
+        let mut attempt = 1;
+        loop {
+            // sensor does not acknowledge wake up command, hence the error is expected and ignored
+            let _ = self.write(WAKE_UP).await;
+
+            // wait 30 ms according to spec
+            self.delay.delay_ms(30).await;
+
+            match self.read_serial_number().await {
+                Ok(_) => {
+                    self.powered = true;
+                    return Ok(());
+                }
+                Err(err) if attempt >= WAKE_UP_ATTEMPTS => return Err(err),
+                Err(_) => attempt += 1,
+            }
+        }
+    }
+
+    pub async fn off(&mut self) -> Result<(), Error> {
+        if !self.powered {
+            return Ok(());
+        }
+
+        self.write(POWER_DOWN).await?;
+        self.powered = false;
+
+        // wait 1 ms according to spec
+        self.delay.delay_ms(1).await;
+
+        Ok(())
+    }
+
+    /// Runs the on-chip self test, takes 10 seconds to complete
+    pub async fn perform_self_test(&mut self) -> Result<(), Error> {
+        self.write(PERFORM_SELF_TEST).await?;
+
+        // wait 10000ms according to spec
+        self.delay.delay_ms(10000).await;
+
+        let [status] = self.read_words().await?;
+
+        match status {
+            0 => Ok(()),
+            status => Err(Error::SelfTest(status)),
+        }
+    }
+
+    /// Resets all configuration settings stored in the EEPROM and erases the FRC and ASC algorithm history
+    pub async fn perform_factory_reset(&mut self) -> Result<(), Error> {
+        self.write(PERFORM_FACTORY_RESET).await?;
+
+        // wait 1200ms according to spec
+        self.delay.delay_ms(1200).await;
+
+        Ok(())
+    }
+
+    /// Reinitializes the sensor by reloading user settings from EEPROM
+    pub async fn reinit(&mut self) -> Result<(), Error> {
+        self.write(REINIT).await?;
+
+        // wait 30ms according to spec
+        self.delay.delay_ms(30).await;
+
+        Ok(())
+    }
+
+    /// Measures once, takes 5 seconds to complete
+    pub async fn measure(&mut self) -> Result<Measurement, Error> {
+        self.write(MEASURE_SINGLE_SHOT_COMMAND).await?;
+
+        // wait 5000ms according to spec
+        self.delay.delay_ms(5000).await;
+
+        self.write(READ_MEASUREMENT_COMMAND).await?;
+
+        // wait 1ms according to spec
+        self.delay.delay_ms(1).await;
+
+        let [co2, bytes_temp, bytes_hum] = self.read_words().await?;
+
+        Ok(Measurement {
+            co2,
+            temperature: bytes_temp as f32 * 175.0f32 / (u16::MAX as f32) - 45.0,
+            humidity: bytes_hum as f32 * 100.0 / (u16::MAX as f32),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embedded_hal::i2c::NoAcknowledgeSource;
+    use embedded_hal_mock::eh1::delay::NoopDelay;
+    use embedded_hal_mock::eh1::i2c::{Mock, Transaction};
+
+    use super::*;
+
+    const ADDRESS: u8 = 0x62;
+    const NACK: ErrorKind = ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address);
+
+    // serial number 0xf896_9f3b_073b with the crc of every word
+    const SERIAL_NUMBER: [u8; 9] = [0xf8, 0x96, 0x31, 0x9f, 0x3b, 0x7a, 0x07, 0x3b, 0x00];
+
+    fn sensor(expectations: &[Transaction], powered: bool) -> Scd4x<Mock, NoopDelay> {
+        let mut sensor = Scd4x::new(Mock::new(expectations), NoopDelay::new(), ADDRESS);
+        sensor.powered = powered;
+        sensor
+    }
+
+    fn wake_up() -> Transaction {
+        Transaction::write(ADDRESS, WAKE_UP.to_be_bytes().to_vec()).with_error(NACK)
+    }
+
+    fn serial_number_command() -> Transaction {
+        Transaction::write(ADDRESS, SERIAL_NUMBER_COMMAND.to_be_bytes().to_vec())
+    }
+
+    #[test]
+    fn wakes_up_despite_nack() {
+        let expectations = [
+            wake_up(),
+            serial_number_command(),
+            Transaction::read(ADDRESS, SERIAL_NUMBER.to_vec()),
+        ];
+        let mut sensor = sensor(&expectations, false);
+
+        assert_eq!(block_on(sensor.on()), Ok(()));
+        assert!(sensor.is_powered());
+        sensor.bus.done();
+    }
+
+    #[test]
+    fn gives_up_after_wake_up_attempts() {
+        let expectations = [
+            wake_up(),
+            serial_number_command().with_error(NACK),
+            wake_up(),
+            serial_number_command().with_error(NACK),
+            wake_up(),
+            serial_number_command().with_error(NACK),
+        ];
+        let mut sensor = sensor(&expectations, false);
+
+        assert_eq!(block_on(sensor.on()), Err(Error::I2c(NACK)));
+        assert!(!sensor.is_powered());
+        sensor.bus.done();
+    }
+
+    #[test]
+    fn tracks_power_state() {
+        let expectations = [
+            Transaction::write(ADDRESS, POWER_DOWN.to_be_bytes().to_vec()),
+            wake_up(),
+            serial_number_command(),
+            Transaction::read(ADDRESS, SERIAL_NUMBER.to_vec()),
+        ];
+        let mut sensor = sensor(&expectations, true);
+
+        assert_eq!(block_on(sensor.off()), Ok(()));
+        assert!(!sensor.is_powered());
+        assert_eq!(block_on(sensor.on()), Ok(()));
+        assert!(sensor.is_powered());
+        sensor.bus.done();
+    }
+
+    #[test]
+    fn rejects_corrupted_serial_number() {
+        let mut corrupted = SERIAL_NUMBER;
+        corrupted[7] ^= 0x01;
+        let expectations = [serial_number_command(), Transaction::read(ADDRESS, corrupted.to_vec())];
+        let mut sensor = sensor(&expectations, true);
+
+        assert_eq!(block_on(sensor.read_serial_number()), Err(Error::Crc));
+        sensor.bus.done();
+    }
+
+    #[test]
+    fn rejects_corrupted_self_test_status() {
+        let expectations = [
+            Transaction::write(ADDRESS, PERFORM_SELF_TEST.to_be_bytes().to_vec()),
+            Transaction::read(ADDRESS, vec![0x00, 0x00, 0x80]),
+        ];
+        let mut sensor = sensor(&expectations, true);
+
+        assert_eq!(block_on(sensor.perform_self_test()), Err(Error::Crc));
+        sensor.bus.done();
+    }
+
+    #[test]
+    fn rejects_corrupted_measurement() {
+        let expectations = [
+            Transaction::write(ADDRESS, MEASURE_SINGLE_SHOT_COMMAND.to_be_bytes().to_vec()),
+            Transaction::write(ADDRESS, READ_MEASUREMENT_COMMAND.to_be_bytes().to_vec()),
+            Transaction::read(ADDRESS, vec![0x01, 0xf4, 0x33, 0x66, 0x67, 0xa2, 0x5e, 0xb9, 0x00]),
+        ];
+        let mut sensor = sensor(&expectations, true);
+
+        assert!(matches!(block_on(sensor.measure()), Err(Error::Crc)));
+        sensor.bus.done();
+    }
+
+    #[test]
+    fn off_while_off_is_noop() {
+        let mut sensor = sensor(&[], false);
+
+        assert_eq!(block_on(sensor.off()), Ok(()));
+        assert!(!sensor.is_powered());
+        sensor.bus.done();
+    }
+}
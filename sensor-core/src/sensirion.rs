@@ -0,0 +1,48 @@
+/// Sensirion CRC-8, polynomial 0x31 with initial value 0xff, guards every word sent by SCD4x and SHT4x
+pub fn crc(data: &[u8]) -> u8 {
+    let mut crc = 0xffu8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x31 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Splits a response into its msb first words, `None` when the crc following any of them does not match
+pub fn words<const N: usize>(buffer: &[u8]) -> Option<[u16; N]> {
+    if buffer.len() < N * 3 {
+        return None;
+    }
+
+    let mut words = [0u16; N];
+    for (word, chunk) in words.iter_mut().zip(buffer.chunks_exact(3)) {
+        if crc(&chunk[0..2]) != chunk[2] {
+            return None;
+        }
+        *word = u16::from_be_bytes([chunk[0], chunk[1]]);
+    }
+    Some(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_datasheet_example() {
+        assert_eq!(crc(&[0xbe, 0xef]), 0x92);
+    }
+
+    #[test]
+    fn splits_words() {
+        assert_eq!(words::<2>(&[0xbe, 0xef, 0x92, 0x00, 0x00, 0x81]), Some([0xbeef, 0x0000]));
+    }
+
+    #[test]
+    fn rejects_corrupted_word() {
+        assert_eq!(words::<2>(&[0xbe, 0xef, 0x92, 0x00, 0x01, 0x81]), None);
+        assert_eq!(words::<2>(&[0xbe, 0xef, 0x92]), None);
+    }
+}
@@ -0,0 +1,276 @@
+//! Receiver of the point-to-point nodes for sites without a LoRaWAN gateway. Listens continuously,
+//! acknowledges frames which ask for it and forwards decoded measurements over USB CDC
+//! as JSON lines, one per frame, to the host it is plugged into.
+#![no_std]
+#![no_main]
+
+#[allow(dead_code)] // shared with the node firmware, which uses most of the entries
+#[path = "../../config/mod.rs"]
+mod config;
+#[path = "../../radio/sx1262.rs"]
+mod sx1262;
+
+use assign_resources::assign_resources;
+use embassy_executor::Spawner;
+use embassy_futures::join::join4;
+use embassy_rp::config::Config;
+use embassy_rp::peripherals::{self, USB};
+use embassy_rp::usb::{Driver, InterruptHandler};
+use embassy_rp::watchdog::Watchdog;
+use embassy_rp::{bind_interrupts, Peri};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Delay, Duration, Timer};
+use embassy_usb::class::cdc_acm::{self, CdcAcmClass};
+use embassy_usb::driver::EndpointError;
+use embassy_usb::Builder;
+use heapless::String;
+use lora_p2p::frame::{Header, MAX_FRAME_SIZE};
+use lora_p2p::json::{self, Signal};
+use lora_p2p::receiver::{ReceiveError, Receiver};
+use lora_phy::mod_params::{ModulationParams, PacketParams, RadioError};
+use lora_phy::{LoRa, RxMode};
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+use crate::config::P2pSyncWord;
+use crate::sx1262::Sx1262;
+
+bind_interrupts!(struct Irqs {
+    USBCTRL_IRQ => InterruptHandler<USB>;
+});
+
+assign_resources! {
+    radio: RadioRes {
+        busy: PIN_2,
+        cs: PIN_3,
+        clk: PIN_10,
+        mosi: PIN_11,
+        miso: PIN_12,
+        rst: PIN_15,
+        dio1: PIN_20,
+        dma_ch0: DMA_CH0,
+        dma_ch1: DMA_CH1,
+        spi1: SPI1,
+    },
+    usb: UsbRes {
+        usb: USB,
+    },
+    watchdog: WatchdogRes {
+        watchdog: WATCHDOG,
+    },
+}
+
+const PREAMBLE_LENGTH: u16 = 8;
+const USB_PACKET_SIZE: usize = 64;
+const LINE_SIZE: usize = 1024;
+const LINE_QUEUE: usize = 8; // lines buffered while the host is not reading, newer ones are dropped
+
+type Line = String<LINE_SIZE>;
+
+static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+static CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
+static CDC_STATE: StaticCell<cdc_acm::State> = StaticCell::new();
+static LINES: StaticCell<Channel<NoopRawMutex, Line, LINE_QUEUE>> = StaticCell::new();
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Config::default());
+    let r = split_resources! {p};
+
+    let driver = Driver::new(r.usb.usb, Irqs);
+    let mut usb_config = embassy_usb::Config::new(0xc0de, 0xcafe);
+    usb_config.manufacturer = Some("nanobreaker");
+    usb_config.product = Some("sx1262 p2p gateway");
+    usb_config.max_power = 100;
+    usb_config.max_packet_size_0 = 64;
+
+    let mut builder = Builder::new(
+        driver,
+        usb_config,
+        CONFIG_DESCRIPTOR.init([0; 256]),
+        BOS_DESCRIPTOR.init([0; 256]),
+        &mut [], // no msos descriptors
+        CONTROL_BUF.init([0; 64]),
+    );
+    let class = CdcAcmClass::new(&mut builder, CDC_STATE.init(cdc_acm::State::new()), USB_PACKET_SIZE as u16);
+    let mut usb = builder.build();
+    let lines = &*LINES.init(Channel::new());
+
+    let radio = async {
+        let public_network = matches!(config::Config::P2P_SYNC_WORD, P2pSyncWord::Public);
+        let lora = match sx1262::init(r.radio, public_network).await {
+            Ok(lora) => lora,
+            Err(e) => defmt::panic!("radio init failed, {:?}", e),
+        };
+        let mut gateway = match Gateway::try_new(lora) {
+            Ok(gateway) => gateway,
+            Err(e) => defmt::panic!("radio init failed, {:?}", e),
+        };
+        gateway.run(lines).await
+    };
+
+    let forward = async {
+        let (mut sender, _) = class.split();
+        loop {
+            sender.wait_connection().await;
+            defmt::info!("Host connected");
+            let _ = forward_lines(&mut sender, lines).await;
+            defmt::info!("Host disconnected");
+        }
+    };
+
+    // started by the bootloader, shared with the node firmware
+    let feed_watchdog = async {
+        let mut watchdog = Watchdog::new(r.watchdog.watchdog);
+        watchdog.pause_on_debug(true);
+        watchdog.start(Duration::from_millis(config::Config::WATCHDOG_TIMEOUT_MS));
+        loop {
+            watchdog.feed();
+            Timer::after_secs(config::Config::WATCHDOG_FEED_SECS).await;
+        }
+    };
+
+    join4(usb.run(), radio, forward, feed_watchdog).await;
+}
+
+async fn forward_lines(
+    sender: &mut cdc_acm::Sender<'static, Driver<'static, USB>>,
+    lines: &Channel<NoopRawMutex, Line, LINE_QUEUE>,
+) -> Result<(), EndpointError> {
+    loop {
+        let line = lines.receive().await;
+        for packet in line.as_bytes().chunks(USB_PACKET_SIZE) {
+            sender.write_packet(packet).await?;
+        }
+        // a full last packet leaves the transfer open until a short one follows
+        if line.len() % USB_PACKET_SIZE == 0 {
+            sender.write_packet(&[]).await?;
+        }
+    }
+}
+
+struct Gateway {
+    lora: LoRa<Sx1262, Delay>,
+    modulation: ModulationParams,
+    rx_params: PacketParams,
+    tx_params: PacketParams,
+    receiver: Receiver<{ config::Config::P2P_GATEWAY_NODES }>,
+}
+
+impl Gateway {
+    // Nodes transmit with normal and listen with inverted IQ, the gateway does the opposite
+    fn try_new(mut lora: LoRa<Sx1262, Delay>) -> Result<Self, RadioError> {
+        let modulation = lora.create_modulation_params(
+            config::Config::P2P_SPREADING_FACTOR,
+            config::Config::P2P_BANDWIDTH,
+            config::Config::P2P_CODING_RATE,
+            config::Config::P2P_FREQUENCY,
+        )?;
+        let rx_params = lora.create_rx_packet_params(PREAMBLE_LENGTH, false, MAX_FRAME_SIZE as u8, true, false, &modulation)?;
+        let tx_params = lora.create_tx_packet_params(PREAMBLE_LENGTH, false, true, true, &modulation)?;
+
+        Ok(Self {
+            lora,
+            modulation,
+            rx_params,
+            tx_params,
+            receiver: Receiver::new(config::Config::P2P_KEY),
+        })
+    }
+
+    async fn run(&mut self, lines: &Channel<NoopRawMutex, Line, LINE_QUEUE>) -> ! {
+        let mut listening = false;
+        let mut frame = [0u8; MAX_FRAME_SIZE];
+        let mut ack = [0u8; MAX_FRAME_SIZE];
+
+        loop {
+            if !listening {
+                if let Err(e) = self
+                    .lora
+                    .prepare_for_rx(RxMode::Continuous, &self.modulation, &self.rx_params)
+                    .await
+                {
+                    defmt::error!("Failed to start receiving, {:?}", e);
+                    Timer::after_secs(1).await;
+                    continue;
+                }
+                listening = true;
+            }
+
+            let (size, status) = match self.lora.rx(&self.rx_params, &mut frame).await {
+                Ok(received) => received,
+                Err(e) => {
+                    defmt::warn!("Receive failed, {:?}", e);
+                    listening = false;
+                    continue;
+                }
+            };
+
+            let received = match self.receiver.receive(&mut frame[..size as usize]) {
+                Ok(received) => received,
+                Err(ReceiveError::Ack) => continue,
+                Err(e) => {
+                    defmt::debug!("Dropping received frame, {:?}", e);
+                    continue;
+                }
+            };
+            let header = received.header;
+            defmt::info!(
+                "Frame {=u32} from node {=u32:#x} on port {=u8}, rssi {=i16} snr {=i16}",
+                header.seq,
+                header.node_id,
+                header.port,
+                status.rssi,
+                status.snr
+            );
+
+            if received.needs_ack() {
+                listening = false;
+                if let Err(e) = self.send_ack(&header, &mut ack).await {
+                    defmt::warn!("Failed to ack frame {=u32} of node {=u32:#x}, {:?}", header.seq, header.node_id, e);
+                }
+            }
+
+            if received.duplicate {
+                continue;
+            }
+
+            let mut line = Line::new();
+            let signal = Signal {
+                rssi: status.rssi,
+                snr: status.snr,
+            };
+            let lpp = header.port == config::Config::FPORT_TELEMETRY;
+            if json::write_line(&mut line, &received, signal, lpp).is_err() {
+                defmt::warn!("Frame {=u32} of node {=u32:#x} does not fit a line", header.seq, header.node_id);
+                continue;
+            }
+            if lines.try_send(line).is_err() {
+                defmt::warn!(
+                    "Host is not reading, dropping frame {=u32} of node {=u32:#x}",
+                    header.seq,
+                    header.node_id
+                );
+            }
+        }
+    }
+
+    async fn send_ack(&mut self, header: &Header, buf: &mut [u8]) -> Result<(), RadioError> {
+        let size = match self.receiver.ack(header, buf) {
+            Ok(size) => size,
+            Err(e) => defmt::panic!("ack does not fit a frame, {:?}", e),
+        };
+
+        self.lora
+            .prepare_for_tx(
+                &self.modulation,
+                &mut self.tx_params,
+                config::Config::P2P_TX_POWER as i32,
+                &buf[..size],
+            )
+            .await?;
+        self.lora.tx().await
+    }
+}
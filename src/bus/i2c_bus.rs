@@ -0,0 +1,178 @@
+use embassy_rp::gpio::{Flex, Pull};
+use embassy_rp::i2c::{self, Async};
+use embassy_rp::peripherals::{I2C0, PIN_16, PIN_17};
+use embassy_rp::Peri;
+use embassy_time::{with_timeout, Duration, Timer};
+use embedded_hal_async::i2c::{Error, ErrorKind, ErrorType, I2c, Operation};
+
+use crate::{config, I2cRes, Irqs};
+
+// half period of 100kHz clock used while recovering the bus
+const RECOVERY_HALF_PERIOD_US: u64 = 5;
+const RECOVERY_CLOCK_PULSES: u8 = 9;
+
+#[derive(defmt::Format)]
+pub enum I2cBusError {
+    I2c(i2c::Error),
+    Timeout,
+}
+
+impl Error for I2cBusError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::I2c(err) => err.kind(),
+            Self::Timeout => ErrorKind::Other,
+        }
+    }
+}
+
+/// I2C0 bus which keeps ownership of its pins, so it can be recovered
+/// from a slave holding SDA low (e.g. after a brownout) without a power cycle.
+pub struct I2cBus {
+    i2c0: Peri<'static, I2C0>,
+    sda: Peri<'static, PIN_16>,
+    scl: Peri<'static, PIN_17>,
+    bus: Option<i2c::I2c<'static, I2C0, Async>>,
+    errors: u8,
+}
+
+impl I2cBus {
+    pub fn new(r: I2cRes) -> Self {
+        let mut i2c_bus = Self {
+            i2c0: r.i2c0,
+            sda: r.sda,
+            scl: r.scl,
+            bus: None,
+            errors: 0,
+        };
+        i2c_bus.init();
+
+        i2c_bus
+    }
+
+    fn init(&mut self) {
+        let mut i2c_config = i2c::Config::default();
+        i2c_config.frequency = config::Config::I2C_FREQUENCY;
+
+        // safety: previous bus instance is dropped before the peripherals are reused
+        let (i2c0, scl, sda) = unsafe { (self.i2c0.clone_unchecked(), self.scl.clone_unchecked(), self.sda.clone_unchecked()) };
+
+        self.bus = Some(i2c::I2c::new_async(i2c0, scl, sda, Irqs, i2c_config));
+    }
+
+    /// Clocks SCL up to 9 times until the slave releases SDA, then issues a STOP condition and re-initializes the bus
+    pub async fn recover(&mut self) {
+        defmt::warn!("Recovering I2C bus");
+
+        self.bus = None;
+
+        {
+            // safety: bus instance was dropped above, pins are released at the end of the scope
+            let (scl, sda) = unsafe { (self.scl.clone_unchecked(), self.sda.clone_unchecked()) };
+            let mut scl = Flex::new(scl);
+            let mut sda = Flex::new(sda);
+
+            scl.set_pull(Pull::Up);
+            sda.set_pull(Pull::Up);
+            scl.set_as_input();
+            sda.set_as_input();
+            scl.set_low();
+            sda.set_low();
+
+            for pulse in 0..RECOVERY_CLOCK_PULSES {
+                if sda.is_high() {
+                    defmt::debug!("SDA released after {=u8} clock pulses", pulse);
+                    break;
+                }
+
+                scl.set_as_output();
+                Timer::after_micros(RECOVERY_HALF_PERIOD_US).await;
+                scl.set_as_input();
+                Timer::after_micros(RECOVERY_HALF_PERIOD_US).await;
+            }
+
+            // stop condition, SDA goes high while SCL is high
+            sda.set_as_output();
+            Timer::after_micros(RECOVERY_HALF_PERIOD_US).await;
+            scl.set_as_input();
+            Timer::after_micros(RECOVERY_HALF_PERIOD_US).await;
+            sda.set_as_input();
+            Timer::after_micros(RECOVERY_HALF_PERIOD_US).await;
+
+            if sda.is_low() {
+                defmt::error!("SDA is still held low after recovery");
+            }
+        }
+
+        self.init();
+        self.errors = 0;
+    }
+
+    async fn track<T>(&mut self, result: Result<T, I2cBusError>) -> Result<T, I2cBusError> {
+        match result {
+            Ok(value) => {
+                self.errors = 0;
+                Ok(value)
+            }
+            // missing acknowledge means nobody answered, which is expected while scanning the bus
+            Err(err) if matches!(err.kind(), ErrorKind::NoAcknowledge(_)) => Err(err),
+            Err(err) => {
+                self.errors += 1;
+                if self.errors >= config::Config::I2C_RECOVERY_THRESHOLD {
+                    self.recover().await;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn timeout() -> Duration {
+        Duration::from_millis(config::Config::I2C_TIMEOUT_MS)
+    }
+}
+
+impl ErrorType for I2cBus {
+    type Error = I2cBusError;
+}
+
+impl I2c for I2cBus {
+    async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        let bus = self.bus.as_mut().unwrap();
+        let result = match with_timeout(Self::timeout(), bus.read(address, read)).await {
+            Ok(result) => result.map_err(I2cBusError::I2c),
+            Err(_) => Err(I2cBusError::Timeout),
+        };
+
+        self.track(result).await
+    }
+
+    async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        let bus = self.bus.as_mut().unwrap();
+        let result = match with_timeout(Self::timeout(), bus.write(address, write)).await {
+            Ok(result) => result.map_err(I2cBusError::I2c),
+            Err(_) => Err(I2cBusError::Timeout),
+        };
+
+        self.track(result).await
+    }
+
+    async fn write_read(&mut self, address: u8, write: &[u8], read: &mut [u8]) -> Result<(), Self::Error> {
+        let bus = self.bus.as_mut().unwrap();
+        let result = match with_timeout(Self::timeout(), bus.write_read(address, write, read)).await {
+            Ok(result) => result.map_err(I2cBusError::I2c),
+            Err(_) => Err(I2cBusError::Timeout),
+        };
+
+        self.track(result).await
+    }
+
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        let bus = self.bus.as_mut().unwrap();
+        let result = match with_timeout(Self::timeout(), bus.transaction(address, operations)).await {
+            Ok(result) => result.map_err(I2cBusError::I2c),
+            Err(_) => Err(I2cBusError::Timeout),
+        };
+
+        self.track(result).await
+    }
+}
@@ -0,0 +1,2 @@
+pub mod i2c_bus;
+pub mod one_wire;
@@ -0,0 +1,211 @@
+use embassy_rp::clocks::clk_sys_freq;
+use embassy_rp::gpio::{Level, Pull};
+use embassy_rp::peripherals::PIO0;
+use embassy_rp::pio::program::pio_asm;
+use embassy_rp::pio::{Common, Config, Direction, Pin, Pio, ShiftConfig, ShiftDirection, StateMachine};
+use fixed::traits::ToFixed;
+use heapless::Vec;
+
+use crate::{Irqs, OneWireRes};
+
+const SEARCH_ROM: u8 = 0xf0;
+
+/// 1-Wire master running on PIO0 state machine 0.
+/// Program is bit oriented, every bit written to the bus yields a bit read back,
+/// which is needed for ROM search, byte transfers are built on top of it.
+pub struct OneWire {
+    _common: Common<'static, PIO0>,
+    sm: StateMachine<'static, PIO0, 0>,
+    dq: Pin<'static, PIO0>,
+    reset_addr: u8,
+}
+
+impl OneWire {
+    pub fn new(r: OneWireRes) -> Self {
+        let Pio { mut common, mut sm0, .. } = Pio::new(r.pio, Irqs);
+
+        // one cycle per microsecond, timings follow the standard speed slots
+        let prg = pio_asm!(
+            ".side_set 1 pindirs",
+            "PUBLIC reset_bus:",
+            "    set x, 28       side 1  [15]", // pull bus low for 480us
+            "loop_a:",
+            "    jmp x-- loop_a  side 1  [15]",
+            "    set x, 8        side 0  [6]", // release bus, wait for presence pulse
+            "loop_b:",
+            "    jmp x-- loop_b  side 0  [6]",
+            "    mov isr, pins   side 0", // sample presence pulse
+            "    push            side 0",
+            "    set x, 24       side 0  [7]", // wait for the end of presence pulse
+            "loop_c:",
+            "    jmp x-- loop_c  side 0  [15]",
+            ".wrap_target",
+            "fetch_bit:",
+            "    out x, 1        side 0",      // next bit from tx fifo
+            "    jmp !x send_0   side 1  [5]", // pull bus low
+            "send_1:",
+            "    set x, 2        side 0  [8]", // release bus, slave might pull it low
+            "    in pins, 1      side 0  [4]", // sample bus 15us after start of the slot
+            "loop_e:",
+            "    jmp x-- loop_e  side 0  [15]",
+            "    jmp fetch_bit   side 0",
+            "send_0:",
+            "    set x, 2        side 1  [5]", // keep bus low for 60us
+            "loop_d:",
+            "    jmp x-- loop_d  side 1  [15]",
+            "    in null, 1      side 0  [8]", // release bus, nothing to read
+            ".wrap",
+        );
+        let loaded = common.load_program(&prg.program);
+
+        let mut dq = common.make_pio_pin(r.dq);
+        dq.set_pull(Pull::Up);
+
+        let mut cfg = Config::default();
+        cfg.use_program(&loaded, &[&dq]);
+        cfg.set_in_pins(&[&dq]);
+        cfg.set_set_pins(&[&dq]);
+        cfg.shift_in = ShiftConfig {
+            auto_fill: true,
+            direction: ShiftDirection::Left,
+            threshold: 1,
+        };
+        cfg.shift_out = ShiftConfig {
+            auto_fill: true,
+            direction: ShiftDirection::Right,
+            threshold: 1,
+        };
+        cfg.clock_divider = (clk_sys_freq() / 1_000_000).to_fixed();
+
+        sm0.set_config(&cfg);
+        // bus is driven low by switching pin direction to output
+        sm0.set_pins(Level::Low, &[&dq]);
+        sm0.set_pin_dirs(Direction::In, &[&dq]);
+        sm0.set_enable(true);
+
+        Self {
+            _common: common,
+            sm: sm0,
+            dq,
+            reset_addr: loaded.origin + prg.public_defines.reset_bus as u8,
+        }
+    }
+
+    /// Issues reset pulse, returns true when at least one device answered with presence pulse
+    pub async fn reset(&mut self) -> bool {
+        while self.sm.rx().try_pull().is_some() {}
+
+        unsafe { self.sm.exec_jmp(self.reset_addr) };
+
+        self.sm.rx().wait_pull().await & 0x01 == 0
+    }
+
+    pub async fn write_bit(&mut self, bit: bool) -> bool {
+        self.sm.tx().wait_push(bit as u32).await;
+        self.sm.rx().wait_pull().await != 0
+    }
+
+    pub async fn read_bit(&mut self) -> bool {
+        self.write_bit(true).await
+    }
+
+    pub async fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            for i in 0..8 {
+                self.write_bit(byte & (1 << i) != 0).await;
+            }
+        }
+    }
+
+    pub async fn read_bytes(&mut self, bytes: &mut [u8]) {
+        for byte in bytes.iter_mut() {
+            *byte = 0;
+            for i in 0..8 {
+                if self.read_bit().await {
+                    *byte |= 1 << i;
+                }
+            }
+        }
+    }
+
+    /// Drives the bus high to power parasitic devices during conversion or eeprom write
+    pub fn strong_pull_up(&mut self, enable: bool) {
+        if enable {
+            self.sm.set_pins(Level::High, &[&self.dq]);
+            self.sm.set_pin_dirs(Direction::Out, &[&self.dq]);
+        } else {
+            self.sm.set_pin_dirs(Direction::In, &[&self.dq]);
+            self.sm.set_pins(Level::Low, &[&self.dq]);
+        }
+    }
+
+    /// Finds ROM codes of devices on the bus, following the algorithm from Maxim application note 187
+    pub async fn search<const N: usize>(&mut self, roms: &mut Vec<[u8; 8], N>) {
+        let mut rom = [0u8; 8];
+        let mut last_discrepancy = 0;
+
+        loop {
+            if !self.reset().await {
+                return;
+            }
+            self.write_bytes(&[SEARCH_ROM]).await;
+
+            let mut discrepancy = 0;
+            for bit in 1..=64 {
+                let (byte, mask) = ((bit - 1) / 8, 1u8 << ((bit - 1) % 8));
+                let id_bit = self.read_bit().await;
+                let cmp_id_bit = self.read_bit().await;
+
+                let direction = match (id_bit, cmp_id_bit) {
+                    // no device participates in the search anymore
+                    (true, true) => return,
+                    (false, false) => {
+                        let direction = if bit < last_discrepancy {
+                            rom[byte] & mask != 0
+                        } else {
+                            bit == last_discrepancy
+                        };
+                        if !direction {
+                            discrepancy = bit;
+                        }
+                        direction
+                    }
+                    (id_bit, _) => id_bit,
+                };
+
+                if direction {
+                    rom[byte] |= mask;
+                } else {
+                    rom[byte] &= !mask;
+                }
+                self.write_bit(direction).await;
+            }
+
+            if crc8(&rom[..7]) == rom[7] && roms.push(rom).is_err() {
+                return;
+            }
+
+            last_discrepancy = discrepancy;
+            if last_discrepancy == 0 {
+                return;
+            }
+        }
+    }
+}
+
+/// Dallas/Maxim CRC-8, polynomial x^8 + x^5 + x^4 + 1, guards ROM codes and scratchpads
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        let mut byte = *byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 0x01;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8c;
+            }
+            byte >>= 1;
+        }
+    }
+    crc
+}
@@ -0,0 +1,19 @@
+pub mod rtc_clock;
+
+/// Trait to represent wall clock of the device, disciplined by the network.
+/// Time is kept in unix seconds, conversion to GPS epoch used by LoRaWAN is left up to the caller.
+pub trait Clock {
+    /// Seconds since unix epoch, none until the clock was synchronized
+    fn now(&mut self) -> Option<u32>;
+
+    /// Seconds since unix epoch whether synchronized or not, zero based if never set
+    fn device_time(&mut self) -> u32;
+
+    /// Shift the clock by correction received from the network, marks it synchronized
+    fn adjust(&mut self, correction: i32);
+
+    /// Seconds since midnight UTC, for time-of-day scheduling
+    fn time_of_day(&mut self) -> Option<u32> {
+        self.now().map(|now| now % (24 * 60 * 60))
+    }
+}
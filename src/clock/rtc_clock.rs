@@ -0,0 +1,143 @@
+use core::cell::RefCell;
+
+use embassy_rp::peripherals::RTC;
+use embassy_rp::rtc::{DateTime, DayOfWeek, Rtc};
+use embassy_rp::watchdog::Watchdog;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::clock::Clock;
+use crate::ClockRes;
+
+// watchdog scratch registers survive warm resets, unlike the rtc which is reset along with the chip
+const SCRATCH_MAGIC: usize = 0;
+const SCRATCH_TIME: usize = 1;
+const MAGIC: u32 = 0x7173_0001;
+
+/// Watchdog fed by its own task, the clock borrows it for the scratch registers
+/// and firmware updates stop it while verifying an image
+pub type SharedWatchdog = Mutex<NoopRawMutex, RefCell<Watchdog>>;
+
+/// Wall clock kept by the RP2040 RTC, the last known time is mirrored
+/// into watchdog scratch registers and restored after a warm reset.
+pub struct RtcClock {
+    rtc: Rtc<'static, RTC>,
+    watchdog: &'static SharedWatchdog,
+    synchronized: bool,
+}
+
+impl RtcClock {
+    pub fn new(r: ClockRes, watchdog: &'static SharedWatchdog) -> Self {
+        let mut clock = Self {
+            rtc: Rtc::new(r.rtc),
+            watchdog,
+            synchronized: false,
+        };
+
+        let (magic, time) = watchdog.lock(|watchdog| {
+            let mut watchdog = watchdog.borrow_mut();
+            (watchdog.get_scratch(SCRATCH_MAGIC), watchdog.get_scratch(SCRATCH_TIME))
+        });
+        if magic == MAGIC {
+            // restored time lags by the reset, it serves as device time until the network corrects it
+            defmt::info!("Restoring clock after warm reset, unix time {=u32}", time);
+            clock.set(time);
+        } else {
+            clock.set(0);
+        }
+
+        clock
+    }
+
+    fn set(&mut self, unix: u32) {
+        if let Err(e) = self.rtc.set_datetime(to_datetime(unix)) {
+            defmt::error!("Setting rtc failed, {:?}", e);
+        }
+    }
+
+    fn persist(&mut self, unix: u32) {
+        self.watchdog.lock(|watchdog| {
+            let mut watchdog = watchdog.borrow_mut();
+            watchdog.set_scratch(SCRATCH_TIME, unix);
+            watchdog.set_scratch(SCRATCH_MAGIC, MAGIC);
+        });
+    }
+}
+
+impl Clock for RtcClock {
+    fn now(&mut self) -> Option<u32> {
+        if !self.synchronized {
+            return None;
+        }
+
+        let now = self.device_time();
+        self.persist(now);
+        Some(now)
+    }
+
+    fn device_time(&mut self) -> u32 {
+        self.rtc.now().map(from_datetime).unwrap_or(0)
+    }
+
+    fn adjust(&mut self, correction: i32) {
+        let time = self.device_time().wrapping_add_signed(correction);
+        self.set(time);
+        self.persist(time);
+        self.synchronized = true;
+    }
+}
+
+/// Days since 1970-01-01 to proleptic gregorian date (year, month, day)
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Proleptic gregorian date to days since 1970-01-01
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = year - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn to_datetime(unix: u32) -> DateTime {
+    let days = (unix / 86_400) as i64;
+    let secs = unix % 86_400;
+    let (year, month, day) = civil_from_days(days);
+    let day_of_week = match (days + 4) % 7 {
+        0 => DayOfWeek::Sunday,
+        1 => DayOfWeek::Monday,
+        2 => DayOfWeek::Tuesday,
+        3 => DayOfWeek::Wednesday,
+        4 => DayOfWeek::Thursday,
+        5 => DayOfWeek::Friday,
+        _ => DayOfWeek::Saturday,
+    };
+
+    DateTime {
+        year: year as u16,
+        month,
+        day,
+        day_of_week,
+        hour: (secs / 3600) as u8,
+        minute: (secs / 60 % 60) as u8,
+        second: (secs % 60) as u8,
+    }
+}
+
+fn from_datetime(t: DateTime) -> u32 {
+    let days = days_from_civil(t.year as i64, t.month, t.day);
+    (days * 86_400 + t.hour as i64 * 3600 + t.minute as i64 * 60 + t.second as i64) as u32
+}
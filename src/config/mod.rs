@@ -1,18 +1,163 @@
+#[cfg(feature = "p2p")]
+use lora_phy::mod_params::{Bandwidth, CodingRate, SpreadingFactor};
 use lorawan_device::region;
 
+/// Adc input a soil probe is wired to
+#[derive(Clone, Copy)]
+#[allow(dead_code)] // variants are picked by the wiring below
+pub enum SoilInput {
+    Adc1,    // GPIO27
+    Adc2,    // GPIO28
+    Mux(u8), // multiplexer channel, multiplexer output is wired to `Config::SOIL_MUX_INPUT`
+}
+
+/// Pin providing excitation to a soil probe, several probes might share the same pin
+#[derive(Clone, Copy)]
+#[allow(dead_code)] // variants are picked by the wiring below
+pub enum SoilPower {
+    Gpio22,
+    Gpio21,
+}
+
+/// Kind of a soil probe, which determines its power up timing and response curve
+#[derive(Clone, Copy)]
+#[allow(dead_code)] // variants are picked by the wiring below
+pub enum SoilProbeKind {
+    Resistive,  // e.g. SparkFun, output rises with moisture
+    Capacitive, // e.g. capacitive v1.2, output drops with moisture
+    Seesaw(u8), // Adafruit STEMMA on the I2C bus at given address, input and power are ignored
+}
+
+pub struct SoilProbe {
+    pub kind: SoilProbeKind,
+    pub input: SoilInput,
+    pub power: SoilPower,
+    pub channel: u8, // payload channel
+}
+
+/// Battery cell chemistry, which determines the discharge curve
+#[derive(Clone, Copy)]
+#[allow(dead_code)] // variants are picked by the config below
+pub enum BatteryChemistry {
+    LiIon,   // LiPo and 18650 cells
+    LiFePo4, // LiFePO4 cells
+}
+
+/// LoRaWAN device class, Class C keeps the receiver on between uplinks which costs several mA
+#[derive(Clone, Copy)]
+#[allow(dead_code)] // variants are picked by the config below
+pub enum LorawanClass {
+    A,        // downlinks arrive only after uplinks
+    C,        // downlinks arrive at any time
+    CUsbOnly, // Class C while powered by usb, Class A on battery
+}
+
+/// Sync word of the point-to-point mode, lora-phy only offers the two LoRaWAN ones
+#[cfg(feature = "p2p")]
+#[allow(dead_code)] // variants are picked by the config below
+pub enum P2pSyncWord {
+    Public,  // 0x34, shared with LoRaWAN networks
+    Private, // 0x12
+}
+
 pub struct Config;
 
 impl Config {
-    pub const I2C_ADDR_AIR_SENSOR: u16 = 0x62;
+    pub const I2C_FREQUENCY: u32 = 100_000;
+    pub const I2C_TIMEOUT_MS: u64 = 100;
+    pub const I2C_RECOVERY_THRESHOLD: u8 = 3; // consecutive errors before bus recovery
+
+    pub const I2C_ADDR_AIR_SENSOR: u8 = 0x62;
 
-    pub const DEV_EUI: [u8; 8] = [0xd5, 0x2e, 0x0f, 0x9f, 0xf9, 0x9f, 0x7b, 0x58];
+    pub const DEV_EUI: Option<[u8; 8]> = Some([0xd5, 0x2e, 0x0f, 0x9f, 0xf9, 0x9f, 0x7b, 0x58]); // None derives it from the flash unique id
+    pub const DEV_EUI_PREFIX: &[u8] = &[]; // msb first, e.g. 24 bit OUI, up to 7 bytes
     pub const APP_EUI: [u8; 8] = [0xda, 0x51, 0x8e, 0xd0, 0x28, 0x22, 0xb6, 0x34];
     pub const APP_KEY: [u8; 16] = [
         0x64, 0x70, 0xc3, 0xb4, 0x64, 0x76, 0x3f, 0x66, 0xb7, 0x3a, 0x32, 0x36, 0xe2, 0xea, 0xe2, 0xee,
     ];
 
-    pub const LORAWAN_REGION: region::Region = region::Region::EU868;
+    pub const LORAWAN_REGION: region::Region = region::Region::EU868; // enable the matching region-* cargo feature
+    pub const LORAWAN_SUBBAND: Option<u8> = None; // 1 to 8, US915 and AU915 only, e.g. 2 for TTN and most US gateways
+    pub const LORAWAN_DATA_RATE: u8 = 5; // initial data rate, DR5 is SF7 in EU868, use DR0 to DR4 in US915
+    pub const LORAWAN_MAX_TX_POWER: u8 = 14; // dBm, radio never transmits above
+    pub const LORAWAN_ADR: bool = true;
+    pub const LORAWAN_DR_FALLBACK_NOACKS: u8 = 2; // unacknowledged confirmed uplinks before stepping data rate down, 0 disables
+    pub const LORAWAN_CLASS: LorawanClass = LorawanClass::A; // Class C for actuators, e.g. valve controllers on mains power
+    pub const DOWNLINK_QUEUE: usize = 4; // Class C downlinks waiting for the device
     pub const RX_WINDOW_LEAD_TIME: u32 = 1000; // default is 50
     pub const RX_WINDOW_BUFFER: u32 = 1000; // defautl is 50
     pub const RESET: bool = false;
+
+    pub const SYSTEM_SAMPLES: usize = 16; // adc conversions averaged per reading
+    pub const SYSTEM_ADC_CALIBRATION: Option<[(u16, u16); 2]> = None; // (raw, millivolts) on adc pin, low and high reference
+    pub const VSYS_USB_VOLTAGE: f32 = 4.8; // vbus behind the schottky diode
+    pub const VSYS_TOLERANCE: f32 = 0.3;
+
+    pub const BATTERY_CHEMISTRY: BatteryChemistry = BatteryChemistry::LiIon;
+    pub const BATTERY_INTERNAL_RESISTANCE_MOHM: f32 = 150.0;
+    pub const BATTERY_IDLE_LOAD_MA: f32 = 25.0; // board consumption while sampling the battery
+    pub const BATTERY_LOW_THRESHOLD: f32 = 20.0; // percent
+
+    pub const SOIL_PROBES: &[SoilProbe] = &[SoilProbe {
+        kind: SoilProbeKind::Resistive,
+        input: SoilInput::Adc1,
+        power: SoilPower::Gpio22,
+        channel: 0x02,
+    }];
+    pub const SOIL_RAW_CHANNEL_OFFSET: u8 = 0x10; // raw readings go out as analog input on the probe channel plus offset
+    pub const SOIL_MUX_INPUT: SoilInput = SoilInput::Adc2;
+    pub const SOIL_MUX_SELECT_LINES: usize = 3; // 3 for CD4051, 4 for 74HC4067
+    pub const SOIL_WARM_UP_MS: u64 = 200;
+    pub const SOIL_CAPACITIVE_WARM_UP_MS: u64 = 1000;
+    pub const SOIL_SAMPLES: usize = 32;
+    pub const SOIL_SEESAW_SAMPLES: usize = 4;
+    pub const SOIL_SAMPLE_DIV: u16 = 4799; // 48MHz / (4799 + 1) = 10kHz sample rate
+    pub const SOIL_TRIM_PERCENT: usize = 10; // 50 turns trimmed mean into median
+    pub const SOIL_TEMPERATURE_RESOLUTION: u8 = 12; // 9 to 12 bits
+    pub const SOIL_TEMPERATURE_CHANNEL: u8 = 0x20; // first ds18b20 probe, following probes take next channels
+    pub const SOIL_CALIBRATION: bool = false; // interactive dry/wet calibration at boot
+    pub const SOIL_CALIBRATION_DELAY: u64 = 30; // seconds to move the probe between reference points
+
+    pub const FPORT_TELEMETRY: u8 = 1;
+    pub const FPORT_DEVICE_INFO: u8 = 2;
+    pub const FPORT_COMMAND: u8 = 3; // downlink commands: 0x01 device info, 0x02 data rate, 0x03 tx power, 0x04 adr, 0x05 soil calibration
+    pub const FPORT_LINK_CHECK: u8 = 4; // empty probe, LoRaWAN drops the port and sends a LinkCheckReq instead
+    pub const UPLINK_NB_TRANS: u8 = 1; // transmissions of unconfirmed uplinks, each a new frame the application receives
+    pub const CONFIRMED_NB_TRANS: u8 = 3; // transmissions of confirmed uplinks until acked, unacked ones may arrive duplicated
+    pub const HEARTBEAT_INTERVAL: u16 = 12; // every n-th telemetry uplink is confirmed, 0 disables heartbeats
+    pub const HEARTBEAT_MISSED_LIMIT: u8 = 3; // consecutive unacknowledged heartbeats before an OTAA rejoin
+    pub const LINK_CHECK_INTERVAL: u16 = 6; // uplinks between link checks, 0 disables them
+    pub const LINK_CHECK_DR_STEP_DOWN_LIMIT: u8 = 2; // missed link checks before stepping data rate down
+    pub const LINK_CHECK_REJOIN_LIMIT: u8 = 4; // missed link checks before an OTAA rejoin
+    pub const CLOCK_SYNC_INTERVAL: u16 = 144; // uplinks between clock syncs, 0 syncs only after join and on request
+    pub const GPS_LEAP_SECONDS: u32 = 18; // GPS time runs ahead of UTC since 2017
+
+    pub const GEN_APP_KEY: [u8; 16] = Self::APP_KEY; // root of the multicast keys, GenAppKey of LoRaWAN 1.0.x devices
+    pub const FRAGMENT_SIZE_MAX: usize = 64; // bytes, larger fragmentation sessions are refused
+    pub const FRAGMENT_LOST_MAX: usize = 64; // lost fragments recoverable by redundancy, up to 128, 16 + size bytes of ram each
+    pub const FIRMWARE_CONFIRM_TIMEOUT: u64 = 60 * 60; // seconds an updated image has to reach the network before it is reverted
+    pub const FIRMWARE_PUBLIC_KEY: [u8; 32] = [
+        0xa1, 0x3b, 0xe4, 0xd0, 0x3b, 0x33, 0x43, 0x5d, 0x7a, 0x0c, 0x4c, 0xcd, 0x7b, 0x35, 0x98, 0x98, 0xe2, 0xf3, 0x17, 0xc6, 0x9d, 0xdd,
+        0x3b, 0x4f, 0xe9, 0x16, 0x4f, 0xd2, 0x39, 0x5b, 0xad, 0x9c,
+    ]; // ed25519 key firmware images are signed with, replace with your own
+    pub const WATCHDOG_TIMEOUT_MS: u64 = 8000; // bootloader starts the watchdog, the application keeps feeding it
+    pub const WATCHDOG_FEED_SECS: u64 = 2;
+}
+
+#[cfg(feature = "p2p")]
+impl Config {
+    pub const P2P_FREQUENCY: u32 = 868_100_000; // Hz
+    pub const P2P_SPREADING_FACTOR: SpreadingFactor = SpreadingFactor::_9;
+    pub const P2P_BANDWIDTH: Bandwidth = Bandwidth::_125KHz;
+    pub const P2P_CODING_RATE: CodingRate = CodingRate::_4_5;
+    pub const P2P_SYNC_WORD: P2pSyncWord = P2pSyncWord::Private;
+    pub const P2P_TX_POWER: i8 = 14; // dBm
+    pub const P2P_ACK_TIMEOUT_MS: u64 = 2000;
+    pub const P2P_SEQUENCE_RESERVE: u32 = 1024; // frames sent between flash writes of the sequence, keeps it moving forward across resets
+    pub const P2P_GATEWAY_NODES: usize = 32; // nodes the gateway tracks sequences of for replay protection
+    pub const P2P_KEY: [u8; 16] = [
+        0x3c, 0x1f, 0x85, 0x6e, 0xa2, 0x47, 0x90, 0x0b, 0xd4, 0x5e, 0x13, 0xc8, 0x71, 0x2a, 0xf6, 0x09,
+    ];
 }
+
+const _: () = assert!(Config::DEV_EUI_PREFIX.len() < 8, "DevEUI prefix must leave room for the unique id");
@@ -1,33 +1,70 @@
+use embassy_futures::select::{select, select3, Either, Either3};
 use embassy_rp::adc::{self, Async};
-use embassy_time::{Duration, Ticker, Timer};
+use embassy_time::{Duration, Instant, Ticker, Timer};
 use heapless::Vec;
-use lorawan_device::{AppEui, AppKey, AppSKey, DevAddr, DevEui, NewSKey};
+use lora_fuota::{frag, multicast};
+use lorawan_device::{AppEui, AppKey, DevEui};
+use sensor_core::cayenne;
 
-use crate::config;
-use crate::radio::lora_radio::LoraRadioError;
-use crate::radio::Radio;
-use crate::sensor::air_sensor::AirSensorError;
+use crate::clock::Clock;
+use crate::config::{self, LorawanClass};
+use crate::firmware::fuota::Fuota;
+use crate::firmware::Firmware;
+use crate::radio::clock_sync::{self, ClockSyncCommand};
+use crate::radio::{Downlink, LinkCheck, LinkQuality, Radio, RadioError, Session, Uplink, UplinkOptions, DOWNLINKS};
+use crate::secret::Secret;
+use crate::sensor::ds18b20::Ds18b20Error;
+use crate::sensor::i2c_sensors::I2cSensorError;
+use crate::sensor::soil_sensor::SoilCalibration;
 use crate::sensor::soil_sensor::SoilSensorError;
-use crate::sensor::system_sensor::SystemSensorError;
-use crate::sensor::Sensor;
+use crate::sensor::system_sensor::{AdcCalibration, SystemSensor};
+use crate::sensor::{CalibrationPoint, Maintenance, Sensor};
 use crate::storage::flash_storage::FlashStorageError;
 use crate::storage::{Key, Storage};
 
+// firmware identity embedded by build.rs
+const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+const FIRMWARE_GIT_HASH: &str = env!("GIT_HASH");
+const FIRMWARE_BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+
+fn firmware_version() -> (u8, u8, u8) {
+    let version = |part: &str| part.parse().unwrap_or(0);
+    (
+        version(env!("CARGO_PKG_VERSION_MAJOR")),
+        version(env!("CARGO_PKG_VERSION_MINOR")),
+        version(env!("CARGO_PKG_VERSION_PATCH")),
+    )
+}
+
+fn firmware_git_hash() -> u32 {
+    u32::from_str_radix(FIRMWARE_GIT_HASH, 16).unwrap_or(0)
+}
+
+fn firmware_build_timestamp() -> u32 {
+    FIRMWARE_BUILD_TIMESTAMP.parse().unwrap_or(0)
+}
+
 #[derive(defmt::Format)]
 pub enum DeviceError {
     Auth,
     AuthFailed,
     AuthJoinAttemptsExhausted,
     SessionExpired,
+    LinkLost,
     NoAck,
     Duty,
     Send,
+    Calibration,
+    DevEui,
     Storage(FlashStorageError),
 }
 
 pub enum State {
     Boot,
     Auth,
+    Info,
+    Sync,
+    Check,
     Duty,
     Send,
     Idle(u64),
@@ -39,47 +76,108 @@ impl Default for State {
     }
 }
 
-pub struct Device<S0, S1, S2, R, D>
+pub struct Device<S1, S2, S3, R, D, C, F>
 where
-    S0: Sensor<18>,
-    S1: Sensor<4>,
-    S2: Sensor<11>,
+    S1: Sensor<60>,
+    S2: Sensor<48>,
+    S3: Sensor<16>,
     R: Radio,
     D: Storage,
+    C: Clock,
+    F: Firmware,
 {
     state: State,
 
     adc: adc::Adc<'static, Async>,
 
-    system: S0,
+    system: SystemSensor,
     soil: S1,
     air: S2,
+    soil_temperature: S3,
     radio: R,
     storage: D,
+    clock: C,
+    firmware: F,
 
-    data: Vec<u8, 33>,
+    data: Vec<u8, 169>,
     auth_attempt: u8,
+    dev_eui: Option<[u8; 8]>,
+    link: Option<LinkQuality>,
+    tx_settings: Option<(u8, Option<i8>)>, // data rate and power of the last uplink
+    info_requested: bool,
+    calibration_requested: Option<(usize, CalibrationPoint)>, // soil probe and reference point requested by the network
+    maintenance_requested: Option<Maintenance>,               // air sensor maintenance requested by the network
+    uplinks: u16,
+    missed_heartbeats: u8,
+    clock_sync_requested: bool,
+    clock_sync_token: u8,
+    clock_sync_pending: bool, // request with the token awaits its answer, late answers arrive as downlinks
+    package_version_requested: bool,
+    link_check: Option<LinkCheck>, // last answered link check
+    link_margin_min: Option<i8>,   // lowest margin since the last telemetry
+    missed_link_checks: u8,
+    link_check_pending: bool, // due link check waits for a free slot, e.g. behind a clock sync
+    force_otaa: bool,
+    class_c: bool,
+    fuota: Fuota,
+    firmware_pending: bool, // running image awaits confirmation, reverted on reset otherwise
 }
 
-impl<S0, S1, S2, R, D> Device<S0, S1, S2, R, D>
+impl<S1, S2, S3, R, D, C, F> Device<S1, S2, S3, R, D, C, F>
 where
-    S0: Sensor<18, Error = SystemSensorError>,
-    S1: Sensor<4, Error = SoilSensorError>,
-    S2: Sensor<11, Error = AirSensorError>,
-    R: Radio<Error = LoraRadioError>,
+    S1: Sensor<60, Error = SoilSensorError>,
+    S2: Sensor<48, Error = I2cSensorError>,
+    S3: Sensor<16, Error = Ds18b20Error>,
+    R: Radio,
     D: Storage<Error = FlashStorageError>,
+    C: Clock,
+    F: Firmware,
 {
-    pub fn new(adc: adc::Adc<'static, Async>, board_sensor: S0, soil_sensor: S1, air_sensor: S2, transceiver: R, database: D) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        adc: adc::Adc<'static, Async>,
+        board_sensor: SystemSensor,
+        soil_sensor: S1,
+        air_sensor: S2,
+        soil_temperature_sensor: S3,
+        transceiver: R,
+        database: D,
+        clock: C,
+        firmware: F,
+    ) -> Self {
         Self {
             state: State::default(),
             adc,
             system: board_sensor,
             soil: soil_sensor,
             air: air_sensor,
+            soil_temperature: soil_temperature_sensor,
             radio: transceiver,
             storage: database,
+            clock,
+            firmware,
             data: Vec::new(),
             auth_attempt: 0,
+            dev_eui: None,
+            link: None,
+            tx_settings: None,
+            info_requested: false,
+            calibration_requested: None,
+            maintenance_requested: None,
+            uplinks: 0,
+            missed_heartbeats: 0,
+            clock_sync_requested: false,
+            clock_sync_token: 0,
+            clock_sync_pending: false,
+            package_version_requested: false,
+            link_check: None,
+            link_margin_min: None,
+            missed_link_checks: 0,
+            link_check_pending: false,
+            force_otaa: false,
+            class_c: false,
+            fuota: Fuota::new(),
+            firmware_pending: false,
         }
     }
 
@@ -92,17 +190,33 @@ where
                     Err(_) => State::Idle(60 * 60),
                 },
                 State::Auth => match self.auth().await {
-                    Ok(()) => State::Duty,
+                    Ok(()) => State::Info,
                     Err(DeviceError::AuthFailed) => State::Auth,
                     Err(_) => State::Idle(60 * 60),
                 },
+                State::Info => match self.uplink_device_info().await {
+                    Ok(()) if self.info_requested => State::Info,
+                    Err(DeviceError::SessionExpired) => State::Auth,
+                    _ => State::Sync,
+                },
+                State::Sync => match self.sync_clock().await {
+                    Err(DeviceError::SessionExpired) => State::Auth,
+                    _ => State::Duty,
+                },
+                State::Check => match self.check_link().await {
+                    Err(DeviceError::SessionExpired) | Err(DeviceError::LinkLost) => State::Auth,
+                    _ => State::Duty,
+                },
                 State::Duty => match self.collect_data().await {
                     Ok(()) => State::Send,
                     Err(_) => State::Idle(60 * 60),
                 },
                 State::Send => match self.uplink().await {
+                    Ok(()) if self.info_requested => State::Info,
+                    Ok(()) if self.is_clock_sync_due() => State::Sync,
+                    Ok(()) if self.is_link_check_due() => State::Check,
                     Ok(()) | Err(DeviceError::NoAck) => State::Duty,
-                    Err(DeviceError::SessionExpired) => State::Auth,
+                    Err(DeviceError::SessionExpired) | Err(DeviceError::LinkLost) => State::Auth,
                     Err(_) => State::Idle(60 * 60),
                 },
                 State::Idle(secs) => {
@@ -110,12 +224,136 @@ where
                     State::Auth
                 }
             };
-            ticker.next().await;
+            self.wait(&mut ticker).await;
+        }
+    }
+
+    /// Waits for the next step, Class C nodes handle downlinks meanwhile.
+    /// Firmware update sessions are served in between, their start and end wake the device up.
+    async fn wait(&mut self, ticker: &mut Ticker) {
+        self.reserve_sequence().await;
+
+        loop {
+            if self.is_joined() {
+                self.serve_fuota().await;
+            }
+            self.check_firmware_confirmation();
+            self.update_class().await;
+
+            let session_change = self.fuota.session_change();
+            let session_change = async move {
+                match session_change {
+                    Some(at) => Timer::at(at).await,
+                    None => core::future::pending().await,
+                }
+            };
+
+            if !self.class_c {
+                match select(ticker.next(), session_change).await {
+                    Either::First(()) => return,
+                    Either::Second(()) => continue,
+                }
+            }
+
+            let listen = select(self.radio.listen(), DOWNLINKS.receive());
+            match select3(ticker.next(), listen, session_change).await {
+                Either3::First(()) => return,
+                Either3::Second(Either::First(result)) => {
+                    // listening stops only on errors, rest of the period is waited out
+                    match result {
+                        Err(e) if e.is_session_expired() => {
+                            defmt::error!("LoRaWAN session expired, re-authenticating");
+                            self.state = State::Auth;
+                        }
+                        Err(e) => defmt::error!("Class C listening failed, {:?}", e),
+                        Ok(()) => {}
+                    }
+                    ticker.next().await;
+                    return;
+                }
+                Either3::Second(Either::Second(downlink)) => self.handle_downlink(&downlink),
+                Either3::Third(()) => {}
+            }
+        }
+    }
+
+    fn is_joined(&self) -> bool {
+        !matches!(self.state, State::Boot | State::Auth | State::Idle(_))
+    }
+
+    /// Class C only while joined and, if configured so, only while powered by usb.
+    /// Scheduled multicast sessions are received in Class C regardless.
+    async fn update_class(&mut self) {
+        // ended sessions are cleared whether joined or not, their end would wake the device up again
+        let in_session = self.fuota.in_session();
+        let class_c = self.is_joined()
+            && (in_session
+                || match config::Config::LORAWAN_CLASS {
+                    LorawanClass::A => false,
+                    LorawanClass::C => true,
+                    LorawanClass::CUsbOnly => self.system.external_power(),
+                });
+        if class_c == self.class_c {
+            return;
+        }
+
+        match self.radio.set_class_c(class_c).await {
+            Ok(()) => {
+                defmt::info!("Switched to Class {=str}", if class_c { "C" } else { "A" });
+                self.class_c = class_c;
+            }
+            Err(e) => defmt::error!("Failed to switch LoRaWAN class, {:?}", e),
+        }
+    }
+
+    /// Erases room for a set up image, sends answers of the update packages and applies a complete image
+    async fn serve_fuota(&mut self) {
+        if let Err(e) = self.fuota.prepare(&mut self.firmware).await {
+            defmt::error!("Update partition erase failed, {:?}", e);
+        }
+
+        while let Some((port, answer)) = self.fuota.take_answer() {
+            defmt::info!("Sending answer on port {=u8} with payload {=[u8]:#x}", port, answer.as_slice());
+            let result = self.radio.uplink(&UplinkOptions::unconfirmed(port), &answer).await;
+            if let Err(DeviceError::SessionExpired) = self.uplink_result(result) {
+                self.state = State::Auth;
+                return;
+            }
+        }
+
+        if let Some(size) = self.fuota.take_image() {
+            defmt::info!("Firmware image of {=usize} bytes received, verifying signature", size);
+            match self.firmware.apply(size) {
+                Ok(()) => {
+                    defmt::info!("Firmware image verified, resetting into the bootloader");
+                    Timer::after_millis(100).await; // let the log reach the probe
+                    self.firmware.reset();
+                }
+                Err(e) => defmt::error!("Firmware image rejected, {:?}", e),
+            }
+        }
+    }
+
+    /// Updated image which did not reach the network in time is reverted by the bootloader
+    fn check_firmware_confirmation(&mut self) {
+        if self.firmware_pending && Instant::now().as_secs() >= config::Config::FIRMWARE_CONFIRM_TIMEOUT {
+            defmt::error!("Updated firmware not confirmed in time, reverting to the previous image");
+            self.firmware.reset();
         }
     }
 
     pub async fn boot(&mut self) -> Result<(), DeviceError> {
-        defmt::info!("Booting device");
+        defmt::info!(
+            "Booting device, firmware {=str} git {=str} built {=str}",
+            FIRMWARE_VERSION,
+            FIRMWARE_GIT_HASH,
+            FIRMWARE_BUILD_TIMESTAMP
+        );
+
+        self.firmware_pending = self.firmware.is_pending();
+        if self.firmware_pending {
+            defmt::warn!("Running updated firmware, confirmed once the device info reaches the network");
+        }
 
         if (self.storage.mount().await).is_err() || config::Config::RESET {
             defmt::info!("Formating flash storage");
@@ -126,11 +364,49 @@ where
             }
         }
 
+        let mut calibration = [0u8; AdcCalibration::SIZE];
+        if config::Config::SYSTEM_ADC_CALIBRATION.is_some() {
+            let size = self.system.calibration(0, &mut calibration);
+            if let Err(e) = self.storage.put(&Key::AdcCalibration, &calibration[..size]).await {
+                defmt::error!("Adc calibration persist failed, {:?}", e);
+            }
+        } else if let Some(size) = self.storage.get(&Key::AdcCalibration, &mut calibration).await {
+            self.system.restore_calibration(0, &calibration[..size]);
+        }
+
+        let mut sequence = [0u8; 4];
+        if let Some(4) = self.storage.get(&Key::Sequence, &mut sequence).await {
+            self.radio.restore_sequence(u32::from_le_bytes(sequence));
+        }
+
         match self.system.verify().await {
             Ok(()) => defmt::info!("System sensors booted"),
             Err(e) => defmt::error!("System sensors boot failed, {:?}", e),
         }
 
+        self.dev_eui = match (config::Config::DEV_EUI, self.system.serial_number()) {
+            (Some(dev_eui), _) => {
+                Self::log_dev_eui("provisioned", dev_eui);
+                Some(dev_eui)
+            }
+            (None, Some(unique_id)) => {
+                let dev_eui = Self::derive_dev_eui(unique_id);
+                Self::log_dev_eui("derived", dev_eui);
+                Some(dev_eui)
+            }
+            (None, None) => {
+                defmt::error!("DevEUI is neither provisioned nor derivable, flash unique id is unknown");
+                None
+            }
+        };
+
+        for probe in 0..self.soil.probes() {
+            let mut calibration = [0u8; SoilCalibration::SIZE];
+            if let Some(size) = self.storage.get(&Key::SoilCalibration(probe as u8), &mut calibration).await {
+                self.soil.restore_calibration(probe, &calibration[..size]);
+            }
+        }
+
         let _ = self.soil.on().await;
         match self.soil.verify().await {
             Ok(()) => defmt::info!("Soil sensor booted"),
@@ -138,27 +414,80 @@ where
         }
         let _ = self.soil.off().await;
 
+        if config::Config::SOIL_CALIBRATION {
+            let delay = config::Config::SOIL_CALIBRATION_DELAY;
+
+            defmt::info!("Soil sensor calibration - place the probes in the air, sampling in {=u64}s", delay);
+            Timer::after_secs(delay).await;
+            for probe in 0..self.soil.probes() {
+                if let Err(e) = self.calibrate_soil(probe, CalibrationPoint::Dry).await {
+                    defmt::error!("Soil probe {=usize} calibration failed, {:?}", probe, e);
+                }
+            }
+
+            defmt::info!("Soil sensor calibration - place the probes in water, sampling in {=u64}s", delay);
+            Timer::after_secs(delay).await;
+            for probe in 0..self.soil.probes() {
+                if let Err(e) = self.calibrate_soil(probe, CalibrationPoint::Wet).await {
+                    defmt::error!("Soil probe {=usize} calibration failed, {:?}", probe, e);
+                }
+            }
+
+            defmt::info!("Soil sensor calibration finished");
+        }
+
         match self.air.verify().await {
-            Ok(()) => defmt::info!("Air sensor booted"),
-            Err(e) => defmt::error!("Air sensor boot failed, {:?}", e),
+            Ok(()) => defmt::info!("I2C sensors booted"),
+            Err(e) => defmt::error!("I2C sensors boot failed, {:?}", e),
+        }
+        let _ = self.air.off().await;
+
+        match self.soil_temperature.verify().await {
+            Ok(()) => defmt::info!("Soil temperature sensors booted"),
+            Err(e) => defmt::error!("Soil temperature sensors boot failed, {:?}", e),
+        }
+
+        let max_payload = self.radio.max_payload();
+        if self.data.capacity() > max_payload {
+            defmt::info!(
+                "Telemetry might take up to {=usize} bytes, it is split into uplinks of {=usize} bytes at the configured data rate",
+                self.data.capacity(),
+                max_payload
+            );
         }
 
         Ok(())
     }
 
+    /// Builds EUI-64 from the configured prefix followed by the flash unique id,
+    /// unique id bytes not fitting behind the prefix are folded in to keep the EUI distinct
+    fn derive_dev_eui(unique_id: u64) -> [u8; 8] {
+        let prefix = config::Config::DEV_EUI_PREFIX;
+        let mut eui = [0u8; 8]; // msb first, as displayed by network servers
+
+        eui[..prefix.len()].copy_from_slice(prefix);
+        for (i, byte) in unique_id.to_be_bytes().iter().enumerate() {
+            eui[prefix.len() + i % (8 - prefix.len())] ^= byte;
+        }
+
+        // LoRaWAN transmits EUIs lsb first, as does `Config::DEV_EUI`
+        eui.reverse();
+        eui
+    }
+
+    fn log_dev_eui(origin: &str, dev_eui: [u8; 8]) {
+        let mut msb_first = dev_eui;
+        msb_first.reverse();
+        defmt::info!("DevEUI {=str} {=[u8]:02x}", origin, msb_first);
+    }
+
     pub async fn auth(&mut self) -> Result<(), DeviceError> {
-        if let Some(keys) = self.get_session_keys().await {
+        let session = if self.force_otaa { None } else { self.get_session().await };
+
+        if let Some(session) = session {
             defmt::info!("Device was already authenticated - joining via ABP method");
 
-            match self
-                .radio
-                .join(&lorawan_device::JoinMode::ABP {
-                    nwkskey: keys.0,
-                    appskey: keys.1,
-                    devaddr: keys.2,
-                })
-                .await
-            {
+            match self.radio.join(&session.abp()).await {
                 Ok(_) => {
                     defmt::info!("ABP authentication ok");
                     Ok(())
@@ -171,22 +500,31 @@ where
         } else {
             defmt::info!("Device was not authenticated - joining via OTAA method");
 
+            let Some(dev_eui) = self.dev_eui else {
+                return Err(DeviceError::DevEui);
+            };
+
             match self
                 .radio
                 .join(&lorawan_device::JoinMode::OTAA {
-                    deveui: DevEui::from(config::Config::DEV_EUI),
+                    deveui: DevEui::from(dev_eui),
                     appeui: AppEui::from(config::Config::APP_EUI),
                     appkey: AppKey::from(config::Config::APP_KEY),
                 })
                 .await
             {
-                Ok(keys) => {
+                Ok(session) => {
                     defmt::info!("OTAA authentication ok");
+                    self.force_otaa = false;
 
-                    match self.persist_session_keys(keys).await {
-                        Ok(_) => Ok(()),
-                        Err(_) => todo!(),
+                    if let Some(session) = session {
+                        // joined session stays valid until reset, next boot joins via OTAA method again
+                        if let Err(e) = self.persist_session(&session).await {
+                            defmt::error!("Session persist failed, {:?}", e);
+                        }
                     }
+
+                    Ok(())
                 }
                 Err(e) => {
                     defmt::error!("OTAA authentication failed {:?}", e);
@@ -203,33 +541,340 @@ where
         }
     }
 
+    /// Telemetry not fitting the current data rate is split at record boundaries, the first frame carrying
+    /// the system records is confirmed if due and the remaining ones follow unconfirmed
     pub async fn uplink(&mut self) -> Result<(), DeviceError> {
-        let data: &[u8] = self.data.as_ref();
+        let max_payload = self.radio.max_payload();
+        let Some(mut end) = cayenne::frame_len(&self.data, max_payload) else {
+            defmt::error!(
+                "Telemetry does not fit an uplink of {=usize} bytes at the current data rate",
+                max_payload
+            );
+            return Err(DeviceError::Send);
+        };
+        let data = &self.data[..end];
+        let alarm = self.system.alarm();
+        let heartbeat = self.is_heartbeat_due();
+        let options = if alarm || heartbeat {
+            UplinkOptions::confirmed(config::Config::FPORT_TELEMETRY)
+        } else {
+            UplinkOptions::unconfirmed(config::Config::FPORT_TELEMETRY)
+        };
+
+        defmt::info!("Sending uplink message {:?} with payload {=[u8]:#x}", options, data);
 
-        defmt::info!("Sending uplink message with payload {=[u8]:#x}", data);
+        let result = self.radio.uplink(&options, data).await;
+        self.uplinks = self.uplinks.wrapping_add(1);
+        let interval = config::Config::LINK_CHECK_INTERVAL;
+        if interval != 0 && self.uplinks % interval == 0 {
+            self.link_check_pending = true;
+        }
+
+        // alarm stays raised until a confirmed uplink carrying it is acknowledged, the next cycle sends it again otherwise
+        if alarm && matches!(&result, Ok(uplink) if uplink.acked) {
+            self.system.clear_alarm();
+        }
+
+        if heartbeat {
+            match &result {
+                Ok(_) => self.missed_heartbeats = 0,
+                Err(e) if e.is_no_ack() => {
+                    self.missed_heartbeats += 1;
+                    defmt::warn!("Heartbeat not acknowledged, {=u8} in a row", self.missed_heartbeats);
+
+                    if self.missed_heartbeats >= config::Config::HEARTBEAT_MISSED_LIMIT {
+                        defmt::error!("LoRaWAN link lost, rejoining via OTAA method");
+                        self.missed_heartbeats = 0;
+                        self.force_otaa = true;
+                        return Err(DeviceError::LinkLost);
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+
+        let result = self.uplink_result(result);
+        if matches!(result, Err(DeviceError::SessionExpired)) {
+            return result;
+        }
+
+        while end < self.data.len() {
+            let start = end;
+            let Some(len) = cayenne::frame_len(&self.data[start..], max_payload) else {
+                return Err(DeviceError::Send);
+            };
+            end += len;
+            let options = UplinkOptions::unconfirmed(config::Config::FPORT_TELEMETRY);
+
+            defmt::info!(
+                "Sending uplink message {:?} with payload {=[u8]:#x}",
+                options,
+                &self.data[start..end]
+            );
+
+            let next = self.radio.uplink(&options, &self.data[start..end]).await;
+            self.uplink_result(next)?;
+        }
+
+        result
+    }
+
+    fn is_heartbeat_due(&self) -> bool {
+        let interval = config::Config::HEARTBEAT_INTERVAL;
+        interval != 0 && self.uplinks % interval == 0
+    }
+
+    pub async fn uplink_device_info(&mut self) -> Result<(), DeviceError> {
+        let mut info: Vec<u8, 34> = Vec::new();
+
+        if self.air.detected() != 0 {
+            info.push(0x00).unwrap(); // channel - 0 [i2c bus]
+            info.push(self.air.detected()).unwrap(); // bitmask of discovered chips
+        }
+
+        if let Some(serial_number) = self.air.serial_number() {
+            info.push(0x01).unwrap(); // channel - 1 [air_sensor]
+            info.extend_from_slice(&serial_number.to_be_bytes()[2..]).unwrap(); // 48 bit serial number
+        }
+
+        if let Some(unique_id) = self.system.serial_number() {
+            info.push(0x02).unwrap(); // channel - 2 [rp2040]
+            info.extend_from_slice(&unique_id.to_be_bytes()).unwrap(); // 64 bit flash unique id
+        }
+
+        let (major, minor, patch) = firmware_version();
+        info.push(0x03).unwrap(); // channel - 3 [firmware]
+        info.extend_from_slice(&[major, minor, patch]).unwrap(); // semantic version
+        info.extend_from_slice(&firmware_git_hash().to_be_bytes()).unwrap(); // 32 bit git hash
+        info.extend_from_slice(&firmware_build_timestamp().to_be_bytes()).unwrap(); // unix seconds
+
+        if let Some(link) = self.link {
+            info.push(0x04).unwrap(); // channel - 4 [link quality]
+            info.extend_from_slice(&link.rssi.to_be_bytes()).unwrap(); // dBm of the last downlink
+            info.extend_from_slice(&link.snr.to_be_bytes()).unwrap(); // dB of the last downlink
+        }
+
+        defmt::info!("Sending device info message with payload {=[u8]:#x}", info.as_slice());
+
+        self.info_requested = false;
+        let options = UplinkOptions::confirmed(config::Config::FPORT_DEVICE_INFO);
+        let result = self.radio.uplink(&options, &info).await;
+        let result = self.uplink_result(result);
+
+        if result.is_ok() && self.firmware_pending {
+            match self.firmware.confirm() {
+                Ok(()) => {
+                    defmt::info!("Updated firmware confirmed");
+                    self.firmware_pending = false;
+                }
+                Err(e) => defmt::error!("Firmware confirmation failed, {:?}", e),
+            }
+        }
+
+        result
+    }
+
+    fn uplink_result(&mut self, result: Result<Uplink, R::Error>) -> Result<(), DeviceError> {
+        match result {
+            Ok(uplink) => {
+                defmt::info!(
+                    "Sent uplink at DR{=u8}, acked {=bool}, downlink fcount {:?}",
+                    uplink.data_rate,
+                    uplink.acked,
+                    uplink.fcnt_down
+                );
+
+                self.tx_settings = Some((uplink.data_rate, uplink.tx_power));
+
+                if let Some(quality) = uplink.quality {
+                    defmt::info!("Downlink rssi {=i16} dBm, snr {=i8} dB", quality.rssi, quality.snr);
+                    self.link = Some(quality);
+                }
+
+                if let Some(downlink) = uplink.downlink {
+                    self.handle_downlink(&downlink);
+                }
 
-        match self.radio.uplink(data).await {
-            Ok(fcnt_down) => {
-                defmt::info!("Sent uplink, received downlink with fcount {=u32}", fcnt_down);
                 Ok(())
             }
-            Err(LoraRadioError::SessionExpired) => {
+            Err(e) if e.is_session_expired() => {
                 defmt::error!("LoRaWAN session expired, re-authenticating");
                 Err(DeviceError::SessionExpired)
             }
-            Err(LoraRadioError::NoAck) => {
+            Err(e) if e.is_no_ack() => {
                 defmt::error!("No acknoledgement received");
                 // todo: is it worth retrying? might be expensive on power
                 Err(DeviceError::NoAck)
             }
-            Err(_) => {
-                defmt::error!("Failed to send uplink");
+            Err(e) => {
+                defmt::error!("Failed to send uplink, {:?}", e);
                 Err(DeviceError::Send)
             }
         }
     }
 
-    async fn get_session_keys(&mut self) -> Option<(lorawan_device::NewSKey, lorawan_device::AppSKey, lorawan_device::DevAddr<[u8; 4]>)> {
+    fn is_clock_sync_due(&self) -> bool {
+        let interval = config::Config::CLOCK_SYNC_INTERVAL;
+        self.clock_sync_requested || self.package_version_requested || (interval != 0 && self.uplinks % interval == 0)
+    }
+
+    fn is_link_check_due(&self) -> bool {
+        self.link_check_pending
+    }
+
+    /// Escalates missed link checks, first by stepping the data rate down, then by a forced OTAA rejoin
+    pub async fn check_link(&mut self) -> Result<(), DeviceError> {
+        defmt::info!("Checking LoRaWAN link");
+        self.link_check_pending = false;
+
+        match self.radio.link_check().await {
+            Ok(Some(check)) => {
+                defmt::info!("Link check answered {:?}", check);
+                self.link_check = Some(check);
+                self.link_margin_min = Some(self.link_margin_min.map_or(check.margin, |min| min.min(check.margin)));
+                self.missed_link_checks = 0;
+                Ok(())
+            }
+            Ok(None) => {
+                self.missed_link_checks += 1;
+                defmt::warn!("Link check not answered, {=u8} in a row", self.missed_link_checks);
+
+                if self.missed_link_checks >= config::Config::LINK_CHECK_REJOIN_LIMIT {
+                    defmt::error!("LoRaWAN link lost, rejoining via OTAA method");
+                    self.missed_link_checks = 0;
+                    self.force_otaa = true;
+                    return Err(DeviceError::LinkLost);
+                }
+
+                if self.missed_link_checks >= config::Config::LINK_CHECK_DR_STEP_DOWN_LIMIT {
+                    if let Some((data_rate @ 1.., _)) = self.tx_settings {
+                        defmt::warn!("Stepping data rate down from DR{=u8}", data_rate);
+                        self.radio.set_data_rate(data_rate - 1);
+                    }
+                }
+
+                Ok(())
+            }
+            Err(e) => self.uplink_result(Err(e)),
+        }
+    }
+
+    pub async fn sync_clock(&mut self) -> Result<(), DeviceError> {
+        if self.package_version_requested {
+            self.package_version_requested = false;
+            let options = UplinkOptions::unconfirmed(clock_sync::FPORT);
+            let result = self.radio.uplink(&options, &clock_sync::package_version_ans()).await;
+            self.uplink_result(result)?;
+        }
+
+        self.clock_sync_requested = false;
+        self.clock_sync_token = (self.clock_sync_token + 1) & 0x0f;
+        self.clock_sync_pending = true;
+        let device_time = clock_sync::unix_to_gps(self.clock.device_time());
+        defmt::info!("Requesting clock sync, device time {=u32} GPS seconds", device_time);
+
+        match self.radio.sync_time(device_time, self.clock_sync_token).await {
+            Ok(Some(correction)) => {
+                self.clock_sync_pending = false;
+                self.clock.adjust(correction);
+                defmt::info!("Clock corrected by {=i32}s, unix time {:?}", correction, self.clock.now());
+                Ok(())
+            }
+            Ok(None) => {
+                defmt::warn!("No clock sync answer received");
+                Ok(())
+            }
+            Err(e) => self.uplink_result(Err(e)),
+        }
+    }
+
+    fn handle_downlink(&mut self, downlink: &Downlink) {
+        defmt::info!(
+            "Received downlink on port {=u8} with payload {=[u8]:#x}",
+            downlink.port,
+            downlink.payload.as_slice()
+        );
+
+        if downlink.port == clock_sync::FPORT {
+            match clock_sync::parse(&downlink.payload) {
+                Some(ClockSyncCommand::PackageVersionReq) => self.package_version_requested = true,
+                Some(ClockSyncCommand::ForceDeviceResyncReq { .. }) => self.clock_sync_requested = true,
+                Some(ClockSyncCommand::AppTimeAns { correction, token }) if self.clock_sync_pending && token == self.clock_sync_token => {
+                    self.clock_sync_pending = false;
+                    self.clock.adjust(correction);
+                    defmt::info!("Clock corrected by {=i32}s, unix time {:?}", correction, self.clock.now());
+                }
+                Some(ClockSyncCommand::AppTimeAns { token, .. }) => {
+                    defmt::warn!("Ignoring clock sync answer with stale token {=u8}", token)
+                }
+                None => defmt::warn!("Unknown clock sync command"),
+            }
+            return;
+        }
+
+        if downlink.port == frag::FPORT {
+            self.fuota.handle_fragmentation(&downlink.payload, &mut self.firmware);
+            return;
+        }
+
+        if downlink.port == multicast::FPORT {
+            let now = self.clock.now().map(clock_sync::unix_to_gps);
+            self.fuota.handle_multicast(&downlink.payload, &mut self.radio, now);
+            return;
+        }
+
+        if downlink.port != config::Config::FPORT_COMMAND {
+            return;
+        }
+
+        match downlink.payload.as_slice() {
+            [0x01] => {
+                defmt::info!("Device info requested by the network");
+                self.info_requested = true;
+            }
+            [0x02, data_rate] => {
+                defmt::info!("Data rate DR{=u8} requested by the network", data_rate);
+                self.radio.set_data_rate(*data_rate);
+            }
+            [0x03, dbm] => {
+                defmt::info!("Transmission power capped to {=i8} dBm by the network", *dbm as i8);
+                self.radio.set_tx_power(*dbm as i8);
+            }
+            [0x04, adr] => {
+                defmt::info!("ADR {=bool} by the network", *adr != 0);
+                self.radio.set_adr(*adr != 0);
+            }
+            [0x05, probe, point @ ..] if usize::from(*probe) < self.soil.probes() => {
+                let point = match point {
+                    [0x01] => CalibrationPoint::Dry,
+                    [0x02] => CalibrationPoint::Wet,
+                    [0x03, percentage] if *percentage <= 100 => CalibrationPoint::Reference(*percentage),
+                    _ => {
+                        defmt::warn!("Unknown calibration point {=[u8]:#x}", point);
+                        return;
+                    }
+                };
+                defmt::info!("Soil probe {=u8} calibration at {:?} requested by the network", probe, point);
+                self.calibration_requested = Some((usize::from(*probe), point));
+            }
+            [0x06, operation] => {
+                let operation = match operation {
+                    0x01 => Maintenance::FactoryReset,
+                    0x02 => Maintenance::Reinit,
+                    _ => {
+                        defmt::warn!("Unknown air sensor maintenance {=u8:#x}", operation);
+                        return;
+                    }
+                };
+                defmt::info!("Air sensor {:?} requested by the network", operation);
+                self.maintenance_requested = Some(operation);
+            }
+            [command, ..] => defmt::warn!("Unknown downlink command {=u8:#x}", command),
+            [] => {}
+        }
+    }
+
+    async fn get_session(&mut self) -> Option<Session> {
         defmt::info!("Reading LoRaWAN session keys");
 
         let mut apps_key_buf = [0u8; 16];
@@ -237,50 +882,69 @@ where
             .storage
             .get(&Key::AppSKey, &mut apps_key_buf)
             .await
-            .map(|_size| AppSKey::from(apps_key_buf));
+            .map(|_size| Secret::new(apps_key_buf));
 
         let mut news_key_buf = [0u8; 16];
         let news_key = self
             .storage
             .get(&Key::NewSKey, &mut news_key_buf)
             .await
-            .map(|_size| NewSKey::from(news_key_buf));
+            .map(|_size| Secret::new(news_key_buf));
 
         let mut dev_addr_buf = [0u8; 4];
-        let dev_addr = self
-            .storage
-            .get(&Key::DevAddr, &mut dev_addr_buf)
-            .await
-            .map(|_size| DevAddr::from(dev_addr_buf));
+        let dev_addr = self.storage.get(&Key::DevAddr, &mut dev_addr_buf).await.map(|_size| dev_addr_buf);
 
-        if let (Some(news_key), Some(apps_key), Some(dev_addr)) = (news_key, apps_key, dev_addr) {
-            Some((news_key, apps_key, dev_addr))
+        if let (Some(nwkskey), Some(appskey), Some(devaddr)) = (news_key, apps_key, dev_addr) {
+            Some(Session { nwkskey, appskey, devaddr })
         } else {
             None
         }
     }
 
-    async fn persist_session_keys(
-        &mut self,
-        keys: (lorawan_device::NewSKey, lorawan_device::AppSKey, lorawan_device::DevAddr<[u8; 4]>),
-    ) -> Result<(), DeviceError> {
+    async fn persist_session(&mut self, session: &Session) -> Result<(), DeviceError> {
         defmt::info!("Persisting LoRaWAN session keys");
+        defmt::debug!("Session {:?}", session);
 
-        let news_key = keys.0.as_ref();
-        defmt::debug!("NewSKey {=[u8]}", news_key);
-        if let Err(e) = self.storage.put(&Key::NewSKey, news_key).await {
+        if let Err(e) = self.storage.put(&Key::NewSKey, session.nwkskey.expose()).await {
             return Err(DeviceError::Storage(e));
         }
 
-        let apps_key = keys.1.as_ref();
-        defmt::debug!("AppSKey {=[u8]}", apps_key);
-        if let Err(e) = self.storage.put(&Key::AppSKey, apps_key).await {
+        if let Err(e) = self.storage.put(&Key::AppSKey, session.appskey.expose()).await {
             return Err(DeviceError::Storage(e));
         }
 
-        let dev_addr = keys.2.as_ref();
-        defmt::debug!("DevAddr {=[u8]}", dev_addr);
-        if let Err(e) = self.storage.put(&Key::DevAddr, dev_addr).await {
+        if let Err(e) = self.storage.put(&Key::DevAddr, &session.devaddr).await {
+            return Err(DeviceError::Storage(e));
+        }
+
+        Ok(())
+    }
+
+    /// Persists the frame sequence ahead of the radio, point-to-point frames keep moving forward across resets
+    async fn reserve_sequence(&mut self) {
+        let Some(sequence) = self.radio.sequence_reservation() else {
+            return;
+        };
+
+        match self.storage.put(&Key::Sequence, &sequence.to_le_bytes()).await {
+            Ok(()) => self.radio.reserve_sequence(sequence),
+            Err(e) => defmt::error!("Frame sequence persist failed, {:?}", e),
+        }
+    }
+
+    pub async fn calibrate_soil(&mut self, probe: usize, point: CalibrationPoint) -> Result<(), DeviceError> {
+        let _ = self.soil.on().await;
+        let result = self.soil.calibrate(&mut self.adc, probe, point).await;
+        let _ = self.soil.off().await;
+
+        if let Err(e) = result {
+            defmt::error!("Soil sensor calibration failed {:?}", e);
+            return Err(DeviceError::Calibration);
+        }
+
+        let mut calibration = [0u8; SoilCalibration::SIZE];
+        let size = self.soil.calibration(probe, &mut calibration);
+        if let Err(e) = self.storage.put(&Key::SoilCalibration(probe as u8), &calibration[..size]).await {
             return Err(DeviceError::Storage(e));
         }
 
@@ -288,6 +952,14 @@ where
     }
 
     pub async fn collect_data(&mut self) -> Result<(), DeviceError> {
+        // probe is sampled before the telemetry, it has to sit in the reference medium by now
+        if let Some((probe, point)) = self.calibration_requested.take() {
+            match self.calibrate_soil(probe, point).await {
+                Ok(()) => defmt::info!("Soil probe {=usize} calibrated at {:?}", probe, point),
+                Err(e) => defmt::error!("Soil probe {=usize} calibration failed, {:?}", probe, e),
+            }
+        }
+
         self.data.clear();
 
         match self.system.probe(&mut self.adc).await {
@@ -308,18 +980,77 @@ where
         }
         let _ = self.soil.off().await;
 
-        // todo: there is a bug with air sensor
-        // after wake up it might not send ack during i2c communication and result in error
-        // hence for now air sensor will always be powered
-        // let _ = self.air.on().await;
-        match self.air.probe(&mut self.adc).await {
+        if let Err(e) = self.air.on().await {
+            defmt::error!("I2C sensors wake up failed {:?}", e);
+            return Err(DeviceError::Duty);
+        }
+        if let Some(operation) = self.maintenance_requested.take() {
+            match self.air.maintain(operation).await {
+                Ok(()) => defmt::info!("Air sensor {:?} done", operation),
+                Err(e) => defmt::error!("Air sensor {:?} failed, {:?}", operation, e),
+            }
+        }
+        let probe = self.air.probe(&mut self.adc).await;
+        let _ = self.air.off().await;
+        match probe {
+            Ok(probe_data) => self.data.extend_from_slice(&probe_data).unwrap(),
+            Err(e) => {
+                defmt::error!("I2C sensors probe failed {:?}", e);
+                return Err(DeviceError::Duty);
+            }
+        }
+
+        match self.soil_temperature.probe(&mut self.adc).await {
             Ok(probe_data) => self.data.extend_from_slice(&probe_data).unwrap(),
             Err(e) => {
-                defmt::error!("Air sensor probe failed {:?}", e);
+                defmt::error!("Soil temperature sensors probe failed {:?}", e);
                 return Err(DeviceError::Duty);
             }
         }
-        // let _ = self.air.off().await;
+
+        if let (Some(now), Some(time_of_day)) = (self.clock.now(), self.clock.time_of_day()) {
+            defmt::info!("Measured at unix time {=u32}, {=u32}s into the day", now, time_of_day);
+
+            let mut buf = [0u8; 6];
+            buf[0] = 0x0b; // channel    - 11 [clock]
+            buf[1] = 0x85; // type       - unix time [4 bytes]
+            buf[2..].copy_from_slice(&now.to_be_bytes()); //            - four bytes
+            self.data.extend_from_slice(&buf).unwrap();
+        }
+
+        if let Some(check) = self.link_check {
+            let margin_scl = check.margin as i16 * 100;
+            let margin_min_scl = self.link_margin_min.unwrap_or(check.margin) as i16 * 100;
+            self.link_margin_min = None;
+
+            let mut buf = [0u8; 11];
+            buf[0] = 0x0c; // channel    - 12 [link check]
+            buf[1] = 0x02; // type       - analog input [2 bytes]
+            buf[2] = (margin_scl >> 8) as u8; //            - first byte
+            buf[3] = margin_scl as u8; //            - second byte
+            buf[4] = 0x0d; // channel    - 13 [link check]
+            buf[5] = 0x02; // type       - analog input [2 bytes]
+            buf[6] = (margin_min_scl >> 8) as u8; //            - first byte
+            buf[7] = margin_min_scl as u8; //            - second byte
+            buf[8] = 0x0c; // channel    - 12 [link check]
+            buf[9] = 0x00; // type       - digital input [1 bytes]
+            buf[10] = check.gateways; //            - first byte
+            self.data.extend_from_slice(&buf).unwrap();
+        }
+
+        if let Some((data_rate, tx_power)) = self.tx_settings {
+            let tx_power_scl = tx_power.unwrap_or(0) as i16 * 100;
+
+            let mut buf = [0u8; 7];
+            buf[0] = 0x0a; // channel    - 10 [radio]
+            buf[1] = 0x00; // type       - digital input [1 bytes]
+            buf[2] = data_rate; //            - first byte
+            buf[3] = 0x0a; // channel    - 10 [radio]
+            buf[4] = 0x02; // type       - analog input [2 bytes]
+            buf[5] = (tx_power_scl >> 8) as u8; //            - first byte
+            buf[6] = tx_power_scl as u8; //            - second byte
+            self.data.extend_from_slice(&buf).unwrap();
+        }
 
         Ok(())
     }
@@ -0,0 +1,117 @@
+use embassy_boot_rp::{BlockingFirmwareUpdater, FirmwareUpdaterConfig, FirmwareUpdaterError, State};
+use embassy_embedded_hal::flash::partition::{self, BlockingPartition};
+use embassy_rp::flash::{Blocking, Flash, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_time::{Duration, Instant};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use static_cell::StaticCell;
+
+use crate::clock::rtc_clock::SharedWatchdog;
+use crate::config;
+use crate::firmware::{Firmware, SIGNATURE_SIZE};
+use crate::storage::flash_storage::{SharedFlash, FLASH_SIZE};
+
+type BootFlash = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+type Partition = BlockingPartition<'static, NoopRawMutex, BootFlash>;
+
+static ALIGNED: StaticCell<[u8; 1]> = StaticCell::new(); // state partition is written a byte at a time
+
+#[derive(defmt::Format)]
+pub enum BootFirmwareError {
+    Flash(partition::Error<embassy_rp::flash::Error>),
+    Updater(FirmwareUpdaterError),
+    Size, // image does not fit the update partition or lacks the signature
+}
+
+/// Firmware slots of embassy-boot, partitions are taken from `memory.x`.
+/// Update partition is one erase sector larger than the active one, the bootloader swaps thru it.
+pub struct BootFirmware {
+    updater: BlockingFirmwareUpdater<'static, Partition, Partition>,
+    dfu: Partition,
+    watchdog: &'static SharedWatchdog,
+}
+
+impl BootFirmware {
+    pub fn new(flash: &'static SharedFlash, watchdog: &'static SharedWatchdog) -> Self {
+        // updater keeps its own view of the update partition, fragments are written thru a second one
+        let dfu = FirmwareUpdaterConfig::from_linkerfile_blocking(flash, flash).dfu;
+        let config = FirmwareUpdaterConfig::from_linkerfile_blocking(flash, flash);
+        let updater = BlockingFirmwareUpdater::new(config, ALIGNED.init([0; 1]));
+
+        Self { updater, dfu, watchdog }
+    }
+}
+
+impl Firmware for BootFirmware {
+    type Error = BootFirmwareError;
+
+    fn is_pending(&mut self) -> bool {
+        matches!(self.updater.get_state(), Ok(State::Swap))
+    }
+
+    fn confirm(&mut self) -> Result<(), Self::Error> {
+        self.updater.mark_booted().map_err(BootFirmwareError::Updater)
+    }
+
+    fn capacity(&self) -> usize {
+        self.dfu.size() as usize - ERASE_SIZE
+    }
+
+    // Sector by sector, a whole partition takes seconds and the watchdog has to be fed meanwhile
+    async fn erase(&mut self, size: usize) -> Result<(), Self::Error> {
+        if size > self.capacity() {
+            return Err(BootFirmwareError::Size);
+        }
+
+        for offset in (0..size.next_multiple_of(ERASE_SIZE)).step_by(ERASE_SIZE) {
+            self.dfu
+                .erase(offset as u32, (offset + ERASE_SIZE) as u32)
+                .map_err(BootFirmwareError::Flash)?;
+            embassy_futures::yield_now().await;
+        }
+
+        Ok(())
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.dfu.read(offset, buf).map_err(BootFirmwareError::Flash)
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error> {
+        self.dfu.write(offset, data).map_err(BootFirmwareError::Flash)
+    }
+
+    fn apply(&mut self, size: usize) -> Result<(), Self::Error> {
+        let Some(image_size) = size.checked_sub(SIGNATURE_SIZE).filter(|_| size <= self.capacity()) else {
+            return Err(BootFirmwareError::Size);
+        };
+
+        let mut signature = [0u8; SIGNATURE_SIZE];
+        self.read(image_size as u32, &mut signature)?;
+
+        // hashing the whole image blocks the executor, the feeding task included, and its duration on hardware
+        // is not measured yet, hence the watchdog is stopped meanwhile rather than risking a reset mid update
+        self.watchdog.lock(|watchdog| watchdog.borrow_mut().stop());
+        let start = Instant::now();
+        let result = self
+            .updater
+            .verify_and_mark_updated(&config::Config::FIRMWARE_PUBLIC_KEY, &signature, image_size as u32);
+        self.watchdog.lock(|watchdog| {
+            watchdog
+                .borrow_mut()
+                .start(Duration::from_millis(config::Config::WATCHDOG_TIMEOUT_MS))
+        });
+        defmt::info!(
+            "Signature of the {=usize} bytes image checked in {=u64}ms",
+            image_size,
+            start.elapsed().as_millis()
+        );
+
+        result.map_err(BootFirmwareError::Updater)
+    }
+
+    fn reset(&mut self) -> ! {
+        cortex_m::peripheral::SCB::sys_reset()
+    }
+}
@@ -0,0 +1,233 @@
+use embassy_time::{Duration, Instant};
+use heapless::{Deque, Vec};
+use lora_fuota::decoder::{Decoder, FragmentStore, Progress};
+use lora_fuota::frag::{self, SessionSetup};
+use lora_fuota::multicast::{self, McGroup, MAX_GROUPS};
+
+use crate::config;
+use crate::firmware::Firmware;
+use crate::radio::Radio;
+
+const ANSWER_SIZE: usize = 2 + 5 * MAX_GROUPS; // largest answer, `McGroupStatusAns` listing all groups
+const ANSWER_QUEUE: usize = 8;
+
+/// Answer of a package command waiting for an uplink, port and payload
+pub type Answer = (u8, Vec<u8, ANSWER_SIZE>);
+
+/// Update partition seen as the store fragments are reassembled into
+struct Dfu<'a, F>(&'a mut F);
+
+impl<F: Firmware> FragmentStore for Dfu<'_, F> {
+    type Error = F::Error;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.read(offset, buf)
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error> {
+        self.0.write(offset, data)
+    }
+}
+
+/// Firmware update over the air, multicast groups and their Class C sessions are set up thru TS005,
+/// the image is sent to them as a TS004 data block of signed firmware. A single fragmentation session
+/// is supported, index 0.
+pub struct Fuota {
+    decoder: Decoder<{ config::Config::FRAGMENT_SIZE_MAX }, { config::Config::FRAGMENT_LOST_MAX }>,
+    session: Option<SessionSetup>,
+    erase_pending: bool,                // partition is erased before fragments are accepted
+    groups: Vec<(u8, u32), MAX_GROUPS>, // id and address of the multicast groups
+    window: Option<(Instant, Instant)>, // start and end of the scheduled Class C session
+    answers: Deque<Answer, ANSWER_QUEUE>,
+}
+
+impl Fuota {
+    pub const fn new() -> Self {
+        Self {
+            decoder: Decoder::new(),
+            session: None,
+            erase_pending: false,
+            groups: Vec::new(),
+            window: None,
+            answers: Deque::new(),
+        }
+    }
+
+    /// Handles a downlink received on `frag::FPORT`
+    pub fn handle_fragmentation<F: Firmware>(&mut self, payload: &[u8], firmware: &mut F) {
+        for command in frag::parse(payload) {
+            match command {
+                Ok(frag::Command::PackageVersionReq) => self.answer(frag::FPORT, &frag::package_version_ans()),
+                Ok(frag::Command::FragSessionSetupReq(setup)) => {
+                    let status = self.setup_session(&setup, firmware.capacity());
+                    self.answer(frag::FPORT, &frag::session_setup_ans(setup.index, status));
+                }
+                Ok(frag::Command::FragSessionDeleteReq { index }) => {
+                    let exists = index == 0 && self.session.take().is_some();
+                    self.decoder.reset();
+                    self.answer(frag::FPORT, &frag::session_delete_ans(index, exists));
+                }
+                // devices which got the whole block answer only when all participants are asked to
+                Ok(frag::Command::FragSessionStatusReq { index, participants }) => {
+                    if index == 0 && self.session.is_some() && (participants || self.decoder.missing() > 0) {
+                        let status = frag::session_status_ans(
+                            index,
+                            self.decoder.fragments(),
+                            self.decoder.missing(),
+                            self.decoder.not_enough_memory(),
+                        );
+                        self.answer(frag::FPORT, &status);
+                    }
+                }
+                Ok(frag::Command::DataFragment { index, n, data }) => {
+                    if index == 0 && self.session.is_some() && !self.erase_pending {
+                        self.receive_fragment(n, data, firmware);
+                    }
+                }
+                Err(e) => defmt::warn!("Invalid fragmentation command, {:?}", e),
+            }
+        }
+    }
+
+    fn setup_session(&mut self, setup: &SessionSetup, capacity: usize) -> u8 {
+        defmt::info!("Fragmentation session setup {:?}", setup);
+
+        let mut status = 0;
+        if setup.matrix != frag::MATRIX_PARITY {
+            status |= frag::SETUP_ENCODING_UNSUPPORTED;
+        }
+        if setup.index != 0 {
+            status |= frag::SETUP_INDEX_UNSUPPORTED;
+        }
+        if setup.nb_frag as usize * setup.frag_size as usize > capacity {
+            status |= frag::SETUP_NOT_ENOUGH_MEMORY;
+        }
+        if status == 0 && self.decoder.setup(setup.nb_frag, setup.frag_size).is_err() {
+            status |= frag::SETUP_NOT_ENOUGH_MEMORY;
+        }
+
+        if status == 0 {
+            self.session = Some(*setup);
+            self.erase_pending = true;
+        }
+        status
+    }
+
+    fn receive_fragment<F: Firmware>(&mut self, n: u16, data: &[u8], firmware: &mut F) {
+        match self.decoder.push(&mut Dfu(firmware), n, data) {
+            Ok(Progress::Complete) => defmt::debug!("Fragment {=u16} received, data block complete", n),
+            Ok(Progress::Pending) => defmt::debug!("Fragment {=u16} received, {=u16} missing", n, self.decoder.missing()),
+            Err(e) => defmt::warn!("Fragment {=u16} dropped, {:?}", n, e),
+        }
+    }
+
+    /// Handles a downlink received on `multicast::FPORT`, `now` is the device time in GPS epoch seconds if synchronized
+    pub fn handle_multicast<R: Radio>(&mut self, payload: &[u8], radio: &mut R, now: Option<u32>) {
+        for command in multicast::parse(payload) {
+            match command {
+                Ok(multicast::Command::PackageVersionReq) => self.answer(multicast::FPORT, &multicast::package_version_ans()),
+                Ok(multicast::Command::McGroupStatusReq { mask }) => {
+                    let status = multicast::group_status_ans(mask, &self.groups);
+                    self.answer(multicast::FPORT, &status);
+                }
+                Ok(multicast::Command::McGroupSetupReq(setup)) => {
+                    defmt::info!("Multicast group {=u8} set up, address {=u32:#x}", setup.id, setup.addr);
+                    self.groups.retain(|(id, _)| *id != setup.id);
+                    let _ = self.groups.push((setup.id, setup.addr));
+                    radio.set_multicast_group(setup.id, Some(McGroup::new(&config::Config::GEN_APP_KEY, &setup)));
+                    self.answer(multicast::FPORT, &multicast::group_setup_ans(setup.id, false));
+                }
+                Ok(multicast::Command::McGroupDeleteReq { id }) => {
+                    let defined = self.groups.iter().any(|(group, _)| *group == id);
+                    self.groups.retain(|(group, _)| *group != id);
+                    radio.set_multicast_group(id, None);
+                    self.answer(multicast::FPORT, &multicast::group_delete_ans(id, !defined));
+                }
+                Ok(multicast::Command::McClassCSessionReq(session)) => {
+                    let mut status = 0;
+                    let channel = radio.class_c_channel();
+                    if !self.groups.iter().any(|(group, _)| *group == session.id) {
+                        status |= multicast::SESSION_GROUP_UNDEFINED;
+                    }
+                    if channel.map(|(frequency, _)| frequency) != Some(session.frequency) {
+                        status |= multicast::SESSION_FREQUENCY_ERROR;
+                    }
+                    if channel.map(|(_, data_rate)| data_rate) != Some(session.data_rate) {
+                        status |= multicast::SESSION_DR_ERROR;
+                    }
+
+                    // without a synchronized clock the session starts right away
+                    let time_to_start = now.map_or(0, |now| session.session_time.saturating_sub(now));
+                    if status == 0 {
+                        let start = Instant::now() + Duration::from_secs(time_to_start as u64);
+                        self.window = Some((start, start + Duration::from_secs(session.duration_secs() as u64)));
+                        defmt::info!("Multicast session {:?} starts in {=u32}s", session, time_to_start);
+                    } else {
+                        defmt::warn!("Multicast session {:?} refused, status {=u8:#x}", session, status);
+                    }
+                    self.answer(multicast::FPORT, &multicast::class_c_session_ans(session.id, status, time_to_start));
+                }
+                Ok(multicast::Command::McClassBSessionReq { id }) => defmt::warn!("Class B session of group {=u8} is not supported", id),
+                Err(e) => defmt::warn!("Invalid multicast command, {:?}", e),
+            }
+        }
+    }
+
+    fn answer(&mut self, port: u8, payload: &[u8]) {
+        let Ok(payload) = Vec::from_slice(payload) else {
+            return;
+        };
+        if self.answers.push_back((port, payload)).is_err() {
+            defmt::warn!("Answer queue full, dropping answer on port {=u8}", port);
+        }
+    }
+
+    pub fn take_answer(&mut self) -> Option<Answer> {
+        self.answers.pop_front()
+    }
+
+    /// Erases the update partition for a new session, fragments are dropped until done
+    pub async fn prepare<F: Firmware>(&mut self, firmware: &mut F) -> Result<(), F::Error> {
+        let Some(session) = self.session.filter(|_| self.erase_pending) else {
+            return Ok(());
+        };
+
+        let size = session.nb_frag as usize * session.frag_size as usize;
+        defmt::info!("Erasing {=usize} bytes of the update partition", size);
+        self.erase_pending = false;
+        if let Err(e) = firmware.erase(size).await {
+            self.session = None;
+            self.decoder.reset();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Size of the received data block once complete, returned once per session
+    pub fn take_image(&mut self) -> Option<usize> {
+        if !self.decoder.is_complete() {
+            return None;
+        }
+
+        self.decoder.reset();
+        self.session.take().map(|session| session.block_size())
+    }
+
+    /// Whether a scheduled multicast session is running, it is received in Class C
+    pub fn in_session(&mut self) -> bool {
+        match self.window {
+            Some((_, end)) if Instant::now() >= end => {
+                defmt::info!("Multicast session ended");
+                self.window = None;
+                false
+            }
+            Some((start, _)) => Instant::now() >= start,
+            None => false,
+        }
+    }
+
+    /// Next start or end of a scheduled multicast session
+    pub fn session_change(&self) -> Option<Instant> {
+        self.window.map(|(start, end)| if Instant::now() < start { start } else { end })
+    }
+}
@@ -0,0 +1,36 @@
+pub mod boot_firmware;
+pub mod fuota;
+
+/// Ed25519 signature appended to firmware images, made over the SHA-512 digest of the image
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// Trait to represent the firmware slots of an A/B bootloader, the running image and the update partition
+/// images are received into. A swapped image has to be confirmed, otherwise the bootloader reverts it on next reset.
+pub trait Firmware {
+    /// Error type representation, left up to the implementor
+    type Error: defmt::Format;
+
+    /// Whether the running image was swapped in by the bootloader and still awaits confirmation
+    fn is_pending(&mut self) -> bool;
+
+    /// Confirm the running image, keeps it after the next reset
+    fn confirm(&mut self) -> Result<(), Self::Error>;
+
+    /// Largest image the update partition holds, signature included
+    fn capacity(&self) -> usize;
+
+    /// Erase room for an image of `size` bytes in the update partition
+    async fn erase(&mut self, size: usize) -> Result<(), Self::Error>;
+
+    /// Read from the update partition
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Write to the erased update partition
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Verify signature of the image of `size` bytes in the update partition and mark it for the bootloader
+    fn apply(&mut self, size: usize) -> Result<(), Self::Error>;
+
+    /// Reset into the bootloader, which swaps a marked image in or reverts an unconfirmed one
+    fn reset(&mut self) -> !;
+}
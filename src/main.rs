@@ -1,29 +1,52 @@
 #![no_std]
 #![no_main]
 
+mod bus;
+mod clock;
 mod config;
 mod device;
+mod firmware;
 mod radio;
+mod secret;
 mod sensor;
 mod storage;
 
+use core::cell::RefCell;
+
 use assign_resources::assign_resources;
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
 use embassy_executor::Spawner;
 use embassy_rp::config::Config;
-use embassy_rp::peripherals::{self, I2C0};
+use embassy_rp::flash::Flash;
+use embassy_rp::peripherals::{self, I2C0, PIO0};
+use embassy_rp::watchdog::Watchdog;
 use embassy_rp::{adc, bind_interrupts, Peri};
+use embassy_sync::blocking_mutex;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Timer};
+use static_cell::StaticCell;
 use {defmt_rtt as _, panic_probe as _};
 
+use crate::bus::i2c_bus::I2cBus;
+use crate::bus::one_wire::OneWire;
+use crate::clock::rtc_clock::{RtcClock, SharedWatchdog};
 use crate::device::Device;
+use crate::firmware::boot_firmware::BootFirmware;
+#[cfg(not(feature = "p2p"))]
 use crate::radio::lora_radio::LoraRadio;
-use crate::sensor::air_sensor::AirSensor;
+#[cfg(feature = "p2p")]
+use crate::radio::p2p_radio::P2pRadio;
+use crate::sensor::ds18b20::Ds18b20;
+use crate::sensor::i2c_sensors::I2cSensors;
 use crate::sensor::soil_sensor::SoilSensor;
 use crate::sensor::system_sensor::SystemSensor;
-use crate::storage::flash_storage::FlashStorage;
+use crate::storage::flash_storage::{FlashStorage, SharedFlash};
 
 bind_interrupts!(struct Irqs {
     ADC_IRQ_FIFO => embassy_rp::adc::InterruptHandler;
     I2C0_IRQ => embassy_rp::i2c::InterruptHandler<I2C0>;
+    PIO0_IRQ_0 => embassy_rp::pio::InterruptHandler<PIO0>;
 });
 
 assign_resources! {
@@ -39,14 +62,31 @@ assign_resources! {
     flash: FlashRes {
         flash: FLASH,
     },
-    air: AirSensorRes {
+    clock: ClockRes {
+        rtc: RTC,
+    },
+    watchdog: WatchdogRes {
+        watchdog: WATCHDOG,
+    },
+    i2c: I2cRes {
         sda: PIN_16,
         scl: PIN_17,
         i2c0: I2C0,
     },
     soil: SoilSensorRes {
         pwr: PIN_22,
+        pwr2: PIN_21,
         sig: PIN_27,
+        sig2: PIN_28,
+        sel0: PIN_6,
+        sel1: PIN_7,
+        sel2: PIN_8,
+        sel3: PIN_9,
+        dma: DMA_CH2,
+    },
+    onewire: OneWireRes {
+        pio: PIO0,
+        dq: PIN_18,
     },
     radio: RadioRes {
         busy: PIN_2,
@@ -62,18 +102,57 @@ assign_resources! {
     },
 }
 
+static I2C_BUS: StaticCell<Mutex<NoopRawMutex, I2cBus>> = StaticCell::new();
+static WATCHDOG: StaticCell<SharedWatchdog> = StaticCell::new();
+static FLASH: StaticCell<SharedFlash> = StaticCell::new();
+
+/// Bootloader leaves the watchdog running, a hung image is reset and, while unconfirmed, reverted
+#[embassy_executor::task]
+async fn feed_watchdog(watchdog: &'static SharedWatchdog) {
+    watchdog.lock(|watchdog| {
+        let mut watchdog = watchdog.borrow_mut();
+        watchdog.pause_on_debug(true);
+        watchdog.start(Duration::from_millis(config::Config::WATCHDOG_TIMEOUT_MS));
+    });
+    loop {
+        watchdog.lock(|watchdog| watchdog.borrow_mut().feed());
+        Timer::after_secs(config::Config::WATCHDOG_FEED_SECS).await;
+    }
+}
+
 #[embassy_executor::main]
-async fn main(_spawner: Spawner) {
+async fn main(spawner: Spawner) {
     let p = embassy_rp::init(Config::default());
     let r = split_resources! {p};
 
     let adc = adc::Adc::new(r.adc.adc, Irqs, adc::Config::default());
-    let system = SystemSensor::new(r.system);
-    let soil = SoilSensor::new(r.soil);
-    let air = AirSensor::new(r.air);
-    let storage = FlashStorage::new(r.flash);
-    let radio = LoraRadio::try_new(r.radio).await.expect("radio init failed");
-    let device = Device::new(adc, system, soil, air, radio, storage);
+    let i2c_bus = &*I2C_BUS.init(Mutex::new(I2cBus::new(r.i2c)));
+    // storage and firmware updates share the flash, both are driven by the device sequentially
+    let flash = &*FLASH.init(blocking_mutex::Mutex::new(RefCell::new(Flash::new_blocking(r.flash.flash))));
+    let mut unique_id = [0u8; 8];
+    let unique_id = flash
+        .lock(|flash| flash.borrow_mut().blocking_unique_id(&mut unique_id))
+        .map(|()| u64::from_be_bytes(unique_id));
+    let system = SystemSensor::new(r.system, unique_id);
+    let soil = SoilSensor::new(r.soil, I2cDevice::new(i2c_bus));
+    let air = I2cSensors::new(move || I2cDevice::new(i2c_bus));
+    let soil_temperature = Ds18b20::new(OneWire::new(r.onewire));
+    let watchdog = &*WATCHDOG.init(blocking_mutex::Mutex::new(RefCell::new(Watchdog::new(r.watchdog.watchdog))));
+    defmt::unwrap!(spawner.spawn(feed_watchdog(watchdog)));
+    let firmware = BootFirmware::new(flash, watchdog);
+    let storage = FlashStorage::new(flash);
+    let clock = RtcClock::new(r.clock, watchdog);
+    #[cfg(not(feature = "p2p"))]
+    let radio = match LoraRadio::try_new(r.radio).await {
+        Ok(radio) => radio,
+        Err(e) => defmt::panic!("radio init failed, {:?}", e),
+    };
+    #[cfg(feature = "p2p")]
+    let radio = match P2pRadio::try_new(r.radio, unique_id.ok()).await {
+        Ok(radio) => radio,
+        Err(e) => defmt::panic!("radio init failed, {:?}", e),
+    };
+    let device = Device::new(adc, system, soil, air, soil_temperature, radio, storage, clock, firmware);
 
     device.run().await;
 }
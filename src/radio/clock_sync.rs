@@ -0,0 +1,56 @@
+use crate::config;
+
+/// LoRaWAN Application Layer Clock Synchronization (TS003) package, network server
+/// answers the device time request with a correction of the device clock in seconds.
+pub const FPORT: u8 = 202;
+
+const PACKAGE_VERSION: u8 = 0x00;
+const APP_TIME: u8 = 0x01;
+const FORCE_DEVICE_RESYNC: u8 = 0x03;
+
+const PACKAGE_ID: u8 = 1;
+const PACKAGE_VERSION_NUMBER: u8 = 1;
+
+/// Seconds between unix epoch and GPS epoch (1980-01-06), leap seconds excluded
+pub const GPS_EPOCH_OFFSET: u32 = 315_964_800;
+
+#[derive(defmt::Format)]
+pub enum ClockSyncCommand {
+    /// Network asks for the implemented package version
+    PackageVersionReq,
+    /// Answer to `AppTimeReq`, seconds to add to the device clock
+    AppTimeAns { correction: i32, token: u8 },
+    /// Network asks the device to resynchronize, `nb_transmissions` requests should be sent
+    ForceDeviceResyncReq { nb_transmissions: u8 },
+}
+
+/// Encodes `AppTimeReq` with answer required, `device_time` is in GPS epoch seconds
+pub fn app_time_req(device_time: u32, token: u8) -> [u8; 6] {
+    let time = device_time.to_le_bytes();
+    [APP_TIME, time[0], time[1], time[2], time[3], 0x10 | (token & 0x0f)]
+}
+
+/// Encodes `PackageVersionAns`
+pub fn package_version_ans() -> [u8; 3] {
+    [PACKAGE_VERSION, PACKAGE_ID, PACKAGE_VERSION_NUMBER]
+}
+
+/// Decodes the first command of a downlink received on `FPORT`
+pub fn parse(payload: &[u8]) -> Option<ClockSyncCommand> {
+    match payload {
+        [PACKAGE_VERSION, ..] => Some(ClockSyncCommand::PackageVersionReq),
+        [APP_TIME, c0, c1, c2, c3, param, ..] => Some(ClockSyncCommand::AppTimeAns {
+            correction: i32::from_le_bytes([*c0, *c1, *c2, *c3]),
+            token: param & 0x0f,
+        }),
+        [FORCE_DEVICE_RESYNC, param, ..] => Some(ClockSyncCommand::ForceDeviceResyncReq {
+            nb_transmissions: param & 0x07,
+        }),
+        _ => None,
+    }
+}
+
+/// Unix seconds to GPS epoch seconds, the gap grows with every leap second
+pub fn unix_to_gps(unix: u32) -> u32 {
+    unix.wrapping_sub(GPS_EPOCH_OFFSET).wrapping_add(config::Config::GPS_LEAP_SECONDS)
+}
@@ -0,0 +1,128 @@
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::KeyInit;
+use aes::Aes128;
+use cmac::{Cmac, Mac};
+
+/// LoRaWAN 1.0.x LinkCheckReq/LinkCheckAns MAC command. The stack offers no way to queue MAC commands,
+/// the request is appended to the FOpts of an uplink frame built by the stack and the frame is signed again.
+pub const CID: u8 = 0x02;
+
+/// Largest PHY payload of a LoRaWAN frame
+pub const MAX_FRAME_SIZE: usize = 255;
+
+const MTYPE_MASK: u8 = 0xe0;
+const MTYPE_UNCONFIRMED_UP: u8 = 0x40;
+const MTYPE_UNCONFIRMED_DOWN: u8 = 0x60;
+const MTYPE_CONFIRMED_UP: u8 = 0x80;
+const MTYPE_CONFIRMED_DOWN: u8 = 0xa0;
+const FHDR_SIZE: usize = 7;
+const MIC_SIZE: usize = 4;
+const FOPTS_MAX: usize = 15;
+
+/// Session the request is sent in, `fcnt` is the 32 bit frame counter of the stack the frame is expected near
+#[derive(Clone, Copy)]
+pub struct Request {
+    pub nwkskey: [u8; 16],
+    pub devaddr: [u8; 4],
+    pub fcnt: u32,
+}
+
+/// Answer of the network, margin in dB above the demodulation floor of the best gateway
+#[derive(defmt::Format, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Answer {
+    pub margin: u8,
+    pub gateways: u8,
+}
+
+/// Appends the request to the FOpts of an uplink frame and signs it again, returns size of the frame written to `out`.
+/// A lone FPort without payload is dropped, network then keeps the frame from the application.
+/// Frames of another session, with full FOpts or with MAC commands in the payload are left alone.
+pub fn append_request(frame: &[u8], request: &Request, out: &mut [u8; MAX_FRAME_SIZE]) -> Option<usize> {
+    if frame.len() < 1 + FHDR_SIZE + MIC_SIZE || frame.len() >= MAX_FRAME_SIZE {
+        return None;
+    }
+    if !matches!(frame[0] & MTYPE_MASK, MTYPE_UNCONFIRMED_UP | MTYPE_CONFIRMED_UP) || frame[1..5] != request.devaddr {
+        return None;
+    }
+
+    let fopts_len = (frame[5] & 0x0f) as usize;
+    let fopts_end = 1 + FHDR_SIZE + fopts_len;
+    let mic_start = frame.len() - MIC_SIZE;
+    if fopts_len == FOPTS_MAX || fopts_end > mic_start {
+        return None;
+    }
+
+    let rest = &frame[fopts_end..mic_start];
+    let payload = match rest {
+        [_port] => &[][..],
+        [0, ..] => return None,
+        _ => rest,
+    };
+
+    // 16 bits on air, the upper half follows from the counter of the stack
+    let fcnt16 = u16::from_le_bytes([frame[6], frame[7]]) as u32;
+    let fcnt = [request.fcnt, request.fcnt.wrapping_add(0x1_0000)]
+        .map(|near| (near & !0xffff) | fcnt16)
+        .into_iter()
+        .find(|fcnt| mic(&request.nwkskey, &request.devaddr, *fcnt, &frame[..mic_start]) == frame[mic_start..])?;
+
+    let len = fopts_end + 1 + payload.len();
+    out[..fopts_end].copy_from_slice(&frame[..fopts_end]);
+    out[5] += 1;
+    out[fopts_end] = CID;
+    out[fopts_end + 1..len].copy_from_slice(payload);
+
+    let mic = mic(&request.nwkskey, &request.devaddr, fcnt, &out[..len]);
+    out[len..len + MIC_SIZE].copy_from_slice(&mic);
+
+    Some(len + MIC_SIZE)
+}
+
+/// Looks for the answer in the FOpts of a downlink frame of the session, MIC is left to the stack
+pub fn parse_answer(frame: &[u8], devaddr: &[u8; 4]) -> Option<Answer> {
+    if frame.len() < 1 + FHDR_SIZE + MIC_SIZE {
+        return None;
+    }
+    if !matches!(frame[0] & MTYPE_MASK, MTYPE_UNCONFIRMED_DOWN | MTYPE_CONFIRMED_DOWN) || frame[1..5] != *devaddr {
+        return None;
+    }
+
+    let fopts_len = (frame[5] & 0x0f) as usize;
+    let mut fopts = frame.get(1 + FHDR_SIZE..1 + FHDR_SIZE + fopts_len)?;
+
+    while let [cid, rest @ ..] = fopts {
+        let size = match *cid {
+            CID => {
+                return rest.get(..2).map(|answer| Answer {
+                    margin: answer[0],
+                    gateways: answer[1],
+                })
+            }
+            0x06 => 0,               // DevStatusReq
+            0x04 | 0x08 | 0x09 => 1, // DutyCycleReq, RXTimingSetupReq, TxParamSetupReq
+            0x03 | 0x05 | 0x0a => 4, // LinkADRReq, RXParamSetupReq, DlChannelReq
+            0x07 | 0x0d => 5,        // NewChannelReq, DeviceTimeAns
+            _ => return None,
+        };
+        fopts = rest.get(size..)?;
+    }
+
+    None
+}
+
+/// MIC of an uplink, B0 block followed by the frame without MIC
+fn mic(nwkskey: &[u8; 16], devaddr: &[u8; 4], fcnt: u32, msg: &[u8]) -> [u8; MIC_SIZE] {
+    let mut b0 = [0u8; 16];
+    b0[0] = 0x49;
+    b0[6..10].copy_from_slice(devaddr);
+    b0[10..14].copy_from_slice(&fcnt.to_le_bytes());
+    b0[15] = msg.len() as u8;
+
+    let mut cmac = <Cmac<Aes128> as KeyInit>::new(GenericArray::from_slice(nwkskey));
+    cmac.update(&b0);
+    cmac.update(msg);
+
+    let mut mic = [0u8; MIC_SIZE];
+    mic.copy_from_slice(&cmac.finalize().into_bytes()[..MIC_SIZE]);
+    mic
+}
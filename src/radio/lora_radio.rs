@@ -1,96 +1,622 @@
+use core::cell::{Cell, RefCell};
+
 use embassy_rp::clocks::RoscRng;
-use embassy_rp::gpio::{Input, Level, Output, Pull};
-use embassy_rp::peripherals::SPI1;
-use embassy_rp::spi::{self, Config, Spi};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_time::Delay;
-use embedded_hal_bus::spi::ExclusiveDevice;
-use lora_phy::iv::GenericSx126xInterfaceVariant;
+use heapless::Vec;
+use lora_fuota::multicast::{McGroup, MAX_GROUPS};
 use lora_phy::lorawan_radio::LorawanRadio;
-use lora_phy::mod_params::RadioError;
-use lora_phy::sx126x::{self, Sx1262, Sx126x, TcxoCtrlVoltage};
-use lora_phy::LoRa;
-use lorawan_device::async_device::{self, EmbassyTimer, JoinResponse, SendResponse};
-use lorawan_device::{region, AppSKey, DevAddr, JoinMode, NewSKey};
+use lorawan_device::async_device::radio::{PhyRxTx, RfConfig, RxQuality, RxStatus, Timings, TxConfig};
+use lorawan_device::async_device::{self, EmbassyTimer, JoinResponse, ListenResponse, SendResponse};
+use lorawan_device::{region, JoinMode};
 
-use crate::radio::Radio;
+use crate::radio::clock_sync::{self, ClockSyncCommand};
+use crate::radio::link_check::{self, Answer, Request};
+use crate::radio::sx1262::{self, Sx1262};
+use crate::radio::{Downlink, LinkCheck, LinkQuality, Radio, RadioError, Session, Uplink, UplinkOptions, DOWNLINKS};
+use crate::secret::Secret;
 use crate::{config, RadioRes};
 
 type SX1262 = lorawan_device::async_device::Device<
-    LorawanRadio<
-        Sx126x<
-            ExclusiveDevice<Spi<'static, SPI1, spi::Async>, Output<'static>, Delay>,
-            GenericSx126xInterfaceVariant<Output<'static>, Input<'static>>,
-            Sx1262,
-        >,
-        Delay,
-        14,
-    >,
+    MonitoredRadio<LorawanRadio<Sx1262, Delay, { config::Config::LORAWAN_MAX_TX_POWER }>>,
     EmbassyTimer,
     RoscRng,
 >;
 
+/// Progress of a link check, the request rides on the next uplink and its answer on the downlink following it
+#[derive(Clone, Copy)]
+enum LinkCheckState {
+    Idle,
+    Armed(Request),
+    Sent([u8; 4]), // device address the answer is expected for
+    Answered(Answer),
+}
+
+/// State of the phy shared with the LoRaWAN stack, which owns the phy and does not expose it
+#[derive(Clone, Copy)]
+struct PhyState {
+    rx_quality: Option<LinkQuality>, // quality of the last received frame
+    tx_power: Option<i8>,            // power of the last transmission in dBm
+    tx_power_limit: i8,              // runtime cap below `Config::LORAWAN_MAX_TX_POWER`
+    link_check: LinkCheckState,
+}
+
+static PHY_STATE: Mutex<CriticalSectionRawMutex, Cell<PhyState>> = Mutex::new(Cell::new(PhyState {
+    rx_quality: None,
+    tx_power: None,
+    tx_power_limit: config::Config::LORAWAN_MAX_TX_POWER as i8,
+    link_check: LinkCheckState::Idle,
+}));
+
+/// Multicast groups set up thru the remote multicast setup package, the stack knows only the unicast session
+static MULTICAST_GROUPS: Mutex<CriticalSectionRawMutex, RefCell<[Option<McGroup>; MAX_GROUPS]>> =
+    Mutex::new(RefCell::new([None, None, None, None]));
+
+fn update_phy_state(f: impl FnOnce(&mut PhyState)) -> PhyState {
+    PHY_STATE.lock(|cell| {
+        let mut state = cell.get();
+        f(&mut state);
+        cell.set(state);
+        state
+    })
+}
+
+/// Phy wrapper recording signal quality of received frames and power of transmissions,
+/// transmission power is capped by the runtime limit. Frames of multicast groups received
+/// while listening continuously are taken out before they reach the stack. An armed link check
+/// request is appended to the next uplink frame and its answer picked out of the downlink.
+pub struct MonitoredRadio<P> {
+    phy: P,
+}
+
+impl<P> PhyRxTx for MonitoredRadio<P>
+where
+    P: PhyRxTx,
+{
+    type PhyError = P::PhyError;
+
+    const ANTENNA_GAIN: i8 = P::ANTENNA_GAIN;
+    const MAX_RADIO_POWER: u8 = P::MAX_RADIO_POWER;
+
+    async fn tx(&mut self, mut config: TxConfig, buf: &[u8]) -> Result<u32, Self::PhyError> {
+        let mut request = None;
+        let state = update_phy_state(|state| {
+            config.pw = config.pw.min(state.tx_power_limit);
+            state.tx_power = Some(config.pw);
+            if let LinkCheckState::Armed(armed) = state.link_check {
+                request = Some(armed);
+                state.link_check = LinkCheckState::Idle;
+            }
+        });
+        defmt::debug!("Transmitting at {=i8} dBm, limit {=i8} dBm", config.pw, state.tx_power_limit);
+
+        let mut frame = [0u8; link_check::MAX_FRAME_SIZE];
+        let buf = match request {
+            Some(request) => match link_check::append_request(buf, &request, &mut frame) {
+                Some(size) => {
+                    update_phy_state(|state| state.link_check = LinkCheckState::Sent(request.devaddr));
+                    &frame[..size]
+                }
+                None => {
+                    defmt::warn!("LinkCheckReq does not fit the uplink frame");
+                    buf
+                }
+            },
+            None => buf,
+        };
+
+        self.phy.tx(config, buf).await
+    }
+
+    async fn setup_rx(&mut self, config: RfConfig) -> Result<(), Self::PhyError> {
+        self.phy.setup_rx(config).await
+    }
+
+    async fn rx_single(&mut self, buf: &mut [u8]) -> Result<RxStatus, Self::PhyError> {
+        let status = self.phy.rx_single(buf).await?;
+        if let RxStatus::Rx(size, quality) = &status {
+            record_quality(quality);
+            receive_link_check(&buf[..*size]);
+        }
+        Ok(status)
+    }
+
+    async fn rx_continuous(&mut self, buf: &mut [u8]) -> Result<(usize, RxQuality), Self::PhyError> {
+        loop {
+            let (size, quality) = self.phy.rx_continuous(buf).await?;
+            record_quality(&quality);
+            if !receive_multicast(&mut buf[..size]) {
+                return Ok((size, quality));
+            }
+        }
+    }
+
+    async fn low_power(&mut self) -> Result<(), Self::PhyError> {
+        self.phy.low_power().await
+    }
+}
+
+impl<P> Timings for MonitoredRadio<P>
+where
+    P: Timings,
+{
+    fn get_rx_window_offset_ms(&self) -> i32 {
+        self.phy.get_rx_window_offset_ms()
+    }
+
+    fn get_rx_window_duration_ms(&self) -> u32 {
+        self.phy.get_rx_window_duration_ms()
+    }
+}
+
+fn record_quality(quality: &RxQuality) {
+    let quality = LinkQuality {
+        rssi: quality.rssi(),
+        snr: quality.snr(),
+    };
+    update_phy_state(|state| state.rx_quality = Some(quality));
+}
+
+/// Decrypts a frame addressed to a multicast group and passes it on thru `DOWNLINKS`,
+/// false when the frame is for the stack
+fn receive_multicast(frame: &mut [u8]) -> bool {
+    let Some(addr) = frame.get(1..5).map(|addr| u32::from_le_bytes([addr[0], addr[1], addr[2], addr[3]])) else {
+        return false;
+    };
+
+    let downlink = MULTICAST_GROUPS.lock(|groups| {
+        let mut groups = groups.borrow_mut();
+        let group = groups.iter_mut().flatten().find(|group| group.addr == addr)?;
+        match group.decrypt(frame) {
+            Ok((port, payload)) => Some(Vec::from_slice(payload).ok().map(|payload| Downlink { port, payload })),
+            Err(e) => {
+                defmt::warn!("Dropping frame of multicast group {=u8}, {:?}", group.id, e);
+                Some(None)
+            }
+        }
+    });
+
+    match downlink {
+        Some(Some(downlink)) => {
+            defmt::debug!("Multicast downlink on port {=u8}", downlink.port);
+            if DOWNLINKS.try_send(downlink).is_err() {
+                defmt::warn!("Downlink queue full, dropping multicast downlink");
+            }
+            true
+        }
+        Some(None) => true,
+        None => false,
+    }
+}
+
+/// Records the answer to a sent link check, the stack authenticates the frame afterwards
+fn receive_link_check(frame: &[u8]) {
+    update_phy_state(|state| {
+        if let LinkCheckState::Sent(devaddr) = state.link_check {
+            if let Some(answer) = link_check::parse_answer(frame, &devaddr) {
+                state.link_check = LinkCheckState::Answered(answer);
+            }
+        }
+    });
+}
+
+fn take_quality() -> Option<LinkQuality> {
+    let mut quality = None;
+    update_phy_state(|state| quality = state.rx_quality.take());
+    quality
+}
+
+fn tx_power() -> Option<i8> {
+    PHY_STATE.lock(|cell| cell.get().tx_power)
+}
+
+/// Maps data rate index to the region agnostic data rate of the stack,
+/// none for data rates the region does not use for uplinks
+fn region_data_rate(region: region::Region, index: u8) -> Option<region::DR> {
+    if region_max_payload(region, index) == 0 {
+        return None;
+    }
+
+    let data_rate = match index {
+        0 => region::DR::_0,
+        1 => region::DR::_1,
+        2 => region::DR::_2,
+        3 => region::DR::_3,
+        4 => region::DR::_4,
+        5 => region::DR::_5,
+        6 => region::DR::_6,
+        7 => region::DR::_7,
+        8 => region::DR::_8,
+        9 => region::DR::_9,
+        10 => region::DR::_10,
+        11 => region::DR::_11,
+        12 => region::DR::_12,
+        13 => region::DR::_13,
+        14 => region::DR::_14,
+        _ => return None,
+    };
+    Some(data_rate)
+}
+
+/// Largest application payload of an uplink at given data rate in the configured region,
+/// zero for data rates the region does not use for uplinks
+fn region_max_payload(region: region::Region, data_rate: u8) -> usize {
+    match region {
+        region::Region::US915 => match data_rate {
+            0 => 11,
+            1 => 53,
+            2 => 125,
+            3 | 4 => 242,
+            _ => 0,
+        },
+        region::Region::AU915 => match data_rate {
+            0..=2 => 51,
+            3 => 115,
+            4..=6 => 222,
+            _ => 0,
+        },
+        region::Region::AS923_1 | region::Region::AS923_2 | region::Region::AS923_3 | region::Region::AS923_4 => match data_rate {
+            0..=2 => 59,
+            3 => 123,
+            4..=7 => 230,
+            _ => 0,
+        },
+        // EU868, EU433 and IN865
+        _ => match data_rate {
+            0..=2 => 51,
+            3 => 115,
+            4..=7 => 222,
+            _ => 0,
+        },
+    }
+}
+
+/// Default RX2 frequency in Hz and data rate of the region, the stack listens on them in Class C
+fn region_rx2(region: region::Region) -> (u32, u8) {
+    match region {
+        region::Region::AS923_1 => (923_200_000, 2),
+        region::Region::AS923_2 => (921_400_000, 2),
+        region::Region::AS923_3 => (916_600_000, 2),
+        region::Region::AS923_4 => (917_500_000, 2),
+        region::Region::AU915 => (923_300_000, 8),
+        region::Region::EU433 => (434_665_000, 0),
+        region::Region::EU868 => (869_525_000, 0),
+        region::Region::IN865 => (866_550_000, 2),
+        region::Region::US915 => (923_300_000, 8),
+    }
+}
+
+/// Whether support of the region is compiled into the LoRaWAN stack thru a cargo feature
+fn region_enabled(region: region::Region) -> bool {
+    match region {
+        region::Region::AS923_1 => cfg!(feature = "region-as923-1"),
+        region::Region::AS923_2 => cfg!(feature = "region-as923-2"),
+        region::Region::AS923_3 => cfg!(feature = "region-as923-3"),
+        region::Region::AS923_4 => cfg!(feature = "region-as923-4"),
+        region::Region::AU915 => cfg!(feature = "region-au915"),
+        region::Region::EU433 => cfg!(feature = "region-eu433"),
+        region::Region::EU868 => cfg!(feature = "region-eu868"),
+        region::Region::IN865 => cfg!(feature = "region-in865"),
+        region::Region::US915 => cfg!(feature = "region-us915"),
+    }
+}
+
 #[derive(defmt::Format)]
 pub enum LoraRadioError {
+    Phy(lora_phy::mod_params::RadioError),
+    RegionDisabled,
+    SubBand,
+    DataRate,
+    PayloadTooLarge(usize), // maximum payload size at the current data rate
     NoJoinAccept,
     NoAck,
     SessionExpired,
     LoRaWAN(lorawan_device::async_device::Error<lora_phy::lorawan_radio::Error>),
 }
 
+impl RadioError for LoraRadioError {
+    fn is_no_ack(&self) -> bool {
+        matches!(self, LoraRadioError::NoAck)
+    }
+
+    fn is_session_expired(&self) -> bool {
+        matches!(self, LoraRadioError::SessionExpired)
+    }
+}
+
 pub struct LoraRadio {
     radio: SX1262,
+    data_rate: u8, // requested data rate, lowered by the fallback
+    adr: bool,
+    no_acks: u8, // consecutive confirmed uplinks without acknowledgement
 }
 
 impl LoraRadio {
-    pub async fn try_new(r: RadioRes) -> Result<Self, RadioError> {
-        let nss = Output::new(r.cs, Level::High);
-        let reset = Output::new(r.rst, Level::High);
-        let dio1 = Input::new(r.dio1, Pull::None);
-        let busy = Input::new(r.busy, Pull::None);
-        let spi = Spi::new(r.spi1, r.clk, r.mosi, r.miso, r.dma_ch0, r.dma_ch1, Config::default());
-        let spi_bus = ExclusiveDevice::new(spi, nss, Delay);
-        let sx1262_config = sx126x::Config {
-            chip: Sx1262,
-            tcxo_ctrl: Some(TcxoCtrlVoltage::Ctrl1V7),
-            use_dcdc: true,
-            rx_boost: false,
+    pub async fn try_new(r: RadioRes) -> Result<Self, LoraRadioError> {
+        let region = config::Config::LORAWAN_REGION;
+        if !region_enabled(region) {
+            defmt::error!("LoRaWAN region is not enabled, build with its region-* feature");
+            return Err(LoraRadioError::RegionDisabled);
+        }
+        let Some(data_rate) = region_data_rate(region, config::Config::LORAWAN_DATA_RATE) else {
+            defmt::error!(
+                "DR{=u8} is not an uplink data rate of the region",
+                config::Config::LORAWAN_DATA_RATE
+            );
+            return Err(LoraRadioError::DataRate);
         };
 
-        let iv = GenericSx126xInterfaceVariant::new(reset, dio1, busy, None, None)?;
-        let lora = LoRa::new(Sx126x::new(spi_bus, iv, sx1262_config), true, Delay).await?;
-        let mut radio: LorawanRadio<_, _, 14> = lora.into();
+        let lora = sx1262::init(r, true).await.map_err(LoraRadioError::Phy)?;
+        let mut radio: LorawanRadio<_, _, { config::Config::LORAWAN_MAX_TX_POWER }> = lora.into();
         radio.set_rx_window_lead_time(config::Config::RX_WINDOW_LEAD_TIME);
         radio.set_rx_window_buffer(config::Config::RX_WINDOW_BUFFER);
-        let region: region::Configuration = region::Configuration::new(config::Config::LORAWAN_REGION);
-        let lora_radio: async_device::Device<_, _, _> =
-            async_device::Device::new(region, radio, EmbassyTimer::new(), embassy_rp::clocks::RoscRng);
+        let mut region: region::Configuration = region::Configuration::new(region);
+        match (config::Config::LORAWAN_REGION, config::Config::LORAWAN_SUBBAND) {
+            (region::Region::US915 | region::Region::AU915, Some(subband @ 1..=8)) => region.set_subband(subband),
+            (region::Region::US915 | region::Region::AU915, None) => {
+                defmt::warn!("No sub-band configured, joining on all channels of the region");
+            }
+            (_, None) => {}
+            (_, Some(_)) => return Err(LoraRadioError::SubBand),
+        }
+        let mut lora_radio: async_device::Device<_, _, _> = async_device::Device::new(
+            region,
+            MonitoredRadio { phy: radio },
+            EmbassyTimer::new(),
+            embassy_rp::clocks::RoscRng,
+        );
+        lora_radio.set_datarate(data_rate);
+
+        Ok(Self {
+            radio: lora_radio,
+            data_rate: config::Config::LORAWAN_DATA_RATE,
+            adr: config::Config::LORAWAN_ADR,
+            no_acks: 0,
+        })
+    }
+
+    /// Steps down to a more robust data rate after `Config::LORAWAN_DR_FALLBACK_NOACKS` unacknowledged uplinks
+    fn fall_back_data_rate(&mut self) {
+        let threshold = config::Config::LORAWAN_DR_FALLBACK_NOACKS;
+        if threshold == 0 {
+            return;
+        }
+
+        self.no_acks = self.no_acks.saturating_add(1);
+        if self.no_acks < threshold {
+            return;
+        }
+
+        let current = self.radio.get_datarate() as u8;
+        if current > 0 {
+            defmt::warn!("{=u8} uplinks not acknowledged, falling back from DR{=u8}", self.no_acks, current);
+            self.set_data_rate(current - 1);
+        }
+        self.no_acks = 0;
+    }
+
+    async fn send(&mut self, options: &UplinkOptions, payload: &[u8]) -> Result<Uplink, LoraRadioError> {
+        // drop quality of frames received before this uplink, e.g. a join accept
+        let _ = take_quality();
+
+        // without adr the requested data rate overrides LinkADRReq of the network
+        if !self.adr {
+            if let Some(data_rate) = region_data_rate(config::Config::LORAWAN_REGION, self.data_rate) {
+                self.radio.set_datarate(data_rate);
+            }
+        }
+        let data_rate = self.radio.get_datarate() as u8;
+
+        let (fcnt_down, acked) = match self.radio.send(payload, options.port, options.confirmed).await {
+            Ok(SendResponse::DownlinkReceived(fcnt_down)) => (Some(fcnt_down), options.confirmed),
+            Ok(SendResponse::RxComplete) if options.confirmed => return Err(LoraRadioError::NoAck),
+            Ok(SendResponse::RxComplete) => (None, false),
+            Ok(SendResponse::SessionExpired) => return Err(LoraRadioError::SessionExpired),
+            Ok(SendResponse::NoAck) => return Err(LoraRadioError::NoAck),
+            Err(err) => return Err(LoraRadioError::LoRaWAN(err)),
+        };
+
+        let downlink = self.radio.take_downlink().map(|downlink| Downlink {
+            port: downlink.fport,
+            payload: downlink.data,
+        });
 
-        Ok(Self { radio: lora_radio })
+        Ok(Uplink {
+            fcnt_down,
+            acked,
+            downlink,
+            quality: take_quality(),
+            data_rate,
+            tx_power: tx_power(),
+        })
     }
 }
 
 impl Radio for LoraRadio {
     type Error = LoraRadioError;
 
-    async fn join(&mut self, mode: &JoinMode) -> Result<(NewSKey, AppSKey, DevAddr<[u8; 4]>), Self::Error> {
+    async fn join(&mut self, mode: &JoinMode) -> Result<Option<Session>, Self::Error> {
         match self.radio.join(mode).await {
             Ok(JoinResponse::JoinSuccess) => {
                 let session = self.radio.get_session().unwrap();
-                Ok((session.nwkskey, session.appskey, session.devaddr))
+                let mut devaddr = [0u8; 4];
+                devaddr.copy_from_slice(session.devaddr.as_ref());
+                Ok(Some(Session {
+                    nwkskey: Secret::from_slice(session.nwkskey.as_ref()),
+                    appskey: Secret::from_slice(session.appskey.as_ref()),
+                    devaddr,
+                }))
             }
             Ok(JoinResponse::NoJoinAccept) => Err(LoraRadioError::NoJoinAccept),
             Err(err) => Err(LoraRadioError::LoRaWAN(err)),
         }
     }
 
-    async fn uplink(&mut self, payload: &[u8]) -> Result<u32, Self::Error> {
-        match self.radio.send(payload, 1, true).await {
-            Ok(response) => match response {
-                SendResponse::DownlinkReceived(fcnt_down) => Ok(fcnt_down),
-                SendResponse::SessionExpired => Err(LoraRadioError::SessionExpired),
-                SendResponse::NoAck | SendResponse::RxComplete => Err(LoraRadioError::NoAck),
-            },
-            Err(err) => Err(LoraRadioError::LoRaWAN(err)),
+    // The stack does not expose NbTrans, repeated transmissions are sent as new frames with their own frame counter,
+    // hence the application receives every transmission which got thru as a duplicate. Data rate falls back once per
+    // uplink missing all of its acknowledgements rather than once per transmission.
+    async fn uplink(&mut self, options: &UplinkOptions, payload: &[u8]) -> Result<Uplink, Self::Error> {
+        let max_payload = self.max_payload();
+        if payload.len() > max_payload {
+            return Err(LoraRadioError::PayloadTooLarge(max_payload));
+        }
+
+        let mut result = Err(LoraRadioError::NoAck);
+
+        for transmission in 0..options.nb_trans.max(1) {
+            if transmission > 0 {
+                defmt::debug!("Retransmitting uplink, transmission {=u8}", transmission + 1);
+            }
+
+            result = self.send(options, payload).await;
+            match &result {
+                Ok(uplink) if uplink.acked || uplink.downlink.is_some() => break,
+                Ok(_) | Err(LoraRadioError::NoAck) => {}
+                Err(_) => break,
+            }
+        }
+
+        match &result {
+            Ok(uplink) if uplink.acked || uplink.downlink.is_some() => self.no_acks = 0,
+            Err(LoraRadioError::NoAck) => self.fall_back_data_rate(),
+            _ => {}
+        }
+
+        result
+    }
+
+    async fn sync_time(&mut self, device_time: u32, token: u8) -> Result<Option<i32>, Self::Error> {
+        let request = clock_sync::app_time_req(device_time, token);
+        let uplink = self.uplink(&UplinkOptions::unconfirmed(clock_sync::FPORT), &request).await?;
+
+        let answer = uplink
+            .downlink
+            .filter(|downlink| downlink.port == clock_sync::FPORT)
+            .and_then(|downlink| clock_sync::parse(&downlink.payload));
+
+        match answer {
+            Some(ClockSyncCommand::AppTimeAns {
+                correction,
+                token: answered,
+            }) if answered == token => Ok(Some(correction)),
+            _ => Ok(None),
         }
     }
+
+    // The stack does not expose LinkCheckReq, the phy wrapper appends it to an empty uplink kept from the
+    // application and picks the answer out of the downlink, which counts only once the stack accepted it
+    async fn link_check(&mut self) -> Result<Option<LinkCheck>, Self::Error> {
+        let Some(session) = self.radio.get_session() else {
+            return Err(LoraRadioError::SessionExpired);
+        };
+        let mut request = Request {
+            nwkskey: [0u8; 16],
+            devaddr: [0u8; 4],
+            fcnt: session.fcnt_up,
+        };
+        request.nwkskey.copy_from_slice(session.nwkskey.as_ref());
+        request.devaddr.copy_from_slice(session.devaddr.as_ref());
+        update_phy_state(|state| state.link_check = LinkCheckState::Armed(request));
+
+        let options = UplinkOptions {
+            port: config::Config::FPORT_LINK_CHECK,
+            confirmed: false,
+            nb_trans: 1,
+        };
+        let result = self.uplink(&options, &[]).await;
+
+        let mut link_check = LinkCheckState::Idle;
+        update_phy_state(|state| link_check = core::mem::replace(&mut state.link_check, LinkCheckState::Idle));
+
+        match (result?.fcnt_down, link_check) {
+            (Some(_), LinkCheckState::Answered(answer)) => Ok(Some(LinkCheck {
+                margin: answer.margin.min(i8::MAX as u8) as i8,
+                gateways: answer.gateways,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    fn max_payload(&mut self) -> usize {
+        region_max_payload(config::Config::LORAWAN_REGION, self.radio.get_datarate() as u8)
+    }
+
+    fn set_data_rate(&mut self, data_rate: u8) {
+        let Some(dr) = region_data_rate(config::Config::LORAWAN_REGION, data_rate) else {
+            defmt::warn!(
+                "DR{=u8} is not an uplink data rate of the region, keeping DR{=u8}",
+                data_rate,
+                self.data_rate
+            );
+            return;
+        };
+
+        self.data_rate = data_rate;
+        self.radio.set_datarate(dr);
+    }
+
+    fn set_tx_power(&mut self, dbm: i8) {
+        let limit = dbm.min(config::Config::LORAWAN_MAX_TX_POWER as i8);
+        update_phy_state(|state| state.tx_power_limit = limit);
+    }
+
+    fn set_adr(&mut self, enabled: bool) {
+        self.adr = enabled;
+    }
+
+    async fn set_class_c(&mut self, enabled: bool) -> Result<(), Self::Error> {
+        if enabled {
+            self.radio.enable_class_c();
+            Ok(())
+        } else {
+            self.radio.disable_class_c().await.map_err(LoraRadioError::LoRaWAN)
+        }
+    }
+
+    // The stack listens on the RX2 frequency and data rate of the region, uplinks interrupt
+    // the listening and it is resumed by the next call
+    async fn listen(&mut self) -> Result<(), Self::Error> {
+        loop {
+            match self.radio.rxc_listen().await {
+                Ok(ListenResponse::DownlinkReceived(fcnt_down)) => {
+                    let Some(downlink) = self.radio.take_downlink() else {
+                        continue;
+                    };
+                    let downlink = Downlink {
+                        port: downlink.fport,
+                        payload: downlink.data,
+                    };
+
+                    defmt::debug!("Class C downlink fcount {=u32}", fcnt_down);
+                    if DOWNLINKS.try_send(downlink).is_err() {
+                        defmt::warn!("Downlink queue full, dropping downlink fcount {=u32}", fcnt_down);
+                    }
+                }
+                Ok(ListenResponse::SessionExpired) => return Err(LoraRadioError::SessionExpired),
+                Err(err) => return Err(LoraRadioError::LoRaWAN(err)),
+            }
+        }
+    }
+
+    fn set_multicast_group(&mut self, id: u8, group: Option<McGroup>) {
+        MULTICAST_GROUPS.lock(|groups| {
+            if let Some(slot) = groups.borrow_mut().get_mut(id as usize) {
+                *slot = group;
+            }
+        });
+    }
+
+    fn class_c_channel(&self) -> Option<(u32, u8)> {
+        Some(region_rx2(config::Config::LORAWAN_REGION))
+    }
+
+    // Frame counters are part of the session kept by the stack
+    fn restore_sequence(&mut self, _sequence: u32) {}
+
+    fn sequence_reservation(&self) -> Option<u32> {
+        None
+    }
+
+    fn reserve_sequence(&mut self, _sequence: u32) {}
 }
@@ -1,17 +1,168 @@
-use lorawan_device::{AppSKey, DevAddr, JoinMode, NewSKey};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use heapless::Vec;
+use lora_fuota::multicast::McGroup;
+use lorawan_device::JoinMode;
 
+use crate::config;
+use crate::secret::Secret;
+
+pub mod clock_sync;
+#[cfg(not(feature = "p2p"))]
+pub mod link_check;
+#[cfg(not(feature = "p2p"))]
 pub mod lora_radio;
+#[cfg(feature = "p2p")]
+pub mod p2p_radio;
+pub mod sx1262;
+
+/// LoRaWAN session established by a join, session keys are redacted in logs
+#[derive(defmt::Format)]
+pub struct Session {
+    pub nwkskey: Secret<16>,
+    pub appskey: Secret<16>,
+    pub devaddr: [u8; 4],
+}
+
+impl Session {
+    /// Join mode to restore the session via ABP method
+    pub fn abp(&self) -> JoinMode {
+        JoinMode::ABP {
+            nwkskey: (*self.nwkskey.expose()).into(),
+            appskey: (*self.appskey.expose()).into(),
+            devaddr: self.devaddr.into(),
+        }
+    }
+}
+
+/// Delivery options of a single uplink
+#[derive(defmt::Format, Clone, Copy)]
+pub struct UplinkOptions {
+    pub port: u8,
+    pub confirmed: bool,
+    pub nb_trans: u8, // transmissions until acked if confirmed, repetitions otherwise, each sent as a new frame
+}
+
+impl UplinkOptions {
+    pub const fn unconfirmed(port: u8) -> Self {
+        Self {
+            port,
+            confirmed: false,
+            nb_trans: config::Config::UPLINK_NB_TRANS,
+        }
+    }
+
+    pub const fn confirmed(port: u8) -> Self {
+        Self {
+            port,
+            confirmed: true,
+            nb_trans: config::Config::CONFIRMED_NB_TRANS,
+        }
+    }
+}
+
+/// Signal quality of the last frame received from the network
+#[derive(defmt::Format, Clone, Copy)]
+pub struct LinkQuality {
+    pub rssi: i16, // dBm
+    pub snr: i8,   // dB
+}
+
+/// Answer to a link check, tells whether the network still hears the node
+#[derive(defmt::Format, Clone, Copy)]
+pub struct LinkCheck {
+    pub margin: i8,   // dB above the demodulation floor
+    pub gateways: u8, // gateways which received the request
+}
+
+/// Lowest snr the sx1262 demodulates at given spreading factor, 2.5 dB per step from -7.5 dB at SF7
+#[cfg(feature = "p2p")]
+pub fn demodulation_floor(spreading_factor: u8) -> f32 {
+    -7.5 - 2.5 * (spreading_factor.saturating_sub(7)) as f32
+}
+
+/// Application data received in the rx windows following an uplink
+#[derive(defmt::Format)]
+pub struct Downlink {
+    pub port: u8,
+    pub payload: Vec<u8, 256>,
+}
+
+/// Downlinks received while listening in Class C, the device picks them up between duty cycles
+pub static DOWNLINKS: Channel<CriticalSectionRawMutex, Downlink, { config::Config::DOWNLINK_QUEUE }> = Channel::new();
+
+/// Outcome of a delivered uplink
+#[derive(defmt::Format)]
+pub struct Uplink {
+    pub fcnt_down: Option<u32>,       // frame counter of the downlink, if any arrived
+    pub acked: bool,                  // confirmed uplink acknowledged by the network
+    pub downlink: Option<Downlink>,   // application payload of the downlink
+    pub quality: Option<LinkQuality>, // quality of the downlink frame
+    pub data_rate: u8,                // data rate the uplink was sent with
+    pub tx_power: Option<i8>,         // dBm of the last transmission
+}
+
+/// Trait to classify radio errors the device reacts upon, common to all radios
+pub trait RadioError: defmt::Format {
+    /// Confirmed message was not acknowledged
+    fn is_no_ack(&self) -> bool;
+
+    /// Session is no longer valid and the device has to join again
+    fn is_session_expired(&self) -> bool;
+}
 
 // Trait to represent basic functionality of lora radio.
 // Be able to join the network, support both otaa and abp methods.
 // Send uplink messages.
 pub trait Radio {
     /// Error type representation, left up to the implementor
-    type Error;
+    type Error: RadioError;
+
+    // Join the LoRaWAN network, in case of success we receive the session to restore via ABP method on next boot if any
+    async fn join(&mut self, mode: &JoinMode) -> Result<Option<Session>, Self::Error>;
+
+    // Send uplink message with given options, in case of success we receive the downlink and link quality if any
+    async fn uplink(&mut self, options: &UplinkOptions, payload: &[u8]) -> Result<Uplink, Self::Error>;
+
+    // Request clock correction from the network, device time is in GPS epoch seconds,
+    // in case of an answer carrying the request token we receive seconds to add to the device clock
+    async fn sync_time(&mut self, device_time: u32, token: u8) -> Result<Option<i32>, Self::Error>;
+
+    // Check the link to the network, in case of an answer we receive the link margin
+    async fn link_check(&mut self) -> Result<Option<LinkCheck>, Self::Error>;
+
+    // Largest payload an uplink can carry at the current data rate
+    fn max_payload(&mut self) -> usize;
+
+    // Set data rate of the following uplinks, network may change it when adr is enabled,
+    // data rates the radio can not send uplinks with are ignored
+    fn set_data_rate(&mut self, data_rate: u8);
+
+    // Cap transmission power in dBm, never above the power the radio was built for
+    fn set_tx_power(&mut self, dbm: i8);
+
+    // Let the network adapt data rate and power thru LinkADRReq
+    fn set_adr(&mut self, enabled: bool);
+
+    // Switch between Class A, downlinks only after uplinks, and Class C, continuous listening
+    async fn set_class_c(&mut self, enabled: bool) -> Result<(), Self::Error>;
+
+    // Listen for downlinks in Class C until the future is dropped or an error occurs,
+    // received downlinks are passed on thru `DOWNLINKS`
+    async fn listen(&mut self) -> Result<(), Self::Error>;
+
+    // Set or, when none, remove a multicast group, its downlinks are received while listening in Class C
+    fn set_multicast_group(&mut self, id: u8, group: Option<McGroup>);
+
+    // Frequency in Hz and data rate Class C downlinks are received on, none if the radio does not listen in Class C
+    fn class_c_channel(&self) -> Option<(u32, u8)>;
+
+    // Continue after the frame sequence persisted before reset
+    fn restore_sequence(&mut self, sequence: u32);
 
-    // Join the LoRaWAN network
-    async fn join(&mut self, mode: &JoinMode) -> Result<(NewSKey, AppSKey, DevAddr<[u8; 4]>), Self::Error>;
+    // Frame sequence to persist before the reserved ones run out, none while enough are left or the radio keeps its own
+    fn sequence_reservation(&self) -> Option<u32>;
 
-    // Send uplink message, in case of success we receive u32 which represent FcntDown
-    async fn uplink(&mut self, payload: &[u8]) -> Result<u32, Self::Error>;
+    // Frames may be sent up to the persisted sequence
+    fn reserve_sequence(&mut self, sequence: u32);
 }
@@ -0,0 +1,311 @@
+use embassy_time::{with_timeout, Delay, Duration};
+use heapless::Vec;
+use lora_fuota::multicast::McGroup;
+use lora_p2p::frame::{self as p2p_frame, FrameError, Header, FLAG_ACK, FLAG_ACK_REQUEST, FLAG_BOOT};
+use lora_phy::mod_params::{ModulationParams, PacketParams, PacketStatus, RadioError, SpreadingFactor};
+use lora_phy::{LoRa, RxMode};
+use lorawan_device::JoinMode;
+
+use crate::config::{self, P2pSyncWord};
+use crate::radio::sx1262::{self, Sx1262};
+use crate::radio::{self, demodulation_floor, Downlink, LinkCheck, LinkQuality, Radio, Session, Uplink, UplinkOptions};
+use crate::secret::Secret;
+use crate::RadioRes;
+
+const PREAMBLE_LENGTH: u16 = 8;
+
+#[derive(defmt::Format)]
+pub enum P2pRadioError {
+    Phy(RadioError),
+    Frame(FrameError),
+    NoAck,
+    Sequence, // reserved sequences ran out, the next reservation was not persisted
+}
+
+impl radio::RadioError for P2pRadioError {
+    fn is_no_ack(&self) -> bool {
+        matches!(self, P2pRadioError::NoAck)
+    }
+
+    fn is_session_expired(&self) -> bool {
+        false
+    }
+}
+
+impl From<RadioError> for P2pRadioError {
+    fn from(e: RadioError) -> Self {
+        P2pRadioError::Phy(e)
+    }
+}
+
+/// Radio talking directly to a single receiver over LoRa modulation without LoRaWAN,
+/// frames follow `lora_p2p::frame`. Nodes transmit with normal and listen with inverted IQ,
+/// so they never hear each other, only the receiver.
+pub struct P2pRadio {
+    lora: LoRa<Sx1262, Delay>,
+    key: Secret<16>,
+    node_id: u32,
+    unique_id: Option<u64>, // flash unique id, its low bytes make the node id
+    seq: u32,               // sequence of the last frame sent
+    reserved: u32,          // last sequence persisted, frames never go beyond it
+    boot: bool,             // no frame was acknowledged since reset
+    spreading_factor: SpreadingFactor,
+    tx_power: i8,
+}
+
+impl P2pRadio {
+    pub async fn try_new(r: RadioRes, unique_id: Option<u64>) -> Result<Self, P2pRadioError> {
+        let public_network = matches!(config::Config::P2P_SYNC_WORD, P2pSyncWord::Public);
+        let lora = sx1262::init(r, public_network).await?;
+
+        Ok(Self {
+            lora,
+            key: Secret::new(config::Config::P2P_KEY),
+            node_id: 0,
+            unique_id,
+            seq: 0,
+            reserved: 0,
+            boot: true,
+            spreading_factor: config::Config::P2P_SPREADING_FACTOR,
+            tx_power: config::Config::P2P_TX_POWER,
+        })
+    }
+
+    fn modulation(&mut self) -> Result<ModulationParams, RadioError> {
+        self.lora.create_modulation_params(
+            self.spreading_factor,
+            config::Config::P2P_BANDWIDTH,
+            config::Config::P2P_CODING_RATE,
+            config::Config::P2P_FREQUENCY,
+        )
+    }
+
+    async fn transmit(&mut self, frame: &[u8]) -> Result<(), P2pRadioError> {
+        let modulation = self.modulation()?;
+        let mut params: PacketParams = self
+            .lora
+            .create_tx_packet_params(PREAMBLE_LENGTH, false, true, false, &modulation)?;
+
+        self.lora
+            .prepare_for_tx(&modulation, &mut params, self.tx_power as i32, frame)
+            .await?;
+        self.lora.tx().await?;
+        Ok(())
+    }
+
+    /// Listens for the ack of `header` until `Config::P2P_ACK_TIMEOUT_MS`, frames of other nodes are skipped
+    async fn receive_ack(&mut self, header: &Header) -> Result<Option<(Header, Vec<u8, 256>, PacketStatus)>, P2pRadioError> {
+        let modulation = self.modulation()?;
+        let params = self
+            .lora
+            .create_rx_packet_params(PREAMBLE_LENGTH, false, p2p_frame::MAX_FRAME_SIZE as u8, true, true, &modulation)?;
+        self.lora.prepare_for_rx(RxMode::Continuous, &modulation, &params).await?;
+
+        let timeout = Duration::from_millis(config::Config::P2P_ACK_TIMEOUT_MS);
+        let result = with_timeout(timeout, async {
+            let mut buf = [0u8; p2p_frame::MAX_FRAME_SIZE];
+            loop {
+                let (size, status) = self.lora.rx(&params, &mut buf).await?;
+                match p2p_frame::decode(self.key.expose(), &mut buf[..size as usize]) {
+                    Ok((ack, payload)) if ack.flags & FLAG_ACK != 0 && ack.node_id == header.node_id && ack.seq == header.seq => {
+                        return Ok((ack, Vec::from_slice(payload).unwrap_or_default(), status));
+                    }
+                    Ok(_) => {}
+                    Err(e) => defmt::debug!("Dropping received frame, {:?}", e),
+                }
+            }
+        })
+        .await;
+
+        self.lora.sleep(false).await?;
+
+        match result {
+            Ok(Ok(ack)) => Ok(Some(ack)),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Ok(None),
+        }
+    }
+
+    async fn send(&mut self, options: &UplinkOptions, payload: &[u8]) -> Result<Uplink, P2pRadioError> {
+        let mut flags = 0;
+        if options.confirmed {
+            flags |= FLAG_ACK_REQUEST;
+        }
+        if self.boot {
+            flags |= FLAG_BOOT;
+        }
+
+        if self.seq == self.reserved {
+            return Err(P2pRadioError::Sequence);
+        }
+
+        self.seq = self.seq.wrapping_add(1);
+        let header = Header {
+            flags,
+            node_id: self.node_id,
+            seq: self.seq,
+            port: options.port,
+        };
+
+        let mut frame = [0u8; p2p_frame::MAX_FRAME_SIZE];
+        let size = p2p_frame::encode(self.key.expose(), &header, payload, &mut frame).map_err(P2pRadioError::Frame)?;
+        self.transmit(&frame[..size]).await?;
+
+        let mut uplink = Uplink {
+            fcnt_down: None,
+            acked: false,
+            downlink: None,
+            quality: None,
+            data_rate: self.data_rate(),
+            tx_power: Some(self.tx_power),
+        };
+
+        if !options.confirmed {
+            self.lora.sleep(false).await?;
+            return Ok(uplink);
+        }
+
+        let Some((ack, payload, status)) = self.receive_ack(&header).await? else {
+            return Err(P2pRadioError::NoAck);
+        };
+
+        self.boot = false;
+        uplink.acked = true;
+        uplink.fcnt_down = Some(ack.seq);
+        uplink.quality = Some(LinkQuality {
+            rssi: status.rssi,
+            snr: status.snr.clamp(i8::MIN as i16, i8::MAX as i16) as i8,
+        });
+        if !payload.is_empty() {
+            uplink.downlink = Some(Downlink { port: ack.port, payload });
+        }
+
+        Ok(uplink)
+    }
+
+    /// Data rate index as in EU868, DR0 is SF12 and DR5 is SF7
+    fn data_rate(&self) -> u8 {
+        match self.spreading_factor {
+            SpreadingFactor::_5 | SpreadingFactor::_6 | SpreadingFactor::_7 => 5,
+            SpreadingFactor::_8 => 4,
+            SpreadingFactor::_9 => 3,
+            SpreadingFactor::_10 => 2,
+            SpreadingFactor::_11 => 1,
+            SpreadingFactor::_12 => 0,
+        }
+    }
+}
+
+impl Radio for P2pRadio {
+    type Error = P2pRadioError;
+
+    // There is no join in point-to-point mode, the node id is taken from the low bytes of the flash unique id,
+    // or from the DevEUI or DevAddr if it is unknown. The pre-shared key is not a session, there is nothing to persist.
+    async fn join(&mut self, mode: &JoinMode) -> Result<Option<Session>, Self::Error> {
+        self.node_id = match (self.unique_id, mode) {
+            (Some(unique_id), _) => unique_id as u32,
+            (None, JoinMode::OTAA { deveui, .. }) => {
+                let eui: &[u8] = deveui.as_ref();
+                u32::from_le_bytes([eui[0], eui[1], eui[2], eui[3]])
+            }
+            (None, JoinMode::ABP { devaddr, .. }) => {
+                let addr: &[u8] = devaddr.as_ref();
+                u32::from_le_bytes([addr[0], addr[1], addr[2], addr[3]])
+            }
+        };
+        defmt::info!("Point-to-point node id {=u32:#x}", self.node_id);
+
+        Ok(None)
+    }
+
+    async fn uplink(&mut self, options: &UplinkOptions, payload: &[u8]) -> Result<Uplink, Self::Error> {
+        let mut result = Err(P2pRadioError::NoAck);
+
+        for _ in 0..options.nb_trans.max(1) {
+            result = self.send(options, payload).await;
+            match &result {
+                Ok(_) => break,
+                Err(P2pRadioError::NoAck) => {}
+                Err(_) => break,
+            }
+        }
+
+        result
+    }
+
+    // The receiver keeps no time
+    async fn sync_time(&mut self, _device_time: u32, _token: u8) -> Result<Option<i32>, Self::Error> {
+        Ok(None)
+    }
+
+    async fn link_check(&mut self) -> Result<Option<LinkCheck>, Self::Error> {
+        let options = UplinkOptions {
+            port: config::Config::FPORT_LINK_CHECK,
+            confirmed: true,
+            nb_trans: 1,
+        };
+
+        match self.send(&options, &[]).await {
+            Ok(uplink) => Ok(uplink.quality.map(|quality| LinkCheck {
+                margin: (quality.snr as f32 - demodulation_floor(12 - uplink.data_rate)) as i8,
+                gateways: 1,
+            })),
+            Err(P2pRadioError::NoAck) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn max_payload(&mut self) -> usize {
+        p2p_frame::MAX_PAYLOAD_SIZE
+    }
+
+    fn set_data_rate(&mut self, data_rate: u8) {
+        self.spreading_factor = match data_rate {
+            0 => SpreadingFactor::_12,
+            1 => SpreadingFactor::_11,
+            2 => SpreadingFactor::_10,
+            3 => SpreadingFactor::_9,
+            4 => SpreadingFactor::_8,
+            _ => SpreadingFactor::_7,
+        };
+    }
+
+    fn set_tx_power(&mut self, dbm: i8) {
+        self.tx_power = dbm.min(config::Config::P2P_TX_POWER);
+    }
+
+    // Data rate and power are fixed by the configuration, there is no network to adapt them
+    fn set_adr(&mut self, _enabled: bool) {}
+
+    // The receiver answers only in acks, there is nothing to listen for between uplinks
+    async fn set_class_c(&mut self, _enabled: bool) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn listen(&mut self) -> Result<(), Self::Error> {
+        core::future::pending().await
+    }
+
+    // Multicast is a LoRaWAN feature, the receiver has no groups to send to
+    fn set_multicast_group(&mut self, _id: u8, _group: Option<McGroup>) {}
+
+    fn class_c_channel(&self) -> Option<(u32, u8)> {
+        None
+    }
+
+    // Frames up to the persisted sequence might have been sent before reset, receivers reject them as replays
+    fn restore_sequence(&mut self, sequence: u32) {
+        self.seq = sequence;
+        self.reserved = sequence;
+    }
+
+    fn sequence_reservation(&self) -> Option<u32> {
+        let reserve = config::Config::P2P_SEQUENCE_RESERVE;
+        let left = self.reserved.wrapping_sub(self.seq);
+        (left <= reserve / 2).then(|| self.seq.wrapping_add(reserve))
+    }
+
+    fn reserve_sequence(&mut self, sequence: u32) {
+        self.reserved = sequence;
+    }
+}
@@ -0,0 +1,37 @@
+use embassy_rp::gpio::{Input, Level, Output, Pull};
+use embassy_rp::peripherals::SPI1;
+use embassy_rp::spi::{self, Config, Spi};
+use embassy_time::Delay;
+use embedded_hal_bus::spi::ExclusiveDevice;
+use lora_phy::iv::GenericSx126xInterfaceVariant;
+use lora_phy::mod_params::RadioError;
+use lora_phy::sx126x::{self, Sx126x, TcxoCtrlVoltage};
+use lora_phy::LoRa;
+
+use crate::RadioRes;
+
+/// SX1262 transceiver of the Waveshare Pico-LoRa-SX1262 module
+pub type Sx1262 = Sx126x<
+    ExclusiveDevice<Spi<'static, SPI1, spi::Async>, Output<'static>, Delay>,
+    GenericSx126xInterfaceVariant<Output<'static>, Input<'static>>,
+    sx126x::Sx1262,
+>;
+
+/// Brings up the transceiver, `public_network` selects LoRaWAN public sync word over the private one
+pub async fn init(r: RadioRes, public_network: bool) -> Result<LoRa<Sx1262, Delay>, RadioError> {
+    let nss = Output::new(r.cs, Level::High);
+    let reset = Output::new(r.rst, Level::High);
+    let dio1 = Input::new(r.dio1, Pull::None);
+    let busy = Input::new(r.busy, Pull::None);
+    let spi = Spi::new(r.spi1, r.clk, r.mosi, r.miso, r.dma_ch0, r.dma_ch1, Config::default());
+    let spi_bus = ExclusiveDevice::new(spi, nss, Delay);
+    let sx1262_config = sx126x::Config {
+        chip: sx126x::Sx1262,
+        tcxo_ctrl: Some(TcxoCtrlVoltage::Ctrl1V7),
+        use_dcdc: true,
+        rx_boost: false,
+    };
+
+    let iv = GenericSx126xInterfaceVariant::new(reset, dio1, busy, None, None)?;
+    LoRa::new(Sx126x::new(spi_bus, iv, sx1262_config), public_network, Delay).await
+}
@@ -0,0 +1,52 @@
+/// Key material which must never reach the logs in plain form,
+/// its `defmt::Format` prints only the length and a short fingerprint
+/// which is enough to tell whether two keys match.
+pub struct Secret<const N: usize>([u8; N]);
+
+impl<const N: usize> Secret<N> {
+    pub const fn new(bytes: [u8; N]) -> Self {
+        Self(bytes)
+    }
+
+    /// Copies key bytes out of a slice, panics if the length does not match
+    pub fn from_slice(bytes: &[u8]) -> Self {
+        let mut secret = [0u8; N];
+        secret.copy_from_slice(bytes);
+        Self(secret)
+    }
+
+    /// Raw key bytes, to be handed over only to storage or the LoRaWAN stack
+    pub fn expose(&self) -> &[u8; N] {
+        &self.0
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for Secret<N> {
+    fn from(bytes: [u8; N]) -> Self {
+        Self::new(bytes)
+    }
+}
+
+impl<const N: usize> defmt::Format for Secret<N> {
+    fn format(&self, f: defmt::Formatter) {
+        Redacted(&self.0).format(f)
+    }
+}
+
+/// Borrowed bytes formatted the same way as `Secret`, for values of unknown length
+pub struct Redacted<'a>(pub &'a [u8]);
+
+impl defmt::Format for Redacted<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "<redacted {=usize} bytes, fp {=u16:04x}>", self.0.len(), fingerprint(self.0))
+    }
+}
+
+/// FNV-1a hash folded to 16 bits, too short to narrow down a 128 bit key
+pub fn fingerprint(bytes: &[u8]) -> u16 {
+    let hash = bytes
+        .iter()
+        .fold(0x811c_9dc5u32, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x0100_0193));
+
+    ((hash >> 16) ^ (hash & 0xffff)) as u16
+}
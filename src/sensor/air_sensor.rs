@@ -1,135 +1,112 @@
 use core::result::Result;
 
 use embassy_rp::adc;
-use embassy_rp::i2c::{self, Async};
-use embassy_rp::peripherals::I2C0;
-use embassy_time::Timer;
-
-use crate::sensor::Sensor;
-use crate::{config, AirSensorRes, Irqs};
-
-const SERIAL_NUMBER_COMMAND: u16 = 0x3682;
-const READ_MEASUREMENT_COMMAND: u16 = 0xec05;
-const MEASURE_SINGLE_SHOT_COMMAND: u16 = 0x219d;
-const POWER_DOWN: u16 = 0x36e0;
-const WAKE_UP: u16 = 0x36f6;
-
-#[derive(defmt::Format)]
-pub enum AirSensorError {
-    I2C(i2c::Error),
+use embassy_time::Delay;
+use embedded_hal_async::i2c::I2c;
+use heapless::Vec;
+use sensor_core::scd4x::{self, Scd4x};
+
+use crate::config;
+use crate::sensor::i2c_sensors::I2cSensorError;
+use crate::sensor::{Maintenance, Sensor};
+
+impl From<scd4x::Error> for I2cSensorError {
+    fn from(value: scd4x::Error) -> Self {
+        match value {
+            scd4x::Error::I2c(kind) => Self::I2C(kind),
+            scd4x::Error::SelfTest(status) => Self::SelfTest(status),
+            scd4x::Error::Crc => Self::Crc,
+        }
+    }
 }
 
-pub struct AirSensor {
-    adr: u16,
-    bus: i2c::I2c<'static, I2C0, Async>,
-    powered: bool,
+/// SCD41 CO2, temperature and humidity sensor, powered down between probes
+pub struct AirSensor<I: I2c> {
+    scd4x: Scd4x<I, Delay>,
+    serial_number: Option<u64>,
 }
 
-impl AirSensor {
-    pub fn new(r: AirSensorRes) -> Self {
-        let i2c_0_bus = i2c::I2c::new_async(r.i2c0, r.scl, r.sda, Irqs, i2c::Config::default());
-
+impl<I: I2c> AirSensor<I> {
+    pub fn new(bus: I) -> Self {
         Self {
-            adr: config::Config::I2C_ADDR_AIR_SENSOR,
-            bus: i2c_0_bus,
-            powered: true,
+            scd4x: Scd4x::new(bus, Delay, config::Config::I2C_ADDR_AIR_SENSOR),
+            serial_number: None,
         }
     }
 
-    async fn write(&mut self, command: u16) -> Result<(), i2c::Error> {
-        self.bus.write_async(self.adr, command.to_be_bytes()).await
+    /// Identifies the chip by reading its serial number, sensor is woken up first in case it was left powered down
+    pub async fn identify(bus: &mut I) -> bool {
+        Scd4x::identify(bus, &mut Delay, config::Config::I2C_ADDR_AIR_SENSOR).await
     }
 
-    async fn read(&mut self, buffer: &mut [u8]) -> Result<(), i2c::Error> {
-        self.bus.read_async(self.adr, buffer).await
+    /// Resets all settings stored in the EEPROM and erases the calibration history, sensor has to be awake
+    pub async fn factory_reset(&mut self) -> Result<(), I2cSensorError> {
+        defmt::info!("Performing air sensor factory reset");
+        Ok(self.scd4x.perform_factory_reset().await?)
+    }
+
+    /// Reloads user settings from the EEPROM, sensor has to be awake
+    pub async fn reinit(&mut self) -> Result<(), I2cSensorError> {
+        defmt::info!("Reinitializing air sensor");
+        Ok(self.scd4x.reinit().await?)
     }
 }
 
-impl Sensor<11> for AirSensor {
-    type Error = AirSensorError;
+impl<I: I2c> Sensor<11> for AirSensor<I> {
+    type Error = I2cSensorError;
 
     async fn on(&mut self) -> Result<(), Self::Error> {
-        if self.powered {
+        if self.scd4x.is_powered() {
             return Ok(());
         }
 
-        if let Err(err) = self.write(WAKE_UP).await {
-            return Err(AirSensorError::I2C(err));
+        match self.scd4x.on().await {
+            Ok(()) => {
+                defmt::debug!("Air sensor woke up");
+                Ok(())
+            }
+            Err(err) => {
+                defmt::warn!("Air sensor did not wake up in {=u8} attempts, {:?}", scd4x::WAKE_UP_ATTEMPTS, err);
+                Err(err.into())
+            }
         }
-
-        // wait 30 ms according to spec
-        Timer::after_millis(30).await;
-
-        // todo: verify that device is turned on?
-        // spec recomends to read serial number as test
-
-        Ok(())
     }
 
     async fn off(&mut self) -> Result<(), Self::Error> {
-        if !self.powered {
-            return Ok(());
-        }
-
-        if let Err(err) = self.write(POWER_DOWN).await {
-            return Err(AirSensorError::I2C(err));
-        }
-
-        // wait 1 ms according to spec
-        Timer::after_millis(1).await;
-
-        Ok(())
+        Ok(self.scd4x.off().await?)
     }
 
     async fn verify(&mut self) -> Result<(), Self::Error> {
-        let mut buffer = [0u8; 9];
+        let serial_number = self.scd4x.read_serial_number().await?;
+        self.serial_number = Some(serial_number);
 
-        if let Err(err) = self.write(SERIAL_NUMBER_COMMAND).await {
-            return Err(AirSensorError::I2C(err));
-        }
+        defmt::info!("Air sensor serial number {=u64:#x}", serial_number);
 
-        // wait 1ms according to spec
-        Timer::after_millis(1).await;
+        defmt::info!("Performing air sensor self test");
+        if let Err(err) = self.scd4x.perform_self_test().await {
+            defmt::warn!("Air sensor self test failed, {:?}, reinitializing", err);
 
-        if let Err(err) = self.read(&mut buffer).await {
-            return Err(AirSensorError::I2C(err));
+            self.reinit().await?;
+            return Err(err.into());
         }
 
-        let word0 = u16::from_ne_bytes([buffer[0], buffer[1]]);
-        let word1 = u16::from_ne_bytes([buffer[3], buffer[4]]);
-        let word2 = u16::from_ne_bytes([buffer[6], buffer[7]]);
-        let serial_number: u64 = (u64::from(word0) << 32) | (u64::from(word1) << 16) | u64::from(word2);
-
-        defmt::debug!("Air sensor serial number {=u64}", serial_number);
-
         Ok(())
     }
 
-    async fn probe(&mut self, _adc: &mut adc::Adc<'static, adc::Async>) -> Result<[u8; 11], Self::Error> {
-        if let Err(err) = self.write(MEASURE_SINGLE_SHOT_COMMAND).await {
-            return Err(AirSensorError::I2C(err));
+    async fn maintain(&mut self, operation: Maintenance) -> Result<(), Self::Error> {
+        match operation {
+            Maintenance::FactoryReset => self.factory_reset().await,
+            Maintenance::Reinit => self.reinit().await,
         }
+    }
 
-        // wait 5000ms according to spec
-        Timer::after_millis(5000).await;
-
-        if let Err(err) = self.write(READ_MEASUREMENT_COMMAND).await {
-            return Err(AirSensorError::I2C(err));
-        }
-
-        // wait 1ms according to spec
-        Timer::after_millis(1).await;
-
-        let mut buffer = [0u8; 9];
-        if let Err(err) = self.read(&mut buffer).await {
-            return Err(AirSensorError::I2C(err));
-        }
+    fn serial_number(&self) -> Option<u64> {
+        self.serial_number
+    }
 
-        let bytes_temp = u16::from_be_bytes([buffer[3], buffer[4]]);
-        let temp = bytes_temp as f32 * 175.0f32 / (u16::MAX as f32) - 45.0;
-        let bytes_hum = u16::from_be_bytes([buffer[6], buffer[7]]);
-        let hum = bytes_hum as f32 * 100.0 / (u16::MAX as f32);
-        let co2 = u16::from_be_bytes([buffer[0], buffer[1]]);
+    async fn probe(&mut self, _adc: &mut adc::Adc<'static, adc::Async>) -> Result<Vec<u8, 11>, Self::Error> {
+        let measurement = self.scd4x.measure().await?;
+        let (temp, hum, co2) = (measurement.temperature, measurement.humidity, measurement.co2);
 
         let temp_scl = (temp * 10.0) as i16;
         let hum_scl = (hum * 2.0) as u8;
@@ -149,6 +126,6 @@ impl Sensor<11> for AirSensor {
         buf[9] = (co2 >> 8) as u8; //            - first byte
         buf[10] = co2 as u8; //            - second byte
 
-        Ok(buf)
+        Ok(Vec::from_slice(&buf).unwrap())
     }
 }
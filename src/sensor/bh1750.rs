@@ -0,0 +1,76 @@
+use core::result::Result;
+
+use embassy_rp::adc;
+use embassy_time::Timer;
+use embedded_hal_async::i2c::{Error as _, I2c};
+use heapless::Vec;
+
+use crate::sensor::i2c_sensors::I2cSensorError;
+use crate::sensor::Sensor;
+
+pub const I2C_ADDR: u8 = 0x23;
+
+const POWER_DOWN: u8 = 0x00;
+const POWER_ON: u8 = 0x01;
+const ONE_TIME_HIGH_RES_MODE: u8 = 0x20;
+
+/// Rohm BH1750 ambient light sensor
+pub struct Bh1750<I: I2c> {
+    bus: I,
+}
+
+impl<I: I2c> Bh1750<I> {
+    pub fn new(bus: I) -> Self {
+        Self { bus }
+    }
+
+    async fn write(&mut self, command: u8) -> Result<(), I2cSensorError> {
+        self.bus.write(I2C_ADDR, &[command]).await.map_err(|err| err.kind().into())
+    }
+
+    /// Chip has no id register, acknowledged power on command is the best we can get
+    pub async fn identify(bus: &mut I) -> bool {
+        bus.write(I2C_ADDR, &[POWER_ON]).await.is_ok() && bus.write(I2C_ADDR, &[POWER_DOWN]).await.is_ok()
+    }
+}
+
+impl<I: I2c> Sensor<4> for Bh1750<I> {
+    type Error = I2cSensorError;
+
+    async fn on(&mut self) -> Result<(), Self::Error> {
+        self.write(POWER_ON).await
+    }
+
+    async fn off(&mut self) -> Result<(), Self::Error> {
+        self.write(POWER_DOWN).await
+    }
+
+    async fn verify(&mut self) -> Result<(), Self::Error> {
+        self.write(POWER_ON).await?;
+        self.write(POWER_DOWN).await
+    }
+
+    async fn probe(&mut self, _adc: &mut adc::Adc<'static, adc::Async>) -> Result<Vec<u8, 4>, Self::Error> {
+        self.write(POWER_ON).await?;
+        self.write(ONE_TIME_HIGH_RES_MODE).await?;
+
+        // wait 180ms according to spec, chip powers down after one time measurement
+        Timer::after_millis(180).await;
+
+        let mut buffer = [0u8; 2];
+        self.bus.read(I2C_ADDR, &mut buffer).await.map_err(|err| err.kind())?;
+
+        let lux = u16::from_be_bytes(buffer) as f32 / 1.2;
+        let lux_scl = lux as u16;
+
+        defmt::info!("BH1750 sensor data - lux {=f32}", lux);
+
+        let mut buf = [0u8; 4];
+        buf[0] = 0x08; // channel    - 8 [bh1750]
+        buf[1] = 0x65; // type       - illuminance [2 bytes]
+        buf[2] = (lux_scl >> 8) as u8; //            - first byte
+        buf[3] = lux_scl as u8; //            - second byte
+
+        Ok(Vec::from_slice(&buf).unwrap())
+    }
+}
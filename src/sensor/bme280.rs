@@ -0,0 +1,206 @@
+use core::result::Result;
+
+use embassy_rp::adc;
+use embassy_time::Timer;
+use embedded_hal_async::i2c::{Error as _, I2c};
+use heapless::Vec;
+
+use crate::sensor::i2c_sensors::I2cSensorError;
+use crate::sensor::Sensor;
+
+pub const I2C_ADDR_PRIMARY: u8 = 0x76;
+pub const I2C_ADDR_SECONDARY: u8 = 0x77;
+
+const CHIP_ID_REGISTER: u8 = 0xd0;
+const CALIB_00_REGISTER: u8 = 0x88;
+const CALIB_26_REGISTER: u8 = 0xe1;
+const CTRL_HUM_REGISTER: u8 = 0xf2;
+const CTRL_MEAS_REGISTER: u8 = 0xf4;
+const PRESS_MSB_REGISTER: u8 = 0xf7;
+const CHIP_ID: u8 = 0x60;
+
+// humidity oversampling x1
+const CTRL_HUM: u8 = 0x01;
+// temperature and pressure oversampling x1, forced mode
+const CTRL_MEAS_FORCED: u8 = 0x25;
+
+/// Trimming parameters burned into the chip during production
+struct Calibration {
+    t1: f32,
+    t2: f32,
+    t3: f32,
+    p1: f32,
+    p2: f32,
+    p3: f32,
+    p4: f32,
+    p5: f32,
+    p6: f32,
+    p7: f32,
+    p8: f32,
+    p9: f32,
+    h1: f32,
+    h2: f32,
+    h3: f32,
+    h4: f32,
+    h5: f32,
+    h6: f32,
+}
+
+impl Calibration {
+    fn parse(calib_00: &[u8; 26], calib_26: &[u8; 7]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([calib_00[i], calib_00[i + 1]]) as f32;
+        let i16_at = |i: usize| i16::from_le_bytes([calib_00[i], calib_00[i + 1]]) as f32;
+
+        Self {
+            t1: u16_at(0),
+            t2: i16_at(2),
+            t3: i16_at(4),
+            p1: u16_at(6),
+            p2: i16_at(8),
+            p3: i16_at(10),
+            p4: i16_at(12),
+            p5: i16_at(14),
+            p6: i16_at(16),
+            p7: i16_at(18),
+            p8: i16_at(20),
+            p9: i16_at(22),
+            h1: calib_00[25] as f32,
+            h2: i16::from_le_bytes([calib_26[0], calib_26[1]]) as f32,
+            h3: calib_26[2] as f32,
+            h4: (((calib_26[3] as i8 as i16) << 4) | (calib_26[4] & 0x0f) as i16) as f32,
+            h5: (((calib_26[5] as i8 as i16) << 4) | (calib_26[4] >> 4) as i16) as f32,
+            h6: calib_26[6] as i8 as f32,
+        }
+    }
+}
+
+/// Bosch BME280 temperature, humidity and pressure sensor
+pub struct Bme280<I: I2c> {
+    bus: I,
+    adr: u8,
+    channel: u8,
+    calibration: Option<Calibration>,
+}
+
+impl<I: I2c> Bme280<I> {
+    pub fn new(bus: I, adr: u8) -> Self {
+        let channel = if adr == I2C_ADDR_PRIMARY { 0x06 } else { 0x07 };
+
+        Self {
+            bus,
+            adr,
+            channel,
+            calibration: None,
+        }
+    }
+
+    async fn read_registers(bus: &mut I, adr: u8, register: u8, buffer: &mut [u8]) -> Result<(), I2cSensorError> {
+        bus.write_read(adr, &[register], buffer).await.map_err(|err| err.kind().into())
+    }
+
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), I2cSensorError> {
+        self.bus.write(self.adr, &[register, value]).await.map_err(|err| err.kind().into())
+    }
+
+    /// Identifies the chip by its chip id register, which tells it apart from BMP280
+    pub async fn identify(bus: &mut I, adr: u8) -> bool {
+        let mut chip_id = [0u8; 1];
+
+        matches!(Self::read_registers(bus, adr, CHIP_ID_REGISTER, &mut chip_id).await, Ok(()) if chip_id[0] == CHIP_ID)
+    }
+}
+
+impl<I: I2c> Sensor<11> for Bme280<I> {
+    type Error = I2cSensorError;
+
+    async fn on(&mut self) -> Result<(), Self::Error> {
+        // chip goes to sleep mode after every forced measurement
+        Ok(())
+    }
+
+    async fn off(&mut self) -> Result<(), Self::Error> {
+        // chip goes to sleep mode after every forced measurement
+        Ok(())
+    }
+
+    async fn verify(&mut self) -> Result<(), Self::Error> {
+        let mut calib_00 = [0u8; 26];
+        let mut calib_26 = [0u8; 7];
+
+        Self::read_registers(&mut self.bus, self.adr, CALIB_00_REGISTER, &mut calib_00).await?;
+        Self::read_registers(&mut self.bus, self.adr, CALIB_26_REGISTER, &mut calib_26).await?;
+
+        self.calibration = Some(Calibration::parse(&calib_00, &calib_26));
+
+        Ok(())
+    }
+
+    async fn probe(&mut self, _adc: &mut adc::Adc<'static, adc::Async>) -> Result<Vec<u8, 11>, Self::Error> {
+        if self.calibration.is_none() {
+            self.verify().await?;
+        }
+
+        // ctrl_hum changes become effective only after writing ctrl_meas
+        self.write_register(CTRL_HUM_REGISTER, CTRL_HUM).await?;
+        self.write_register(CTRL_MEAS_REGISTER, CTRL_MEAS_FORCED).await?;
+
+        // wait 10ms, max measurement time with x1 oversampling is 9.3ms according to spec
+        Timer::after_millis(10).await;
+
+        let mut buffer = [0u8; 8];
+        Self::read_registers(&mut self.bus, self.adr, PRESS_MSB_REGISTER, &mut buffer).await?;
+
+        let adc_p = (((buffer[0] as u32) << 12) | ((buffer[1] as u32) << 4) | ((buffer[2] as u32) >> 4)) as f32;
+        let adc_t = (((buffer[3] as u32) << 12) | ((buffer[4] as u32) << 4) | ((buffer[5] as u32) >> 4)) as f32;
+        let adc_h = (((buffer[6] as u32) << 8) | (buffer[7] as u32)) as f32;
+
+        // floating point compensation formulas according to spec
+        let c = self.calibration.as_ref().unwrap();
+
+        let var1 = (adc_t / 16384.0 - c.t1 / 1024.0) * c.t2;
+        let var2 = (adc_t / 131072.0 - c.t1 / 8192.0) * (adc_t / 131072.0 - c.t1 / 8192.0) * c.t3;
+        let t_fine = var1 + var2;
+        let temp = t_fine / 5120.0;
+
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * c.p6 / 32768.0;
+        var2 += var1 * c.p5 * 2.0;
+        var2 = var2 / 4.0 + c.p4 * 65536.0;
+        var1 = (c.p3 * var1 * var1 / 524288.0 + c.p2 * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * c.p1;
+        let pressure = if var1 == 0.0 {
+            0.0
+        } else {
+            let p = (1048576.0 - adc_p - var2 / 4096.0) * 6250.0 / var1;
+            let var1 = c.p9 * p * p / 2147483648.0;
+            let var2 = p * c.p8 / 32768.0;
+            p + (var1 + var2 + c.p7) / 16.0
+        };
+
+        let h = t_fine - 76800.0;
+        let h =
+            (adc_h - (c.h4 * 64.0 + c.h5 / 16384.0 * h)) * (c.h2 / 65536.0 * (1.0 + c.h6 / 67108864.0 * h * (1.0 + c.h3 / 67108864.0 * h)));
+        let hum = (h * (1.0 - c.h1 * h / 524288.0)).clamp(0.0, 100.0);
+
+        let temp_scl = (temp * 10.0) as i16;
+        let hum_scl = (hum * 2.0) as u8;
+        let pressure_scl = (pressure / 10.0) as u16;
+
+        defmt::info!("BME280 sensor data - tmp {=f32}°C hum {=f32}% prs {=f32}Pa", temp, hum, pressure);
+
+        let mut buf = [0u8; 11];
+        buf[0] = self.channel; // channel    - 6/7 [bme280]
+        buf[1] = 0x67; // type       - temperature [2 bytes]
+        buf[2] = (temp_scl >> 8) as u8; //            - first byte
+        buf[3] = temp_scl as u8; //            - second byte
+        buf[4] = self.channel; // channel    - 6/7 [bme280]
+        buf[5] = 0x68; // type       - humidity [1 byte]
+        buf[6] = hum_scl; //            - first byte
+        buf[7] = self.channel; // channel    - 6/7 [bme280]
+        buf[8] = 0x73; // type       - barometer [2 bytes]
+        buf[9] = (pressure_scl >> 8) as u8; //            - first byte
+        buf[10] = pressure_scl as u8; //            - second byte
+
+        Ok(Vec::from_slice(&buf).unwrap())
+    }
+}
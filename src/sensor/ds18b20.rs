@@ -0,0 +1,157 @@
+use core::result::Result;
+
+use embassy_rp::adc;
+use embassy_time::Timer;
+use heapless::Vec;
+
+use crate::bus::one_wire::{crc8, OneWire};
+use crate::config;
+use crate::sensor::Sensor;
+
+/// Maximum amount of probes on the bus, bounded by the uplink payload size
+pub const MAX_PROBES: usize = 4;
+/// Sum of payload sizes of all probes
+pub const PAYLOAD_SIZE: usize = MAX_PROBES * 4;
+
+const FAMILY_CODE: u8 = 0x28;
+
+const MATCH_ROM: u8 = 0x55;
+const SKIP_ROM: u8 = 0xcc;
+const CONVERT_T: u8 = 0x44;
+const WRITE_SCRATCHPAD: u8 = 0x4e;
+const READ_SCRATCHPAD: u8 = 0xbe;
+const READ_POWER_SUPPLY: u8 = 0xb4;
+
+#[derive(defmt::Format)]
+pub enum Ds18b20Error {
+    NoPresence,
+    Crc,
+}
+
+/// Maxim DS18B20 temperature probes sharing one 1-Wire bus
+pub struct Ds18b20 {
+    bus: OneWire,
+    roms: Vec<[u8; 8], MAX_PROBES>,
+    parasitic: bool,
+}
+
+impl Ds18b20 {
+    pub fn new(bus: OneWire) -> Self {
+        Self {
+            bus,
+            roms: Vec::new(),
+            parasitic: false,
+        }
+    }
+
+    /// Resets the bus and addresses either a single probe or all of them
+    async fn select(&mut self, rom: Option<&[u8; 8]>) -> Result<(), Ds18b20Error> {
+        if !self.bus.reset().await {
+            return Err(Ds18b20Error::NoPresence);
+        }
+
+        match rom {
+            Some(rom) => {
+                self.bus.write_bytes(&[MATCH_ROM]).await;
+                self.bus.write_bytes(rom).await;
+            }
+            None => self.bus.write_bytes(&[SKIP_ROM]).await,
+        }
+
+        Ok(())
+    }
+
+    /// Conversion time halves with every bit of resolution dropped, 750ms for 12 bits
+    fn conversion_time_ms() -> u64 {
+        750 >> (12 - config::Config::SOIL_TEMPERATURE_RESOLUTION.clamp(9, 12))
+    }
+}
+
+impl Sensor<PAYLOAD_SIZE> for Ds18b20 {
+    type Error = Ds18b20Error;
+
+    async fn on(&mut self) -> Result<(), Self::Error> {
+        // probes draw power from the bus
+        Ok(())
+    }
+
+    async fn off(&mut self) -> Result<(), Self::Error> {
+        // probes draw power from the bus
+        Ok(())
+    }
+
+    async fn verify(&mut self) -> Result<(), Self::Error> {
+        self.roms.clear();
+        self.bus.search(&mut self.roms).await;
+        self.roms.retain(|rom| rom[0] == FAMILY_CODE);
+
+        for rom in self.roms.iter() {
+            defmt::info!("Discovered DS18B20 {=[u8]:#x}", rom);
+        }
+
+        if self.roms.is_empty() {
+            return Err(Ds18b20Error::NoPresence);
+        }
+
+        // any parasitic powered probe pulls the bus low while reading power supply
+        self.select(None).await?;
+        self.bus.write_bytes(&[READ_POWER_SUPPLY]).await;
+        self.parasitic = !self.bus.read_bit().await;
+
+        defmt::info!("DS18B20 parasitic power {=bool}", self.parasitic);
+
+        // alarm thresholds are not used, resolution goes to bits 5 and 6 of the configuration register
+        let resolution = config::Config::SOIL_TEMPERATURE_RESOLUTION.clamp(9, 12);
+        let configuration = ((resolution - 9) << 5) | 0x1f;
+        self.select(None).await?;
+        self.bus.write_bytes(&[WRITE_SCRATCHPAD, 0x00, 0x00, configuration]).await;
+
+        Ok(())
+    }
+
+    async fn probe(&mut self, _adc: &mut adc::Adc<'static, adc::Async>) -> Result<Vec<u8, PAYLOAD_SIZE>, Self::Error> {
+        let mut buf: Vec<u8, PAYLOAD_SIZE> = Vec::new();
+
+        if self.roms.is_empty() {
+            return Ok(buf);
+        }
+
+        // all probes convert at once
+        self.select(None).await?;
+        self.bus.write_bytes(&[CONVERT_T]).await;
+        if self.parasitic {
+            self.bus.strong_pull_up(true);
+        }
+        Timer::after_millis(Self::conversion_time_ms()).await;
+        if self.parasitic {
+            self.bus.strong_pull_up(false);
+        }
+
+        for i in 0..self.roms.len() {
+            let rom = self.roms[i];
+            let mut scratchpad = [0u8; 9];
+
+            self.select(Some(&rom)).await?;
+            self.bus.write_bytes(&[READ_SCRATCHPAD]).await;
+            self.bus.read_bytes(&mut scratchpad).await;
+
+            if crc8(&scratchpad[..8]) != scratchpad[8] {
+                defmt::error!("DS18B20 {=[u8]:#x} scratchpad crc mismatch", rom);
+                return Err(Ds18b20Error::Crc);
+            }
+
+            let temp = i16::from_le_bytes([scratchpad[0], scratchpad[1]]) as f32 / 16.0;
+            let temp_scl = (temp * 10.0) as i16;
+            let channel = config::Config::SOIL_TEMPERATURE_CHANNEL + i as u8;
+
+            defmt::info!("DS18B20 {=usize} sensor data - tmp {=f32}°C", i, temp);
+
+            buf.push(channel).unwrap(); // channel    - n [ds18b20]
+            buf.push(0x67).unwrap(); // type       - temperature [2 bytes]
+            buf.push((temp_scl >> 8) as u8).unwrap(); //            - first byte
+            buf.push(temp_scl as u8).unwrap(); //            - second byte
+        }
+
+        Ok(buf)
+    }
+}
@@ -0,0 +1,236 @@
+use core::result::Result;
+
+use embassy_rp::adc;
+use embedded_hal_async::i2c::{ErrorKind, I2c};
+use heapless::Vec;
+
+use crate::config;
+use crate::sensor::air_sensor::AirSensor;
+use crate::sensor::bh1750::{self, Bh1750};
+use crate::sensor::bme280::{self, Bme280};
+use crate::sensor::sht4x::{self, Sht4x};
+use crate::sensor::veml7700::{self, Veml7700};
+use crate::sensor::{Maintenance, Sensor};
+
+/// Sum of payload sizes of all supported chips
+pub const PAYLOAD_SIZE: usize = 48;
+
+const MAX_SENSORS: usize = 6;
+
+/// Error of any chip on the shared bus
+#[derive(defmt::Format)]
+pub enum I2cSensorError {
+    I2C(ErrorKind),
+    Crc,
+    SelfTest(u16), // status reported by a chip with an on-chip self test
+}
+
+impl From<ErrorKind> for I2cSensorError {
+    fn from(value: ErrorKind) -> Self {
+        Self::I2C(value)
+    }
+}
+
+/// Chips which can be discovered on the bus, discriminant is the bit reported in the device info message
+#[derive(defmt::Format, Clone, Copy, PartialEq)]
+pub enum I2cChip {
+    Scd4x = 0x01,
+    Sht4x = 0x02,
+    Bme280Primary = 0x04,
+    Bme280Secondary = 0x08,
+    Bh1750 = 0x10,
+    Veml7700 = 0x20,
+}
+
+impl I2cChip {
+    const ALL: [I2cChip; MAX_SENSORS] = [
+        I2cChip::Scd4x,
+        I2cChip::Sht4x,
+        I2cChip::Bme280Primary,
+        I2cChip::Bme280Secondary,
+        I2cChip::Bh1750,
+        I2cChip::Veml7700,
+    ];
+
+    pub fn address(&self) -> u8 {
+        match self {
+            I2cChip::Scd4x => config::Config::I2C_ADDR_AIR_SENSOR,
+            I2cChip::Sht4x => sht4x::I2C_ADDR,
+            I2cChip::Bme280Primary => bme280::I2C_ADDR_PRIMARY,
+            I2cChip::Bme280Secondary => bme280::I2C_ADDR_SECONDARY,
+            I2cChip::Bh1750 => bh1750::I2C_ADDR,
+            I2cChip::Veml7700 => veml7700::I2C_ADDR,
+        }
+    }
+
+    async fn identify<I: I2c>(&self, bus: &mut I) -> bool {
+        match self {
+            I2cChip::Scd4x => AirSensor::identify(bus).await,
+            I2cChip::Sht4x => Sht4x::identify(bus).await,
+            I2cChip::Bme280Primary | I2cChip::Bme280Secondary => Bme280::identify(bus, self.address()).await,
+            I2cChip::Bh1750 => Bh1750::identify(bus).await,
+            I2cChip::Veml7700 => Veml7700::identify(bus).await,
+        }
+    }
+}
+
+enum I2cSensor<I: I2c> {
+    Scd4x(AirSensor<I>),
+    Sht4x(Sht4x<I>),
+    Bme280(Bme280<I>),
+    Bh1750(Bh1750<I>),
+    Veml7700(Veml7700<I>),
+}
+
+impl<I: I2c> I2cSensor<I> {
+    fn new(chip: I2cChip, bus: I) -> Self {
+        match chip {
+            I2cChip::Scd4x => Self::Scd4x(AirSensor::new(bus)),
+            I2cChip::Sht4x => Self::Sht4x(Sht4x::new(bus)),
+            I2cChip::Bme280Primary | I2cChip::Bme280Secondary => Self::Bme280(Bme280::new(bus, chip.address())),
+            I2cChip::Bh1750 => Self::Bh1750(Bh1750::new(bus)),
+            I2cChip::Veml7700 => Self::Veml7700(Veml7700::new(bus)),
+        }
+    }
+
+    async fn on(&mut self) -> Result<(), I2cSensorError> {
+        match self {
+            Self::Scd4x(sensor) => sensor.on().await,
+            Self::Sht4x(sensor) => sensor.on().await,
+            Self::Bme280(sensor) => sensor.on().await,
+            Self::Bh1750(sensor) => sensor.on().await,
+            Self::Veml7700(sensor) => sensor.on().await,
+        }
+    }
+
+    async fn off(&mut self) -> Result<(), I2cSensorError> {
+        match self {
+            Self::Scd4x(sensor) => sensor.off().await,
+            Self::Sht4x(sensor) => sensor.off().await,
+            Self::Bme280(sensor) => sensor.off().await,
+            Self::Bh1750(sensor) => sensor.off().await,
+            Self::Veml7700(sensor) => sensor.off().await,
+        }
+    }
+
+    async fn verify(&mut self) -> Result<(), I2cSensorError> {
+        match self {
+            Self::Scd4x(sensor) => sensor.verify().await,
+            Self::Sht4x(sensor) => sensor.verify().await,
+            Self::Bme280(sensor) => sensor.verify().await,
+            Self::Bh1750(sensor) => sensor.verify().await,
+            Self::Veml7700(sensor) => sensor.verify().await,
+        }
+    }
+
+    async fn probe(&mut self, adc: &mut adc::Adc<'static, adc::Async>, payload: &mut Vec<u8, PAYLOAD_SIZE>) -> Result<(), I2cSensorError> {
+        match self {
+            Self::Scd4x(sensor) => payload.extend_from_slice(&sensor.probe(adc).await?).unwrap(),
+            Self::Sht4x(sensor) => payload.extend_from_slice(&sensor.probe(adc).await?).unwrap(),
+            Self::Bme280(sensor) => payload.extend_from_slice(&sensor.probe(adc).await?).unwrap(),
+            Self::Bh1750(sensor) => payload.extend_from_slice(&sensor.probe(adc).await?).unwrap(),
+            Self::Veml7700(sensor) => payload.extend_from_slice(&sensor.probe(adc).await?).unwrap(),
+        }
+
+        Ok(())
+    }
+}
+
+/// Set of I2C sensors discovered on the shared bus during verify.
+/// Every discovered chip gets its own bus device created by `new_device`.
+pub struct I2cSensors<I: I2c, F: Fn() -> I> {
+    new_device: F,
+    sensors: Vec<I2cSensor<I>, MAX_SENSORS>,
+    detected: u8,
+}
+
+impl<I: I2c, F: Fn() -> I> I2cSensors<I, F> {
+    pub fn new(new_device: F) -> Self {
+        Self {
+            new_device,
+            sensors: Vec::new(),
+            detected: 0,
+        }
+    }
+
+    /// Probes known addresses and instantiates drivers for identified chips
+    async fn discover(&mut self) {
+        let mut bus = (self.new_device)();
+
+        self.sensors.clear();
+        self.detected = 0;
+
+        for chip in I2cChip::ALL {
+            if chip.identify(&mut bus).await {
+                defmt::info!("Discovered {:?} at address {=u8:#x}", chip, chip.address());
+
+                self.detected |= chip as u8;
+                let _ = self.sensors.push(I2cSensor::new(chip, (self.new_device)()));
+            }
+        }
+    }
+}
+
+impl<I: I2c, F: Fn() -> I> Sensor<PAYLOAD_SIZE> for I2cSensors<I, F> {
+    type Error = I2cSensorError;
+
+    async fn on(&mut self) -> Result<(), Self::Error> {
+        for sensor in self.sensors.iter_mut() {
+            sensor.on().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn off(&mut self) -> Result<(), Self::Error> {
+        for sensor in self.sensors.iter_mut() {
+            sensor.off().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn verify(&mut self) -> Result<(), Self::Error> {
+        self.discover().await;
+
+        let mut result = Ok(());
+        for sensor in self.sensors.iter_mut() {
+            if let Err(err) = sensor.verify().await {
+                result = Err(err);
+            }
+        }
+
+        result
+    }
+
+    fn serial_number(&self) -> Option<u64> {
+        self.sensors.iter().find_map(|sensor| match sensor {
+            I2cSensor::Scd4x(sensor) => sensor.serial_number(),
+            _ => None,
+        })
+    }
+
+    fn detected(&self) -> u8 {
+        self.detected
+    }
+
+    async fn maintain(&mut self, operation: Maintenance) -> Result<(), Self::Error> {
+        for sensor in self.sensors.iter_mut() {
+            if let I2cSensor::Scd4x(sensor) = sensor {
+                sensor.maintain(operation).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn probe(&mut self, adc: &mut adc::Adc<'static, adc::Async>) -> Result<Vec<u8, PAYLOAD_SIZE>, Self::Error> {
+        let mut payload = Vec::new();
+
+        for sensor in self.sensors.iter_mut() {
+            sensor.probe(adc, &mut payload).await?;
+        }
+
+        Ok(payload)
+    }
+}
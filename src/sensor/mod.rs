@@ -1,8 +1,35 @@
 use embassy_rp::adc::{self, Async};
+use heapless::Vec;
 
 pub mod air_sensor;
+pub mod bh1750;
+pub mod bme280;
+pub mod ds18b20;
+pub mod i2c_sensors;
+pub mod sht4x;
 pub mod soil_sensor;
 pub mod system_sensor;
+pub mod veml7700;
+
+/// Reference point recorded while calibrating a sensor
+#[derive(defmt::Format, Clone, Copy)]
+pub enum CalibrationPoint {
+    /// Sensor exposed to the lowest value, e.g. soil probe in the air
+    Dry,
+    /// Sensor exposed to the highest value, e.g. soil probe in water
+    Wet,
+    /// Sensor exposed to a known reference value in percent
+    Reference(u8),
+}
+
+/// Maintenance operation of a sensor requested by the network
+#[derive(defmt::Format, Clone, Copy)]
+pub enum Maintenance {
+    /// Restore factory settings, e.g. erase the calibration history of the air sensor
+    FactoryReset,
+    /// Reload the stored settings
+    Reinit,
+}
 
 /// Trait to describe generic functionality of a sensor.
 /// In general we want to be able to gather environmental data in form of probing
@@ -10,6 +37,9 @@ pub mod system_sensor;
 ///
 /// For example a soil sensor should be turned off after probing otherwise
 /// constant power will accelerate oxidation process and hence limit the lifetime of the sensor.
+///
+/// `PAYLOAD_SIZE` is the maximum size of the encoded payload, sensors with hardware
+/// discovered at runtime might produce less.
 pub trait Sensor<const PAYLOAD_SIZE: usize> {
     /// Error type representation, left up to the implementor
     type Error;
@@ -23,6 +53,47 @@ pub trait Sensor<const PAYLOAD_SIZE: usize> {
     /// Async method to verify device
     async fn verify(&mut self) -> Result<(), Self::Error>;
 
+    /// Serial number of the device, available after successful verify
+    fn serial_number(&self) -> Option<u64> {
+        None
+    }
+
+    /// Bitmask of detected chips, for sensors which discover their hardware during verify
+    fn detected(&self) -> u8 {
+        0
+    }
+
+    /// Whether a probe crossed an alarm threshold, e.g. low battery, raised once per crossing until cleared
+    fn alarm(&self) -> bool {
+        false
+    }
+
+    /// Clears the raised alarm once the network acknowledged its report
+    fn clear_alarm(&mut self) {}
+
+    /// Amount of individually calibrated probes attached to the sensor
+    fn probes(&self) -> usize {
+        1
+    }
+
+    /// Async method to record a calibration reference point of a probe, sensors without calibration ignore it
+    async fn calibrate(&mut self, _adc: &mut adc::Adc<'static, Async>, _probe: usize, _point: CalibrationPoint) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Async method to run a maintenance operation, sensors without maintenance ignore it
+    async fn maintain(&mut self, _operation: Maintenance) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Restore calibration of a probe previously persisted in the storage
+    fn restore_calibration(&mut self, _probe: usize, _data: &[u8]) {}
+
+    /// Serialize calibration of a probe to be persisted in the storage, returns amount of written bytes
+    fn calibration(&self, _probe: usize, _buf: &mut [u8]) -> usize {
+        0
+    }
+
     /// Async method to probe the environment and gather data, response must be encoded thru Cayenne LPP codec
-    async fn probe(&mut self, adc: &mut adc::Adc<'static, Async>) -> Result<[u8; PAYLOAD_SIZE], Self::Error>;
+    async fn probe(&mut self, adc: &mut adc::Adc<'static, Async>) -> Result<Vec<u8, PAYLOAD_SIZE>, Self::Error>;
 }
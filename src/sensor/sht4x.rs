@@ -0,0 +1,109 @@
+use core::result::Result;
+
+use embassy_rp::adc;
+use embassy_time::Timer;
+use embedded_hal_async::i2c::{Error as _, I2c};
+use heapless::Vec;
+use sensor_core::sensirion;
+
+use crate::sensor::i2c_sensors::I2cSensorError;
+use crate::sensor::Sensor;
+
+pub const I2C_ADDR: u8 = 0x44;
+
+const MEASURE_HIGH_PRECISION_COMMAND: u8 = 0xfd;
+const SERIAL_NUMBER_COMMAND: u8 = 0x89;
+
+/// Sensirion SHT4x temperature and humidity sensor
+pub struct Sht4x<I: I2c> {
+    bus: I,
+    serial_number: Option<u64>,
+}
+
+impl<I: I2c> Sht4x<I> {
+    pub fn new(bus: I) -> Self {
+        Self { bus, serial_number: None }
+    }
+
+    /// Sends a command and reads back two crc protected words
+    async fn command(&mut self, command: u8, delay_ms: u64) -> Result<(u16, u16), I2cSensorError> {
+        let mut buffer = [0u8; 6];
+
+        self.bus.write(I2C_ADDR, &[command]).await.map_err(|err| err.kind())?;
+        Timer::after_millis(delay_ms).await;
+        self.bus.read(I2C_ADDR, &mut buffer).await.map_err(|err| err.kind())?;
+
+        let [word0, word1] = sensirion::words(&buffer).ok_or(I2cSensorError::Crc)?;
+
+        Ok((word0, word1))
+    }
+
+    /// Identifies the chip by reading its serial number
+    pub async fn identify(bus: &mut I) -> bool {
+        let mut buffer = [0u8; 6];
+
+        if bus.write(I2C_ADDR, &[SERIAL_NUMBER_COMMAND]).await.is_err() {
+            return false;
+        }
+
+        // wait 1ms according to spec
+        Timer::after_millis(1).await;
+
+        match bus.read(I2C_ADDR, &mut buffer).await {
+            Ok(()) => sensirion::words::<2>(&buffer).is_some(),
+            Err(_) => false,
+        }
+    }
+}
+
+impl<I: I2c> Sensor<7> for Sht4x<I> {
+    type Error = I2cSensorError;
+
+    async fn on(&mut self) -> Result<(), Self::Error> {
+        // sensor goes to idle after every measurement
+        Ok(())
+    }
+
+    async fn off(&mut self) -> Result<(), Self::Error> {
+        // sensor goes to idle after every measurement
+        Ok(())
+    }
+
+    async fn verify(&mut self) -> Result<(), Self::Error> {
+        let (word0, word1) = self.command(SERIAL_NUMBER_COMMAND, 1).await?;
+        let serial_number = (u64::from(word0) << 16) | u64::from(word1);
+        self.serial_number = Some(serial_number);
+
+        defmt::info!("SHT4x serial number {=u64:#x}", serial_number);
+
+        Ok(())
+    }
+
+    fn serial_number(&self) -> Option<u64> {
+        self.serial_number
+    }
+
+    async fn probe(&mut self, _adc: &mut adc::Adc<'static, adc::Async>) -> Result<Vec<u8, 7>, Self::Error> {
+        // wait 10ms according to spec
+        let (bytes_temp, bytes_hum) = self.command(MEASURE_HIGH_PRECISION_COMMAND, 10).await?;
+
+        let temp = bytes_temp as f32 * 175.0 / (u16::MAX as f32) - 45.0;
+        let hum = (bytes_hum as f32 * 125.0 / (u16::MAX as f32) - 6.0).clamp(0.0, 100.0);
+
+        let temp_scl = (temp * 10.0) as i16;
+        let hum_scl = (hum * 2.0) as u8;
+
+        defmt::info!("SHT4x sensor data - tmp {=f32}°C hum {=f32}%", temp, hum);
+
+        let mut buf = [0u8; 7];
+        buf[0] = 0x05; // channel    - 5 [sht4x]
+        buf[1] = 0x67; // type       - temperature [2 bytes]
+        buf[2] = (temp_scl >> 8) as u8; //            - first byte
+        buf[3] = temp_scl as u8; //            - second byte
+        buf[4] = 0x05; // channel    - 5 [sht4x]
+        buf[5] = 0x68; // type       - humidity [1 byte]
+        buf[6] = hum_scl; //            - first byte
+
+        Ok(Vec::from_slice(&buf).unwrap())
+    }
+}
@@ -2,68 +2,419 @@ use core::result::Result;
 
 use embassy_rp::adc::{self};
 use embassy_rp::gpio::{self, Level, Pull};
+use embassy_rp::peripherals::DMA_CH2;
+use embassy_rp::Peri;
+use embassy_time::Timer;
+use embedded_hal_async::i2c::{Error as _, ErrorKind, I2c};
+use heapless::Vec;
+use sensor_core::filter::{self, Estimate};
 
-use crate::sensor::Sensor;
-use crate::SoilSensorRes;
+use crate::config::{SoilInput, SoilPower, SoilProbeKind};
+use crate::sensor::{CalibrationPoint, Sensor};
+use crate::{config, SoilSensorRes};
+
+/// Maximum amount of probes, bounded by the uplink payload size
+pub const MAX_PROBES: usize = 4;
+/// Payload size of a single probe, moisture, raw reading, spread and temperature
+const PROBE_PAYLOAD_SIZE: usize = 15;
+/// Sum of payload sizes of all probes
+pub const PAYLOAD_SIZE: usize = MAX_PROBES * PROBE_PAYLOAD_SIZE;
+
+const MAX_CURVE_POINTS: usize = 4;
+const UNSET: u16 = u16::MAX;
+
+// seesaw registers, module base followed by function
+const SEESAW_STATUS_TEMP: [u8; 2] = [0x00, 0x04];
+const SEESAW_TOUCH_CHANNEL_OFFSET: [u8; 2] = [0x0f, 0x10];
 
 #[derive(defmt::Format)]
 pub enum SoilSensorError {
     Adc(adc::Error),
+    Calibration,
+    NoSamples,
+    NoProbe,
+    I2C(ErrorKind),
+}
+
+impl From<ErrorKind> for SoilSensorError {
+    fn from(value: ErrorKind) -> Self {
+        Self::I2C(value)
+    }
 }
 
-pub struct SoilSensor {
-    pwr: gpio::Output<'static>,
-    sig: adc::Channel<'static>,
+/// Maps raw adc readings to moisture percentage thru a piecewise linear curve.
+/// Curve always spans from dry (0%) to wet (100%) reference points,
+/// optional intermediate points refine it for non linear probes.
+#[derive(Default)]
+pub struct SoilCalibration {
+    dry: Option<u16>,
+    wet: Option<u16>,
+    points: Vec<(u16, u8), MAX_CURVE_POINTS>,
 }
 
-impl SoilSensor {
-    pub fn new(r: SoilSensorRes) -> Self {
-        let pwr = gpio::Output::new(r.pwr, Level::Low);
-        let sig = adc::Channel::new_pin(r.sig, Pull::None);
+impl SoilCalibration {
+    /// Serialized size, dry and wet points followed by curve points count and the points
+    pub const SIZE: usize = 5 + MAX_CURVE_POINTS * 3;
+
+    /// Typical response of the probe kind, used until the probe is calibrated
+    fn typical(kind: SoilProbeKind) -> Self {
+        let (dry, wet) = match kind {
+            SoilProbeKind::Resistive => (0, 3500),
+            // capacitive probe output drops with moisture
+            SoilProbeKind::Capacitive => (2800, 1300),
+            SoilProbeKind::Seesaw(_) => (300, 1000),
+        };
+
+        Self {
+            dry: Some(dry),
+            wet: Some(wet),
+            points: Vec::new(),
+        }
+    }
+
+    pub fn moisture(&self, adc_raw: u16) -> Option<f32> {
+        let (dry, wet) = (self.dry?, self.wet?);
+        if dry == wet {
+            return None;
+        }
+
+        let mut curve: Vec<(f32, f32), { MAX_CURVE_POINTS + 2 }> = Vec::new();
+        curve.push((dry as f32, 0.0)).unwrap();
+        curve.push((wet as f32, 100.0)).unwrap();
+        for (raw, percentage) in self.points.iter() {
+            curve.push((*raw as f32, *percentage as f32)).unwrap();
+        }
+        curve.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+        let raw = adc_raw as f32;
+        let (first, last) = (curve[0], curve[curve.len() - 1]);
+        if raw <= first.0 {
+            return Some(first.1);
+        }
+        if raw >= last.0 {
+            return Some(last.1);
+        }
+
+        curve
+            .windows(2)
+            .find(|segment| raw >= segment[0].0 && raw <= segment[1].0 && segment[0].0 != segment[1].0)
+            .map(|segment| segment[0].1 + (raw - segment[0].0) * (segment[1].1 - segment[0].1) / (segment[1].0 - segment[0].0))
+    }
+
+    fn record(&mut self, point: CalibrationPoint, adc_raw: u16) -> Result<(), SoilSensorError> {
+        match point {
+            CalibrationPoint::Dry => self.dry = Some(adc_raw),
+            CalibrationPoint::Wet => self.wet = Some(adc_raw),
+            CalibrationPoint::Reference(percentage) => {
+                self.points.retain(|(_, p)| *p != percentage);
+                self.points.push((adc_raw, percentage)).map_err(|_| SoilSensorError::Calibration)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn serialize(&self, buf: &mut [u8]) -> usize {
+        let dry = self.dry.unwrap_or(UNSET).to_be_bytes();
+        let wet = self.wet.unwrap_or(UNSET).to_be_bytes();
+
+        buf[0..2].copy_from_slice(&dry);
+        buf[2..4].copy_from_slice(&wet);
+        buf[4] = self.points.len() as u8;
+        for (i, (raw, percentage)) in self.points.iter().enumerate() {
+            buf[5 + i * 3..7 + i * 3].copy_from_slice(&raw.to_be_bytes());
+            buf[7 + i * 3] = *percentage;
+        }
+
+        5 + self.points.len() * 3
+    }
+
+    fn deserialize(data: &[u8]) -> Option<Self> {
+        if data.len() < 5 {
+            return None;
+        }
+
+        let optional = |value: u16| if value == UNSET { None } else { Some(value) };
+        let count = (data[4] as usize).min(MAX_CURVE_POINTS);
+        let mut points = Vec::new();
+        for point in data[5..].chunks_exact(3).take(count) {
+            let _ = points.push((u16::from_be_bytes([point[0], point[1]]), point[2]));
+        }
+
+        Some(Self {
+            dry: optional(u16::from_be_bytes([data[0], data[1]])),
+            wet: optional(u16::from_be_bytes([data[2], data[3]])),
+            points,
+        })
+    }
+}
+
+/// Soil moisture probes wired either directly to the free adc pins or thru an analog multiplexer
+/// (CD4051 with 3 select lines, 74HC4067 with 4 select lines) connected to one of the adc pins,
+/// or Adafruit STEMMA seesaw probes on the shared I2C bus.
+/// Probe count, kind and wiring come from `config::Config::SOIL_PROBES`.
+pub struct SoilSensor<I: I2c> {
+    pwr: [gpio::Output<'static>; 2], // excitation, might be shared among probes
+    sig: [adc::Channel<'static>; 2], // adc1 and adc2 inputs
+    sel: [gpio::Output<'static>; 4], // multiplexer select lines
+    dma: Peri<'static, DMA_CH2>,
+    bus: I,
+    calibration: Vec<SoilCalibration, MAX_PROBES>,
+}
+
+impl<I: I2c> SoilSensor<I> {
+    pub fn new(r: SoilSensorRes, bus: I) -> Self {
+        let pwr = [gpio::Output::new(r.pwr, Level::Low), gpio::Output::new(r.pwr2, Level::Low)];
+        let sig = [adc::Channel::new_pin(r.sig, Pull::None), adc::Channel::new_pin(r.sig2, Pull::None)];
+        let sel = [
+            gpio::Output::new(r.sel0, Level::Low),
+            gpio::Output::new(r.sel1, Level::Low),
+            gpio::Output::new(r.sel2, Level::Low),
+            gpio::Output::new(r.sel3, Level::Low),
+        ];
+        let calibration = config::Config::SOIL_PROBES
+            .iter()
+            .take(MAX_PROBES)
+            .map(|probe| SoilCalibration::typical(probe.kind))
+            .collect();
+
+        Self {
+            pwr,
+            sig,
+            sel,
+            dma: r.dma,
+            bus,
+            calibration,
+        }
+    }
+
+    fn power_index(power: SoilPower) -> usize {
+        match power {
+            SoilPower::Gpio22 => 0,
+            SoilPower::Gpio21 => 1,
+        }
+    }
+
+    /// Routes the probe to an adc input, returns index of the input
+    async fn select(&mut self, input: SoilInput) -> usize {
+        match input {
+            SoilInput::Adc1 => 0,
+            SoilInput::Adc2 => 1,
+            SoilInput::Mux(channel) => {
+                for (line, sel) in self.sel.iter_mut().take(config::Config::SOIL_MUX_SELECT_LINES).enumerate() {
+                    sel.set_level(Level::from(channel & (1 << line) != 0));
+                }
 
-        Self { pwr, sig }
+                // let the multiplexer output settle
+                Timer::after_millis(1).await;
+
+                match config::Config::SOIL_MUX_INPUT {
+                    SoilInput::Adc2 => 1,
+                    _ => 0,
+                }
+            }
+        }
+    }
+
+    async fn seesaw_read(&mut self, adr: u8, register: [u8; 2], delay_us: u64, buf: &mut [u8]) -> Result<(), SoilSensorError> {
+        self.bus.write(adr, &register).await.map_err(|err| err.kind())?;
+
+        // seesaw needs time to prepare the response
+        Timer::after_micros(delay_us).await;
+
+        self.bus.read(adr, buf).await.map_err(|err| err.kind())?;
+
+        Ok(())
+    }
+
+    async fn seesaw_temperature(&mut self, adr: u8) -> Result<f32, SoilSensorError> {
+        let mut buf = [0u8; 4];
+        self.seesaw_read(adr, SEESAW_STATUS_TEMP, 1000, &mut buf).await?;
+
+        // 16.16 fixed point
+        Ok(i32::from_be_bytes(buf) as f32 / 65536.0)
+    }
+
+    /// Oversamples the probe and filters out the noise,
+    /// analog probes are sampled thru free-running adc and dma
+    async fn sample(&mut self, adc: &mut adc::Adc<'static, adc::Async>, probe: usize) -> Result<Estimate, SoilSensorError> {
+        let probe_config = config::Config::SOIL_PROBES.get(probe).ok_or(SoilSensorError::NoProbe)?;
+        let mut samples = [0u16; config::Config::SOIL_SAMPLES];
+
+        let samples = match probe_config.kind {
+            SoilProbeKind::Seesaw(adr) => {
+                let samples = &mut samples[..config::Config::SOIL_SEESAW_SAMPLES];
+                for sample in samples.iter_mut() {
+                    let mut buf = [0u8; 2];
+                    self.seesaw_read(adr, SEESAW_TOUCH_CHANNEL_OFFSET, 5000, &mut buf).await?;
+                    *sample = u16::from_be_bytes(buf);
+                }
+                samples
+            }
+            SoilProbeKind::Resistive | SoilProbeKind::Capacitive => {
+                let sig = self.select(probe_config.input).await;
+                adc.read_many(
+                    &mut self.sig[sig],
+                    &mut samples,
+                    config::Config::SOIL_SAMPLE_DIV,
+                    self.dma.reborrow(),
+                )
+                .await
+                .map_err(SoilSensorError::Adc)?;
+                &mut samples[..]
+            }
+        };
+
+        let estimate = filter::estimate(samples, config::Config::SOIL_TRIM_PERCENT).ok_or(SoilSensorError::NoSamples)?;
+
+        defmt::debug!("Soil probe {=usize} samples {=[u16]}", probe, samples);
+        defmt::debug!("Soil probe {=usize} estimate {:?}", probe, estimate);
+
+        Ok(estimate)
     }
 }
 
-impl Sensor<4> for SoilSensor {
+impl<I: I2c> Sensor<PAYLOAD_SIZE> for SoilSensor<I> {
     type Error = SoilSensorError;
 
     async fn on(&mut self) -> Result<(), Self::Error> {
-        if self.pwr.is_set_low() {
-            self.pwr.set_high();
+        let mut warm_up = 0;
+
+        for probe in config::Config::SOIL_PROBES.iter().take(MAX_PROBES) {
+            let probe_warm_up = match probe.kind {
+                SoilProbeKind::Resistive => config::Config::SOIL_WARM_UP_MS,
+                SoilProbeKind::Capacitive => config::Config::SOIL_CAPACITIVE_WARM_UP_MS,
+                // seesaw is powered from the I2C bus supply
+                SoilProbeKind::Seesaw(_) => continue,
+            };
+
+            let pwr = &mut self.pwr[Self::power_index(probe.power)];
+            if pwr.is_set_low() {
+                pwr.set_high();
+                warm_up = warm_up.max(probe_warm_up);
+            }
         }
 
+        // let the probes settle before sampling
+        Timer::after_millis(warm_up).await;
+
         Ok(())
     }
 
     async fn off(&mut self) -> Result<(), Self::Error> {
-        if self.pwr.is_set_high() {
-            self.pwr.set_low();
+        for pwr in self.pwr.iter_mut() {
+            if pwr.is_set_high() {
+                pwr.set_low();
+            }
         }
 
         Ok(())
     }
 
     async fn verify(&mut self) -> Result<(), Self::Error> {
-        // todo: is there a way to verify?
+        defmt::info!("Soil sensor configured with {=usize} probes", self.calibration.len());
+
+        // analog probes can't be verified, seesaw probes should at least answer
+        for probe in config::Config::SOIL_PROBES.iter().take(MAX_PROBES) {
+            if let SoilProbeKind::Seesaw(adr) = probe.kind {
+                self.seesaw_temperature(adr).await?;
+            }
+        }
+
         Ok(())
     }
 
-    async fn probe(&mut self, adc: &mut adc::Adc<'static, adc::Async>) -> Result<[u8; 4], Self::Error> {
-        match adc.read(&mut self.sig).await {
-            Ok(adc_raw) => {
-                defmt::info!("Soil sensor data - moist {=u16}", adc_raw);
+    fn probes(&self) -> usize {
+        self.calibration.len()
+    }
+
+    async fn calibrate(
+        &mut self,
+        adc: &mut adc::Adc<'static, adc::Async>,
+        probe: usize,
+        point: CalibrationPoint,
+    ) -> Result<(), Self::Error> {
+        let estimate = self.sample(adc, probe).await?;
+
+        defmt::info!(
+            "Soil probe {=usize} calibration point {:?} - moist {=u16} spread {=u16}",
+            probe,
+            point,
+            estimate.value,
+            estimate.spread
+        );
 
-                let mut buf = [0u8; 4];
+        self.calibration
+            .get_mut(probe)
+            .ok_or(SoilSensorError::NoProbe)?
+            .record(point, estimate.value)
+    }
 
-                buf[0] = 0x02;
-                buf[1] = 0x65;
-                buf[2] = (adc_raw >> 8) as u8;
-                buf[3] = adc_raw as u8;
+    fn restore_calibration(&mut self, probe: usize, data: &[u8]) {
+        if let (Some(slot), Some(calibration)) = (self.calibration.get_mut(probe), SoilCalibration::deserialize(data)) {
+            *slot = calibration;
+        }
+    }
 
-                Ok(buf)
+    fn calibration(&self, probe: usize, buf: &mut [u8]) -> usize {
+        self.calibration.get(probe).map_or(0, |calibration| calibration.serialize(buf))
+    }
+
+    async fn probe(&mut self, adc: &mut adc::Adc<'static, adc::Async>) -> Result<Vec<u8, PAYLOAD_SIZE>, Self::Error> {
+        let mut buf: Vec<u8, PAYLOAD_SIZE> = Vec::new();
+
+        for probe in 0..self.calibration.len() {
+            let kind = config::Config::SOIL_PROBES[probe].kind;
+            let channel = config::Config::SOIL_PROBES[probe].channel;
+            let estimate = self.sample(adc, probe).await?;
+            let adc_raw = estimate.value;
+            let spread_scl = (estimate.spread as u32 * 100).min(i16::MAX as u32) as u16;
+
+            if let Some(moisture) = self.calibration[probe].moisture(adc_raw) {
+                defmt::info!(
+                    "Soil probe {=usize} data - moist {=f32}% raw {=u16} spread {=u16}",
+                    probe,
+                    moisture,
+                    adc_raw,
+                    estimate.spread
+                );
+
+                let moisture_scl = (moisture * 2.0) as u8;
+
+                buf.push(channel).unwrap(); // channel    - n [soil_probe]
+                buf.push(0x68).unwrap(); // type       - humidity [1 byte]
+                buf.push(moisture_scl).unwrap(); //            - first byte
+            } else {
+                defmt::info!(
+                    "Soil probe {=usize} data - moist {=u16} spread {=u16} (not calibrated)",
+                    probe,
+                    adc_raw,
+                    estimate.spread
+                );
+            }
+
+            buf.push(channel + config::Config::SOIL_RAW_CHANNEL_OFFSET).unwrap(); // channel    - n + offset [soil_probe raw]
+            buf.push(0x02).unwrap(); // type       - analog input [2 bytes]
+            buf.push((adc_raw >> 8) as u8).unwrap(); //            - first byte
+            buf.push(adc_raw as u8).unwrap(); //            - second byte
+            buf.push(channel).unwrap(); // channel    - n [soil_probe]
+            buf.push(0x02).unwrap(); // type       - analog input [2 bytes]
+            buf.push((spread_scl >> 8) as u8).unwrap(); //            - first byte
+            buf.push(spread_scl as u8).unwrap(); //            - second byte
+
+            if let SoilProbeKind::Seesaw(adr) = kind {
+                let temp = self.seesaw_temperature(adr).await?;
+                let temp_scl = (temp * 10.0) as i16;
+
+                defmt::info!("Soil probe {=usize} data - tmp {=f32}°C", probe, temp);
+
+                buf.push(channel).unwrap(); // channel    - n [soil_probe]
+                buf.push(0x67).unwrap(); // type       - temperature [2 bytes]
+                buf.push((temp_scl >> 8) as u8).unwrap(); //            - first byte
+                buf.push(temp_scl as u8).unwrap(); //            - second byte
             }
-            Err(err) => Err(SoilSensorError::Adc(err)),
         }
+
+        Ok(buf)
     }
 }
@@ -1,12 +1,113 @@
 use embassy_rp::adc::{self};
+use embassy_rp::flash;
 use embassy_rp::gpio::{self, Input, Pull};
+use heapless::Vec;
 
+use crate::config::BatteryChemistry;
 use crate::sensor::Sensor;
-use crate::SystemRes;
+use crate::{config, SystemRes};
+
+/// Resting cell voltage to state of charge, from full to empty
+const LI_ION_DISCHARGE_CURVE: &[(f32, f32)] = &[
+    (4.20, 100.0),
+    (4.10, 90.0),
+    (4.00, 80.0),
+    (3.92, 70.0),
+    (3.87, 60.0),
+    (3.82, 50.0),
+    (3.79, 40.0),
+    (3.77, 30.0),
+    (3.73, 20.0),
+    (3.70, 15.0),
+    (3.68, 10.0),
+    (3.50, 5.0),
+    (3.00, 0.0),
+];
+
+/// LiFePO4 curve is flat in the middle, hence readings there are less precise
+const LI_FE_PO4_DISCHARGE_CURVE: &[(f32, f32)] = &[
+    (3.40, 100.0),
+    (3.35, 99.0),
+    (3.32, 90.0),
+    (3.30, 70.0),
+    (3.27, 40.0),
+    (3.26, 30.0),
+    (3.25, 20.0),
+    (3.22, 17.0),
+    (3.20, 14.0),
+    (3.00, 9.0),
+    (2.50, 0.0),
+];
+
+// battery must recover this much above the threshold to clear low battery state
+const LOW_BATTERY_HYSTERESIS: f32 = 5.0;
+
+// vsys, vbat are wired thru 1:3 voltage dividers
+const DIVIDER: f32 = 3.0;
+
+/// Linear correction of the adc, maps averaged raw value to millivolts on the adc pin.
+/// Same reference feeds all channels, hence single calibration applies to all of them.
+#[derive(Clone, Copy)]
+pub struct AdcCalibration {
+    gain: f32,   // millivolts per count
+    offset: f32, // millivolts
+}
+
+impl Default for AdcCalibration {
+    /// Nominal 3.3V reference without offset
+    fn default() -> Self {
+        Self {
+            gain: 3300.0 / 4096.0,
+            offset: 0.0,
+        }
+    }
+}
+
+impl AdcCalibration {
+    pub const SIZE: usize = 8;
+
+    /// Two reference points of raw value and millivolts measured on the adc pin
+    fn from_points([(raw_low, mv_low), (raw_high, mv_high)]: [(u16, u16); 2]) -> Option<Self> {
+        if raw_high == raw_low {
+            return None;
+        }
+
+        let gain = (mv_high as f32 - mv_low as f32) / (raw_high as f32 - raw_low as f32);
+        let offset = mv_low as f32 - gain * raw_low as f32;
+
+        Some(Self { gain, offset })
+    }
+
+    fn volts(&self, raw: f32) -> f32 {
+        (raw * self.gain + self.offset) / 1000.0
+    }
+
+    fn serialize(&self, buf: &mut [u8]) -> usize {
+        buf[0..4].copy_from_slice(&self.gain.to_be_bytes());
+        buf[4..8].copy_from_slice(&self.offset.to_be_bytes());
+
+        Self::SIZE
+    }
+
+    fn deserialize(data: &[u8]) -> Option<Self> {
+        if data.len() < Self::SIZE {
+            return None;
+        }
+
+        let gain = f32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let offset = f32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        if !gain.is_finite() || !offset.is_finite() || gain <= 0.0 {
+            return None;
+        }
+
+        Some(Self { gain, offset })
+    }
+}
 
 #[derive(defmt::Format)]
 pub enum SystemSensorError {
     Adc(adc::Error),
+    Flash(flash::Error),
 }
 
 impl From<adc::Error> for SystemSensorError {
@@ -16,10 +117,14 @@ impl From<adc::Error> for SystemSensorError {
 }
 
 pub struct SystemSensor {
-    temp_adc: adc::Channel<'static>, // rp2040 chip temperature
-    usb_pwr: gpio::Input<'static>,   // usb power connection
-    btr_adc: adc::Channel<'static>,  // battery power connection
-    vsys_adc: adc::Channel<'static>, // system voltage
+    temp_adc: adc::Channel<'static>,      // rp2040 chip temperature
+    usb_pwr: gpio::Input<'static>,        // usb power connection
+    btr_adc: adc::Channel<'static>,       // battery power connection
+    vsys_adc: adc::Channel<'static>,      // system voltage
+    unique_id: Result<u64, flash::Error>, // read by main before the storage takes the flash over
+    calibration: AdcCalibration,
+    low_battery: bool,
+    low_battery_alarm: bool, // raised on the transition into low battery
 }
 
 #[derive(defmt::Format)]
@@ -29,23 +134,44 @@ pub enum PowerSource {
 }
 
 impl SystemSensor {
-    pub fn new(r: SystemRes) -> Self {
+    pub fn new(r: SystemRes, unique_id: Result<u64, flash::Error>) -> Self {
         let temp_adc = adc::Channel::new_temp_sensor(r.adc_tmp);
         let btr_adc = adc::Channel::new_pin(r.btr, Pull::None);
         let vsys_adc = adc::Channel::new_pin(r.vsys, Pull::None);
         let usb_pwr = Input::new(r.usb, Pull::None);
+        let calibration = config::Config::SYSTEM_ADC_CALIBRATION
+            .and_then(AdcCalibration::from_points)
+            .unwrap_or_default();
 
         Self {
             temp_adc,
             usb_pwr,
             btr_adc,
             vsys_adc,
+            unique_id,
+            calibration,
+            low_battery: false,
+            low_battery_alarm: false,
         }
     }
 
+    /// Averages several conversions to suppress adc jitter, returns calibrated voltage on the adc pin
+    async fn sample(
+        adc: &mut adc::Adc<'static, adc::Async>,
+        channel: &mut adc::Channel<'static>,
+        calibration: &AdcCalibration,
+    ) -> Result<(u16, f32), adc::Error> {
+        let mut sum = 0u32;
+        for _ in 0..config::Config::SYSTEM_SAMPLES {
+            sum += adc.read(channel).await? as u32;
+        }
+        let adc_raw = sum as f32 / config::Config::SYSTEM_SAMPLES as f32;
+
+        Ok((adc_raw as u16, calibration.volts(adc_raw)))
+    }
+
     async fn get_temperature(&mut self, adc: &mut adc::Adc<'static, adc::Async>) -> Result<f32, adc::Error> {
-        let adc_raw = adc.read(&mut self.temp_adc).await?;
-        let adc_voltage = adc_raw as f32 * 3.3 / 4096.0;
+        let (adc_raw, adc_voltage) = Self::sample(adc, &mut self.temp_adc, &self.calibration).await?;
         let temp = 27.0 - (adc_voltage - 0.706) / 0.001721;
         let sign = if temp < 0.0 { -1.0 } else { 1.0 };
         let rounded_temp_x10: i16 = ((temp * 10.0) + 0.5 * sign) as i16;
@@ -57,21 +183,64 @@ impl SystemSensor {
         Ok(temp)
     }
 
+    /// Maps resting voltage to state of charge thru the discharge curve of configured chemistry
+    fn state_of_charge(voltage: f32) -> f32 {
+        let curve = match config::Config::BATTERY_CHEMISTRY {
+            BatteryChemistry::LiIon => LI_ION_DISCHARGE_CURVE,
+            BatteryChemistry::LiFePo4 => LI_FE_PO4_DISCHARGE_CURVE,
+        };
+
+        if voltage >= curve[0].0 {
+            return 100.0;
+        }
+
+        curve
+            .windows(2)
+            .find(|segment| voltage >= segment[1].0)
+            .map_or(0.0, |segment| {
+                let ((v_high, soc_high), (v_low, soc_low)) = (segment[0], segment[1]);
+                soc_low + (voltage - v_low) * (soc_high - soc_low) / (v_high - v_low)
+            })
+            .clamp(0.0, 100.0)
+    }
+
+    /// Battery is sampled at the start of a duty cycle while radio and sensors are idle,
+    /// remaining sag caused by the board itself is compensated thru the cell internal resistance
     async fn get_battery_capacity(&mut self, adc: &mut adc::Adc<'static, adc::Async>) -> Result<(f32, f32), adc::Error> {
-        let adc_raw = adc.read(&mut self.btr_adc).await?;
-        let adc_voltage = (adc_raw as f32) * 3.3 * 3.0 / 4096.0;
-        let percentage = ((adc_voltage - 3.0) / (4.2 - 3.0)) * 100.0;
+        let (adc_raw, adc_voltage) = Self::sample(adc, &mut self.btr_adc, &self.calibration).await?;
+        let adc_voltage = adc_voltage * DIVIDER;
+        let sag = config::Config::BATTERY_IDLE_LOAD_MA * config::Config::BATTERY_INTERNAL_RESISTANCE_MOHM / 1_000_000.0;
+        let percentage = Self::state_of_charge(adc_voltage + sag);
 
         defmt::debug!("battery adc_raw {=u16}", adc_raw);
         defmt::debug!("battery adc_voltage {=f32}", adc_voltage);
+        defmt::debug!("battery sag {=f32}", sag);
         defmt::debug!("battery percentage {=f32}", percentage);
 
+        // charger holds the cell up while on usb, its reading says nothing about the remaining capacity
+        let threshold = config::Config::BATTERY_LOW_THRESHOLD;
+        if matches!(self.get_power_source(), PowerSource::Usb) {
+            if self.low_battery {
+                defmt::info!("Battery low cleared, powered by usb");
+                self.low_battery = false;
+                self.low_battery_alarm = false;
+            }
+        } else if !self.low_battery && percentage < threshold {
+            defmt::warn!("Battery low, {=f32}% below threshold {=f32}%", percentage, threshold);
+            self.low_battery = true;
+            self.low_battery_alarm = true;
+        } else if self.low_battery && percentage > threshold + LOW_BATTERY_HYSTERESIS {
+            defmt::info!("Battery recovered, {=f32}%", percentage);
+            self.low_battery = false;
+            self.low_battery_alarm = false;
+        }
+
         Ok((adc_voltage, percentage))
     }
 
     async fn get_vsys_voltage(&mut self, adc: &mut adc::Adc<'static, adc::Async>) -> Result<f32, adc::Error> {
-        let adc_raw = adc.read(&mut self.vsys_adc).await?;
-        let adc_voltage = (adc_raw as f32) * 3.3 * 3.0 / 4096.0;
+        let (adc_raw, adc_voltage) = Self::sample(adc, &mut self.vsys_adc, &self.calibration).await?;
+        let adc_voltage = adc_voltage * DIVIDER;
 
         defmt::debug!("vsys adc_raw {=u16}", adc_raw);
         defmt::debug!("vsys adc_voltage {=f32}", adc_voltage);
@@ -86,9 +255,14 @@ impl SystemSensor {
             PowerSource::Battery
         }
     }
+
+    /// Whether the board is powered by usb rather than by its battery
+    pub fn external_power(&mut self) -> bool {
+        matches!(self.get_power_source(), PowerSource::Usb)
+    }
 }
 
-impl Sensor<18> for SystemSensor {
+impl Sensor<21> for SystemSensor {
     type Error = SystemSensorError;
 
     async fn on(&mut self) -> Result<(), Self::Error> {
@@ -102,20 +276,63 @@ impl Sensor<18> for SystemSensor {
     }
 
     async fn verify(&mut self) -> Result<(), Self::Error> {
-        // todo: to implement some basic verify check, maybe read pico serial number?
+        let unique_id = self.unique_id.map_err(SystemSensorError::Flash)?;
+
+        defmt::info!("Flash unique id {=u64:#x}", unique_id);
+
         Ok(())
     }
 
-    async fn probe(&mut self, adc: &mut adc::Adc<'static, adc::Async>) -> Result<[u8; 18], Self::Error> {
+    fn serial_number(&self) -> Option<u64> {
+        self.unique_id.ok()
+    }
+
+    fn alarm(&self) -> bool {
+        self.low_battery_alarm
+    }
+
+    fn clear_alarm(&mut self) {
+        self.low_battery_alarm = false;
+    }
+
+    fn restore_calibration(&mut self, _probe: usize, data: &[u8]) {
+        // calibration provisioned in the config takes precedence over the persisted one
+        if config::Config::SYSTEM_ADC_CALIBRATION.is_some() {
+            return;
+        }
+
+        if let Some(calibration) = AdcCalibration::deserialize(data) {
+            self.calibration = calibration;
+        }
+    }
+
+    fn calibration(&self, _probe: usize, buf: &mut [u8]) -> usize {
+        self.calibration.serialize(buf)
+    }
+
+    async fn probe(&mut self, adc: &mut adc::Adc<'static, adc::Async>) -> Result<Vec<u8, 21>, Self::Error> {
         let temp = self.get_temperature(adc).await?;
         let (btr_voltage, btr_capacity) = self.get_battery_capacity(adc).await?;
         let vsys_voltage = self.get_vsys_voltage(adc).await?;
-        let power_source = match self.get_power_source() {
+        let power_source = self.get_power_source();
+
+        // vbus is a known reference, large deviation means the adc reference drifted
+        let deviation = vsys_voltage - config::Config::VSYS_USB_VOLTAGE;
+        if matches!(power_source, PowerSource::Usb)
+            && (deviation > config::Config::VSYS_TOLERANCE || deviation < -config::Config::VSYS_TOLERANCE)
+        {
+            defmt::warn!(
+                "System voltage {=f32}V is off the usb reference, adc calibration is advised",
+                vsys_voltage
+            );
+        }
+
+        let power_source = match power_source {
             PowerSource::Battery => 0x00,
             PowerSource::Usb => 0x01,
         };
 
-        let temp_scl = (temp * 10.0) as u16;
+        let temp_scl = (temp * 10.0) as i16;
         let btr_voltage_scl = (btr_voltage * 100.0) as u16;
         let btr_capacity_scl = (btr_capacity * 2.0) as u8;
         let vsys_voltage_scl = (vsys_voltage * 100.0) as u16;
@@ -129,7 +346,7 @@ impl Sensor<18> for SystemSensor {
             power_source,
         );
 
-        let mut buf = [0u8; 18];
+        let mut buf = [0u8; 21];
         buf[0] = 0x03; // channel    - 3 [rp2040]
         buf[1] = 0x67; // type       - temperature [2 bytes]
         buf[2] = (temp_scl >> 8) as u8; //            - first byte
@@ -148,7 +365,10 @@ impl Sensor<18> for SystemSensor {
         buf[15] = 0x04; // channel    - 3 [rp2040]
         buf[16] = 0x00; // type       - diginal input [1 bytes]
         buf[17] = power_source; //            - first byte
+        buf[18] = 0x03; // channel    - 3 [rp2040]
+        buf[19] = 0x00; // type       - diginal input [1 bytes]
+        buf[20] = self.low_battery as u8; //            - first byte
 
-        Ok(buf)
+        Ok(Vec::from_slice(&buf).unwrap())
     }
 }
@@ -0,0 +1,93 @@
+use core::result::Result;
+
+use embassy_rp::adc;
+use embassy_time::Timer;
+use embedded_hal_async::i2c::{Error as _, I2c};
+use heapless::Vec;
+
+use crate::sensor::i2c_sensors::I2cSensorError;
+use crate::sensor::Sensor;
+
+pub const I2C_ADDR: u8 = 0x10;
+
+const ALS_CONF_REGISTER: u8 = 0x00;
+const ALS_REGISTER: u8 = 0x04;
+const ID_REGISTER: u8 = 0x07;
+const DEVICE_ID: u8 = 0x81;
+
+// gain x1, integration time 100ms
+const ALS_CONF_POWER_ON: u16 = 0x0000;
+const ALS_CONF_SHUTDOWN: u16 = 0x0001;
+// lux per count for gain x1 and integration time 100ms
+const RESOLUTION: f32 = 0.0576;
+
+/// Vishay VEML7700 ambient light sensor
+pub struct Veml7700<I: I2c> {
+    bus: I,
+}
+
+impl<I: I2c> Veml7700<I> {
+    pub fn new(bus: I) -> Self {
+        Self { bus }
+    }
+
+    async fn write_register(&mut self, register: u8, value: u16) -> Result<(), I2cSensorError> {
+        let [lsb, msb] = value.to_le_bytes();
+        self.bus
+            .write(I2C_ADDR, &[register, lsb, msb])
+            .await
+            .map_err(|err| err.kind().into())
+    }
+
+    async fn read_register(bus: &mut I, register: u8) -> Result<u16, I2cSensorError> {
+        let mut buffer = [0u8; 2];
+        bus.write_read(I2C_ADDR, &[register], &mut buffer).await.map_err(|err| err.kind())?;
+
+        Ok(u16::from_le_bytes(buffer))
+    }
+
+    /// Identifies the chip by its device id register
+    pub async fn identify(bus: &mut I) -> bool {
+        matches!(Self::read_register(bus, ID_REGISTER).await, Ok(id) if id as u8 == DEVICE_ID)
+    }
+}
+
+impl<I: I2c> Sensor<4> for Veml7700<I> {
+    type Error = I2cSensorError;
+
+    async fn on(&mut self) -> Result<(), Self::Error> {
+        self.write_register(ALS_CONF_REGISTER, ALS_CONF_POWER_ON).await?;
+
+        // wait 3ms according to spec
+        Timer::after_millis(3).await;
+
+        Ok(())
+    }
+
+    async fn off(&mut self) -> Result<(), Self::Error> {
+        self.write_register(ALS_CONF_REGISTER, ALS_CONF_SHUTDOWN).await
+    }
+
+    async fn verify(&mut self) -> Result<(), Self::Error> {
+        Self::read_register(&mut self.bus, ID_REGISTER).await.map(|_| ())
+    }
+
+    async fn probe(&mut self, _adc: &mut adc::Adc<'static, adc::Async>) -> Result<Vec<u8, 4>, Self::Error> {
+        // wait for at least one integration cycle after power on
+        Timer::after_millis(120).await;
+
+        let adc_raw = Self::read_register(&mut self.bus, ALS_REGISTER).await?;
+        let lux = adc_raw as f32 * RESOLUTION;
+        let lux_scl = lux as u16;
+
+        defmt::info!("VEML7700 sensor data - lux {=f32}", lux);
+
+        let mut buf = [0u8; 4];
+        buf[0] = 0x09; // channel    - 9 [veml7700]
+        buf[1] = 0x65; // type       - illuminance [2 bytes]
+        buf[2] = (lux_scl >> 8) as u8; //            - first byte
+        buf[3] = lux_scl as u8; //            - second byte
+
+        Ok(Vec::from_slice(&buf).unwrap())
+    }
+}
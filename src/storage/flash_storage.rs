@@ -1,13 +1,22 @@
+use core::cell::RefCell;
+
 use ekv::flash::{self, PageID};
+use embassy_embedded_hal::flash::partition::{self, BlockingPartition};
 use embassy_rp::flash::{Blocking, Flash};
 use embassy_rp::peripherals::FLASH;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
 use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
 
+use crate::secret::Redacted;
 use crate::storage::{Key, Storage};
-use crate::FlashRes;
 
-const FLASH_SIZE: usize = 2 * 1024 * 1024;
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// Flash shared by the storage and the firmware slots, both access it thru partitions
+pub type SharedFlash = Mutex<NoopRawMutex, RefCell<Flash<'static, FLASH, Blocking, FLASH_SIZE>>>;
+
+type FlashError = partition::Error<embassy_rp::flash::Error>;
 
 extern "C" {
     static __config_start: u32;
@@ -22,21 +31,21 @@ pub struct DbFlash<T: NorFlash + ReadNorFlash> {
 
 #[derive(defmt::Format)]
 pub enum FlashStorageError {
-    Mount(ekv::MountError<embassy_rp::flash::Error>),
-    Format(ekv::FormatError<embassy_rp::flash::Error>),
-    Write(ekv::WriteError<embassy_rp::flash::Error>),
-    Commit(ekv::CommitError<embassy_rp::flash::Error>),
+    Mount(ekv::MountError<FlashError>),
+    Format(ekv::FormatError<FlashError>),
+    Write(ekv::WriteError<FlashError>),
+    Commit(ekv::CommitError<FlashError>),
 }
 
 pub struct FlashStorage {
-    flash: ekv::Database<DbFlash<Flash<'static, FLASH, Blocking, FLASH_SIZE>>, NoopRawMutex>,
+    flash: ekv::Database<DbFlash<BlockingPartition<'static, NoopRawMutex, Flash<'static, FLASH, Blocking, FLASH_SIZE>>>, NoopRawMutex>,
 }
 
 impl FlashStorage {
-    pub fn new(r: FlashRes) -> Self {
+    pub fn new(flash: &'static SharedFlash) -> Self {
         let flash = {
-            let db_flash: DbFlash<Flash<_, _, FLASH_SIZE>> = DbFlash {
-                flash: Flash::new_blocking(r.flash),
+            let db_flash = DbFlash {
+                flash: BlockingPartition::new(flash, 0, FLASH_SIZE as u32),
                 start: unsafe { &__config_start as *const u32 as usize },
             };
 
@@ -51,7 +60,11 @@ impl Storage for FlashStorage {
     type Error = FlashStorageError;
 
     async fn put(&mut self, key: &Key, value: &[u8]) -> Result<(), Self::Error> {
-        defmt::debug!("Writing key {:?} value {=[u8]:#x} to flash", key, value);
+        if key.is_secret() {
+            defmt::debug!("Writing key {:?} value {:?} to flash", key, Redacted(value));
+        } else {
+            defmt::debug!("Writing key {:?} value {=[u8]:#x} to flash", key, value);
+        }
 
         let mut wtx = self.flash.write_transaction().await;
         let key: [u8; 1] = key.into();
@@ -69,11 +82,17 @@ impl Storage for FlashStorage {
 
     async fn get(&mut self, key: &Key, buf: &mut [u8]) -> Option<usize> {
         let rtx = self.flash.read_transaction().await;
-        let key: [u8; 1] = key.into();
+        let id: [u8; 1] = key.into();
 
-        rtx.read(&key, buf)
+        rtx.read(&id, buf)
             .await
-            .inspect(|_| defmt::debug!("Read key {:?} value {=[u8]:#x}", key, buf))
+            .inspect(|size| {
+                if key.is_secret() {
+                    defmt::debug!("Read key {:?} value {:?}", key, Redacted(&buf[..*size]));
+                } else {
+                    defmt::debug!("Read key {:?} value {=[u8]:#x}", key, &buf[..*size]);
+                }
+            })
             .ok()
     }
 
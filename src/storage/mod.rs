@@ -6,6 +6,9 @@ pub enum Key {
     AppSKey,
     NewSKey,
     DevAddr,
+    AdcCalibration,
+    Sequence,            // last reserved point-to-point frame sequence
+    SoilCalibration(u8), // probe index
 }
 
 impl From<&Key> for [u8; 1] {
@@ -14,10 +17,20 @@ impl From<&Key> for [u8; 1] {
             Key::AppSKey => [0x00],
             Key::NewSKey => [0x01],
             Key::DevAddr => [0x02],
+            Key::AdcCalibration => [0x03],
+            Key::Sequence => [0x04],
+            Key::SoilCalibration(probe) => [0x10 + probe],
         }
     }
 }
 
+impl Key {
+    /// Whether the value stored under the key is key material, which must be redacted in logs
+    pub fn is_secret(&self) -> bool {
+        matches!(self, Key::AppSKey | Key::NewSKey)
+    }
+}
+
 /// Trait to represent all needed operatios with the key-value storage
 pub trait Storage {
     /// Error type representation, left up to the implementor